@@ -1,13 +1,32 @@
-use crate::audio::AudioStream;
-use crate::parameter::Parameter;
-use ratatui::widgets::TableState;
+use crate::audio::{output_devices, AudioStream, Routing};
+use crate::command::{self, Argument, Command, Macros, COMMANDS};
+use crate::complete;
+use crate::config::{Config, KeyBinding, Theme, DEFAULT_HISTORY_SIZE};
+use crate::midi::{self, Midi};
+use crate::parameter::{parse_value, parse_waveform, parse_waveform_name, Parameter, WAVEFORMS};
+use crate::preset::{ChannelPreset, OutputPreset, Preset};
+use crate::preview::Preview;
+use crate::remote::{ParameterValue, Remote};
+use crate::scope::Scope;
+use crate::script::Script;
+use crate::spectrum::Spectrum;
+use crate::xy::Lissajous;
+use color_eyre::eyre::{bail, Result};
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::{
+    layout::Rect,
+    widgets::{ListState, TableState},
+};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use waveforms_rs::Waveform;
+use std::time::Instant;
+use waveforms_rs::{Waveform, WaveformType};
 
 /// Application state
-pub const WAVEFORMS_COUNT: usize = 2;
-const TAB_COUNT: usize = 3;
-pub const TAB_TITLES: [&str; 3] = ["Channel A", "Channel B", "Output"];
+pub const DEFAULT_CHANNELS: usize = 2;
+pub const MAX_CHANNELS: usize = 8;
+/// Sample rate of the preview until a stream is created.
+const PREVIEW_SAMPLE_RATE: f32 = 44100.0;
 
 #[derive(Default)]
 pub struct App {
@@ -20,12 +39,59 @@ pub struct App {
     pub command: String,
     pub command_history: Vec<String>,
     command_history_index: usize,
+    // maximum number of commands kept in the history
+    pub history_limit: usize,
+    // file the history is saved to
+    pub history_path: Option<PathBuf>,
+    // query of the reverse history search, when searching
+    pub history_search: Option<String>,
+    pub history_search_state: ListState,
+    // candidates of the last ambiguous completion
+    pub completions: Vec<String>,
+    // string buffer for keypad mode
+    pub keypad: String,
     // warning message to display
     pub warning: Option<String>,
+    // information message to display
+    pub message: Option<String>,
     // waveform preview generators for each channel
     pub waveform_previews: Vec<Waveform>,
     pub selected_waveform: usize,
+    // time window shown in the preview
+    pub preview: Preview,
+    // output channels that clipped, as last reported by the audio thread
+    pub clipped: Vec<bool>,
     pub audio: AudioStream,
+    // screen areas of the last rendered frame, used for mouse interaction
+    pub areas: LayoutAreas,
+    // last mouse position while dragging in the preview
+    drag_position: Option<(u16, u16)>,
+    pub theme: Theme,
+    pub key_bindings: Vec<KeyBinding>,
+    // preset picker
+    pub preset_names: Vec<String>,
+    pub preset_list_state: ListState,
+    // script being run
+    pub script: Option<Script>,
+    // user-defined aliases and macros
+    pub macros: Macros,
+    /// Requests of the remote control interfaces.
+    pub remote: Remote,
+    /// Notes and controllers of the MIDI input.
+    pub midi: Midi,
+    /// What is shown next to the parameters.
+    pub view: View,
+    pub scope: Scope,
+    pub spectrum: Spectrum,
+    pub xy: Lissajous,
+}
+
+/// Screen areas of the interactive widgets.
+#[derive(Default, Debug, Clone)]
+pub struct LayoutAreas {
+    pub tabs: Vec<Rect>,
+    pub parameters: Rect,
+    pub preview: Rect,
 }
 
 #[derive(Default, Debug, PartialEq)]
@@ -35,18 +101,39 @@ pub enum Mode {
     Command,
     Keypad,
     Help,
+    Presets,
+}
+
+/// What is shown next to the parameters.
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum View {
+    /// Waveforms computed from the channel parameters.
+    #[default]
+    Preview,
+    /// Oscilloscope of the samples sent to the device.
+    Scope,
+    /// Spectrum of the samples sent to the device.
+    Spectrum,
+    /// Lissajous figure of two channels.
+    Xy,
 }
 
 impl App {
     pub fn new() -> Self {
+        Self::with_channels(DEFAULT_CHANNELS)
+    }
+
+    pub fn with_channels(channels: usize) -> Self {
+        let channels = channels.clamp(1, MAX_CHANNELS);
+
         // generate waveform previews
         let mut waveform_previews: Vec<Waveform> = Vec::new();
-        for i in 0..WAVEFORMS_COUNT {
-            waveform_previews.push(Waveform::new(44100.0, 440.0 * (i as f32 + 1.0)));
+        for i in 0..channels {
+            waveform_previews.push(Waveform::new(PREVIEW_SAMPLE_RATE, 440.0 * (i as f32 + 1.0)));
         }
 
         let mut table_state = TableState::default();
-        table_state.select(Some(0));
+        table_state.select(Some(Parameter::Frequency.index()));
 
         Self {
             should_quit: false,
@@ -56,112 +143,622 @@ impl App {
             command: String::new(),
             waveform_previews,
             selected_waveform: 0,
+            preview: Preview::default(),
+            clipped: Vec::new(),
             command_history: vec!["".to_string()],
             command_history_index: 0,
+            history_limit: DEFAULT_HISTORY_SIZE,
+            history_path: None,
+            history_search: None,
+            history_search_state: ListState::default(),
+            completions: Vec::new(),
+            keypad: String::new(),
             warning: None,
+            message: None,
             table_state,
-            audio: AudioStream::default(),
+            audio: AudioStream::new(channels),
+            areas: LayoutAreas::default(),
+            drag_position: None,
+            theme: Theme::default(),
+            key_bindings: Vec::new(),
+            preset_names: Vec::new(),
+            preset_list_state: ListState::default(),
+            script: None,
+            macros: Macros::default(),
+            remote: Remote::default(),
+            midi: Midi::default(),
+            view: View::default(),
+            scope: Scope::default(),
+            spectrum: Spectrum::default(),
+            xy: Lissajous::default(),
+        }
+    }
+
+    /// Apply the preferences and channel defaults of the configuration, then run its startup
+    /// commands. The audio stream should already be created so the defaults reach it.
+    pub fn apply_config(&mut self, config: &Config) -> Result<()> {
+        self.theme = config.theme()?;
+        self.key_bindings = config.key_bindings()?;
+        self.history_limit = config.history_size;
+        self.macros = config.macros()?;
+        self.midi = config.midi()?;
+        self.preview.periods = config.preview_periods;
+
+        for (channel, defaults) in config.channel_defaults.iter().enumerate() {
+            if channel >= self.channels() {
+                break;
+            }
+            let values = [
+                (
+                    Parameter::Waveform,
+                    defaults.waveform.as_deref().and_then(parse_waveform_name),
+                ),
+                (
+                    Parameter::Frequency,
+                    defaults.frequency.as_ref().and_then(|x| x.to_f32()),
+                ),
+                (
+                    Parameter::Amplitude,
+                    defaults.amplitude.as_ref().and_then(|x| x.to_f32()),
+                ),
+                (
+                    Parameter::PhaseOffset,
+                    defaults.phase_offset.as_ref().and_then(|x| x.to_f32()),
+                ),
+                (
+                    Parameter::DcOffset,
+                    defaults.dc_offset.as_ref().and_then(|x| x.to_f32()),
+                ),
+                (Parameter::Output, defaults.output.map(|x| x as u8 as f32)),
+            ];
+            for (parameter, value) in values {
+                if let Some(value) = value {
+                    self.set_channel_parameter_value(channel, parameter, value);
+                }
+            }
+        }
+
+        for command in config.startup.iter() {
+            self.execute_command(command);
+        }
+        Ok(())
+    }
+
+    /// Command bound to a key, if any.
+    pub fn key_binding(&self, code: KeyCode, mut modifiers: KeyModifiers) -> Option<String> {
+        // shift is already part of the character
+        if let KeyCode::Char(_) = code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        self.key_bindings
+            .iter()
+            .find(|x| x.code == code && x.modifiers == modifiers)
+            .map(|x| x.command.clone())
+    }
+
+    /// Handles the tick event of the terminal, running the script and the remote requests.
+    pub fn tick(&mut self) {
+        for job in self.remote.pending() {
+            job(self);
+        }
+        self.step_script();
+        self.check_clips();
+        if self.remote.has_subscribers() {
+            let values = self.parameter_values();
+            self.remote.notify(values);
         }
     }
 
-    pub fn tick(&self) {}
+    /// Warn when an output clips, once until the clip indicators are reset.
+    fn check_clips(&mut self) {
+        let clips = self.audio.status.lock().unwrap().clips.clone();
+        let outputs: Vec<String> = clips
+            .iter()
+            .enumerate()
+            .filter(|(i, clip)| **clip && !self.clipped.get(*i).copied().unwrap_or(false))
+            .map(|(i, _)| (i + 1).to_string())
+            .collect();
+        if !outputs.is_empty() {
+            self.set_warning(&format!(
+                "Output {} clipped, press c to reset",
+                outputs.join(", ")
+            ));
+        }
+        self.clipped = clips;
+    }
+
+    pub fn reset_clips(&mut self) {
+        self.audio.status.lock().unwrap().clips.fill(false);
+        self.clipped.clear();
+        self.set_message("Clip indicators reset");
+    }
 
     pub fn quit(&mut self) {
         self.should_quit = true;
     }
 
+    /// Number of waveform channels.
+    pub fn channels(&self) -> usize {
+        self.waveform_previews.len()
+    }
+
+    /// Number of tabs, one for each channel and the output.
+    pub fn tab_count(&self) -> usize {
+        self.channels() + 1
+    }
+
+    pub fn tab_titles(&self) -> Vec<String> {
+        let mut titles: Vec<String> = (0..self.channels()).map(channel_name).collect();
+        titles.push("Output".to_string());
+        titles
+    }
+
     pub fn next_tab(&mut self) {
-        self.set_tab((self.tab_index + 1) % TAB_COUNT);
+        self.set_tab((self.tab_index + 1) % self.tab_count());
     }
 
     pub fn previous_tab(&mut self) {
         if self.tab_index > 0 {
             self.set_tab(self.tab_index - 1);
         } else {
-            self.set_tab(TAB_COUNT - 1);
+            self.set_tab(self.tab_count() - 1);
         }
     }
 
     pub fn set_tab(&mut self, index: usize) {
-        if index < TAB_COUNT {
+        if index < self.tab_count() {
             self.tab_index = index;
         }
         if index < self.waveform_previews.len() {
             self.selected_waveform = index;
         }
+        // keep the selected parameter in the list shown by the tab
+        if self.is_output_tab() != self.selected_parameter.is_output() {
+            let parameter = self.parameters()[0];
+            self.select_parameter(parameter);
+        }
+    }
+
+    pub fn is_output_tab(&self) -> bool {
+        self.tab_index >= self.waveform_previews.len()
+    }
+
+    /// Parameters listed in the current tab.
+    pub fn parameters(&self) -> &'static [Parameter] {
+        if self.is_output_tab() {
+            &Parameter::OUTPUT
+        } else {
+            &Parameter::CHANNEL
+        }
     }
 
     pub fn next_parameter(&mut self) {
-        self.selected_parameter = self.selected_parameter.next();
-        let value = self.selected_parameter as usize;
-        self.table_state.select(Some(value));
+        self.select_parameter(self.selected_parameter.next());
     }
 
     pub fn previous_parameter(&mut self) {
-        self.selected_parameter = self.selected_parameter.previous();
-        let value = self.selected_parameter as usize;
-        self.table_state.select(Some(value));
+        self.select_parameter(self.selected_parameter.previous());
     }
 
-    pub fn increase_parameter_value(&mut self, parameter: Parameter) {
+    pub fn select_parameter(&mut self, parameter: Parameter) {
+        self.selected_parameter = parameter;
+        self.table_state.select(Some(parameter.index()));
+    }
+
+    /// Current value of a parameter of the selected channel or the output.
+    pub fn parameter_value(&self, parameter: Parameter) -> Option<f32> {
+        self.channel_parameter_value(self.selected_waveform, parameter)
+    }
+
+    /// Current value of a parameter of a channel or the output.
+    pub fn channel_parameter_value(&self, channel: usize, parameter: Parameter) -> Option<f32> {
+        let waveform = self.waveform_previews.get(channel)?;
+        let settings = self.audio.settings.lock().unwrap();
         match parameter {
-            Parameter::Frequency => {
-                let mut frequency = self.waveform_previews[self.selected_waveform].frequency();
-                frequency += 1.0;
-                self.waveform_previews[self.selected_waveform].set_frequency(frequency);
-            }
-            Parameter::Amplitude => {
-                let mut amplitude = self.waveform_previews[self.selected_waveform].amplitude();
-                amplitude += 1.0;
-                self.waveform_previews[self.selected_waveform].set_amplitude(amplitude);
-            }
-            Parameter::Waveform => {
-                let waveform =
-                    *self.waveform_previews[self.selected_waveform].waveform_type() as usize;
-                if let Ok(wave) = (waveform + 1).try_into() {
-                    self.waveform_previews[self.selected_waveform].set_waveform_type(wave);
+            Parameter::Waveform => Some(*waveform.waveform_type() as usize as f32 + 1.0),
+            Parameter::Frequency => Some(waveform.frequency()),
+            Parameter::Amplitude => Some(waveform.amplitude()),
+            Parameter::PhaseOffset => Some(waveform.phase_offset()),
+            Parameter::DcOffset => Some(waveform.dc_offset()),
+            Parameter::Pan => settings.pans.get(channel).copied(),
+            Parameter::Output => Some(settings.channel_gain(channel)),
+            Parameter::Routing => Some(usize::from(settings.routing) as f32),
+            Parameter::Volume => Some(settings.volume),
+            Parameter::Mute => Some(settings.mute as u8 as f32),
+        }
+    }
+
+    pub fn increase_parameter_value(&mut self, parameter: Parameter) {
+        self.step_parameter_value(parameter, 1.0);
+    }
+
+    pub fn decrease_parameter_value(&mut self, parameter: Parameter) {
+        self.step_parameter_value(parameter, -1.0);
+    }
+
+    fn step_parameter_value(&mut self, parameter: Parameter, steps: f32) {
+        match self.parameter_value(parameter) {
+            Some(value) => {
+                let value = value + steps * parameter.step();
+                if parameter == Parameter::Frequency && value <= 0.0 {
+                    return;
                 }
+                self.set_parameter_value(parameter, value);
             }
-            _ => {
+            None => {
                 self.set_warning("Parameter not implemented");
             }
         }
     }
 
+    /// Show the next view, in the order of `command::VIEWS`.
+    pub fn next_view(&mut self) {
+        let views: Vec<View> = command::VIEWS
+            .iter()
+            .filter_map(|x| command::parse_view(x))
+            .collect();
+        let index = views.iter().position(|x| *x == self.view).unwrap_or(0);
+        self.view = views[(index + 1) % views.len()];
+    }
+
+    pub fn toggle_mute(&mut self) {
+        let mut settings = self.audio.settings.lock().unwrap();
+        settings.mute = !settings.mute;
+    }
+
+    /// Toggle the output of the selected channel, or of all channels in the output tab.
+    pub fn toggle_output(&mut self) {
+        let mut settings = self.audio.settings.lock().unwrap();
+        if self.is_output_tab() {
+            let enabled = !settings.enabled.iter().any(|x| *x);
+            settings.enabled.iter_mut().for_each(|x| *x = enabled);
+        } else if let Some(enabled) = settings.enabled.get_mut(self.selected_waveform) {
+            *enabled = !*enabled;
+        }
+    }
+
+    /// Start dragging in the preview at the given screen position.
+    pub fn start_drag(&mut self, column: u16, row: u16) {
+        self.drag_position = Some((column, row));
+    }
+
+    pub fn stop_drag(&mut self) {
+        self.drag_position = None;
+    }
+
+    /// Drag in the preview, vertical movement adjusts amplitude or DC offset,
+    /// horizontal movement zooms the time axis.
+    pub fn drag(&mut self, column: u16, row: u16, offset: bool) {
+        let Some((last_column, last_row)) = self.drag_position else {
+            return;
+        };
+        self.drag_position = Some((column, row));
+
+        let width = self.areas.preview.width.saturating_sub(2).max(1) as f32;
+        let height = self.areas.preview.height.saturating_sub(2).max(1) as f32;
+        let dx = column as f32 - last_column as f32;
+        let dy = last_row as f32 - row as f32;
+
+        if dy != 0.0 {
+            // one row of the canvas spans 2.2 / height units
+            let parameter = if offset {
+                Parameter::DcOffset
+            } else {
+                Parameter::Amplitude
+            };
+            if let Some(value) = self.parameter_value(parameter) {
+                self.set_parameter_value(parameter, value + dy * 2.2 / height);
+            }
+        }
+        if dx != 0.0 {
+            // dragging right zooms in
+            self.preview.zoom_by(1.0 / (1.0 - dx / width).max(0.01));
+        }
+    }
+
+    /// Pan the preview by a tenth of its window, positive to go later in time.
+    pub fn pan_preview(&mut self, direction: f32) {
+        let frequency = self
+            .parameter_value(Parameter::Frequency)
+            .unwrap_or_default();
+        self.preview.pan_by(direction / 10.0, frequency);
+    }
+
+    /// Sample rate of the stream, which the preview generators run at.
+    pub fn preview_sample_rate(&self) -> f32 {
+        match self.audio.info.sample_rate {
+            0 => PREVIEW_SAMPLE_RATE,
+            sample_rate => sample_rate as f32,
+        }
+    }
+
+    /// Run the preview generators at the sample rate of the stream, keeping their parameters.
+    pub fn update_preview_sample_rate(&mut self) {
+        let sample_rate = self.preview_sample_rate();
+        for waveform in self.waveform_previews.iter_mut() {
+            let mut preview = Waveform::new(sample_rate, waveform.frequency());
+            preview.set_waveform_type(*waveform.waveform_type());
+            preview.set_amplitude(waveform.amplitude());
+            preview.set_phase_offset(waveform.phase_offset());
+            preview.set_dc_offset(waveform.dc_offset());
+            *waveform = preview;
+        }
+    }
+
     pub fn set_parameter_value(&mut self, parameter: Parameter, value: f32) {
+        self.set_channel_parameter_value(self.selected_waveform, parameter, value);
+    }
+
+    pub fn set_channel_parameter_value(
+        &mut self,
+        channel: usize,
+        parameter: Parameter,
+        value: f32,
+    ) {
+        if channel >= self.channels() {
+            self.set_warning("Invalid channel");
+            return;
+        }
+        let value = match parameter.range() {
+            Some((min, max)) => value.clamp(min, max),
+            None => value,
+        };
         match parameter {
             Parameter::Frequency => {
-                self.waveform_previews[self.selected_waveform].set_frequency(value);
-                // update audio thread waveforms
+                self.waveform_previews[channel].set_frequency(value);
+                // update audio thread waveforms, they only exist once the stream is created
                 let audio_waveforms = Arc::clone(&self.audio.waveforms);
                 let mut waveforms = audio_waveforms.lock().unwrap();
-                waveforms[self.selected_waveform].set_frequency(value);
+                if let Some(waveform) = waveforms.get_mut(channel) {
+                    waveform.set_frequency(value);
+                }
             }
             Parameter::Amplitude => {
-                self.waveform_previews[self.selected_waveform].set_amplitude(value);
+                self.waveform_previews[channel].set_amplitude(value);
                 // update audio thread waveforms
                 let audio_waveforms = Arc::clone(&self.audio.waveforms);
                 let mut waveforms = audio_waveforms.lock().unwrap();
-                waveforms[self.selected_waveform].set_amplitude(value);
+                if let Some(waveform) = waveforms.get_mut(channel) {
+                    waveform.set_amplitude(value);
+                }
             }
-            Parameter::Waveform => {
-                if let Ok(waveform) = (value as usize - 1).try_into() {
-                    self.waveform_previews[self.selected_waveform].set_waveform_type(waveform);
+            // waveforms are numbered from 1, zero and negative values are invalid
+            Parameter::Waveform => match (value as usize).checked_sub(1).map(|x| x.try_into()) {
+                Some(Ok(waveform)) => {
+                    self.waveform_previews[channel].set_waveform_type(waveform);
                     // update audio thread waveforms
                     let audio_waveforms = Arc::clone(&self.audio.waveforms);
                     let mut waveforms = audio_waveforms.lock().unwrap();
-                    waveforms[self.selected_waveform].set_waveform_type(waveform);
+                    if let Some(audio_waveform) = waveforms.get_mut(channel) {
+                        audio_waveform.set_waveform_type(waveform);
+                    }
+                }
+                _ => self.set_warning("Invalid waveform"),
+            },
+            Parameter::PhaseOffset => {
+                self.waveform_previews[channel].set_phase_offset(value);
+                // update audio thread waveforms
+                let audio_waveforms = Arc::clone(&self.audio.waveforms);
+                let mut waveforms = audio_waveforms.lock().unwrap();
+                if let Some(waveform) = waveforms.get_mut(channel) {
+                    waveform.set_phase_offset(value);
                 }
             }
-            _ => {
-                self.set_warning("Parameter not implemented");
+            Parameter::DcOffset => {
+                self.waveform_previews[channel].set_dc_offset(value);
+                // update audio thread waveforms
+                let audio_waveforms = Arc::clone(&self.audio.waveforms);
+                let mut waveforms = audio_waveforms.lock().unwrap();
+                if let Some(waveform) = waveforms.get_mut(channel) {
+                    waveform.set_dc_offset(value);
+                }
+            }
+            Parameter::Routing => match Routing::try_from(value.max(0.0) as usize) {
+                Ok(Routing::Channel(source)) if source >= self.channels() => {
+                    self.set_warning("Invalid routing");
+                }
+                Ok(Routing::Split) if self.unrouted_channels() => {
+                    self.set_warning(&format!(
+                        "Only {} output channels available to split",
+                        self.audio.info.channels
+                    ));
+                }
+                Ok(routing) => self.audio.settings.lock().unwrap().routing = routing,
+                Err(_) => self.set_warning("Invalid routing"),
+            },
+            Parameter::Volume => {
+                self.audio.settings.lock().unwrap().volume = value.clamp(0.0, 1.0);
+            }
+            Parameter::Mute => {
+                self.audio.settings.lock().unwrap().mute = value >= 0.5;
+            }
+            Parameter::Output => {
+                let mut settings = self.audio.settings.lock().unwrap();
+                if let Some(enabled) = settings.enabled.get_mut(channel) {
+                    *enabled = value >= 0.5;
+                }
+            }
+            Parameter::Pan => {
+                let mut settings = self.audio.settings.lock().unwrap();
+                if let Some(pan) = settings.pans.get_mut(channel) {
+                    *pan = value;
+                }
+            }
+        }
+    }
+
+    /// Current state of all channels and the output.
+    pub fn preset(&self) -> Preset {
+        let settings = self.audio.settings.lock().unwrap();
+        let channels = self
+            .waveform_previews
+            .iter()
+            .enumerate()
+            .map(|(i, waveform)| ChannelPreset {
+                waveform: format!("{}", waveform.waveform_type()),
+                frequency: waveform.frequency(),
+                amplitude: waveform.amplitude(),
+                phase_offset: waveform.phase_offset(),
+                dc_offset: waveform.dc_offset(),
+                pan: settings.pans.get(i).copied().unwrap_or(0.0),
+                output: settings.channel_gain(i) > 0.0,
+            })
+            .collect();
+        Preset {
+            output: OutputPreset {
+                volume: settings.volume,
+                mute: settings.mute,
+                routing: usize::from(settings.routing),
+            },
+            channels,
+        }
+    }
+
+    /// Restore a preset into the previews and the audio thread, holding the audio locks for
+    /// the whole update so the stream never plays a partially loaded preset.
+    pub fn apply_preset(&mut self, preset: &Preset) -> Result<()> {
+        let mut waveform_types = Vec::new();
+        for channel in preset.channels.iter() {
+            match parse_waveform(&channel.waveform)
+                .and_then(|x| WaveformType::try_from((x as usize).wrapping_sub(1)).ok())
+            {
+                Some(waveform) => waveform_types.push(waveform),
+                None => bail!("Unknown waveform '{}'", channel.waveform),
+            }
+        }
+        let routing = match Routing::try_from(preset.output.routing) {
+            Ok(Routing::Channel(source)) if source >= self.channels() => Routing::Split,
+            Ok(routing) => routing,
+            Err(_) => Routing::Split,
+        };
+
+        let audio_waveforms = Arc::clone(&self.audio.waveforms);
+        let mut waveforms = audio_waveforms.lock().unwrap();
+        let mut settings = self.audio.settings.lock().unwrap();
+        for (i, (channel, waveform_type)) in preset.channels.iter().zip(waveform_types).enumerate()
+        {
+            let Some(preview) = self.waveform_previews.get_mut(i) else {
+                break;
+            };
+            let mut targets = vec![preview];
+            if let Some(waveform) = waveforms.get_mut(i) {
+                targets.push(waveform);
+            }
+            for waveform in targets {
+                waveform.set_waveform_type(waveform_type);
+                waveform.set_frequency(channel.frequency);
+                waveform.set_amplitude(channel.amplitude);
+                waveform.set_phase_offset(channel.phase_offset);
+                waveform.set_dc_offset(channel.dc_offset);
+            }
+            if let Some(enabled) = settings.enabled.get_mut(i) {
+                *enabled = channel.output;
+            }
+            if let Some(pan) = settings.pans.get_mut(i) {
+                *pan = channel.pan.clamp(-1.0, 1.0);
+            }
+        }
+        settings.volume = preset.output.volume.clamp(0.0, 1.0);
+        settings.mute = preset.output.mute;
+        settings.routing = routing;
+        Ok(())
+    }
+
+    /// Show the current output device, or the available ones whose name contains `name`.
+    pub fn show_devices(&mut self, name: Option<&str>) {
+        let Some(name) = name else {
+            self.set_message(&format!("Device: {}", self.audio.info.device));
+            return;
+        };
+        let devices: Vec<String> = output_devices()
+            .into_iter()
+            .filter(|x| x.to_lowercase().contains(&name.to_lowercase()))
+            .collect();
+        match devices.is_empty() {
+            true => self.set_warning(&format!("No output device matching '{}'", name)),
+            false => self.set_message(&format!("Output devices: {}", devices.join(", "))),
+        }
+    }
+
+    /// Whether split routing would leave channels without an output, once a device is open.
+    fn unrouted_channels(&self) -> bool {
+        let outputs = self.audio.info.channels;
+        outputs > 0 && self.channels() > outputs
+    }
+
+    /// Fail when channels would be generated without reaching an output of the device.
+    pub fn check_output_channels(&self) -> Result<()> {
+        let routing = self.audio.settings.lock().unwrap().routing;
+        if routing == Routing::Split && self.unrouted_channels() {
+            bail!(
+                "{} channels but only {} output channels available, use fewer channels or mix them",
+                self.channels(),
+                self.audio.info.channels
+            );
+        }
+        Ok(())
+    }
+
+    pub fn save_preset(&mut self, name: &str) {
+        match self.preset().save(name) {
+            Ok(()) => self.set_message(&format!("Preset '{}' saved", name)),
+            Err(error) => self.set_warning(&format!("{}", error)),
+        }
+    }
+
+    pub fn load_preset(&mut self, name: &str) {
+        match Preset::load(name).and_then(|x| self.apply_preset(&x)) {
+            Ok(()) => self.set_message(&format!("Preset '{}' loaded", name)),
+            Err(error) => self.set_warning(&format!("{}", error)),
+        }
+    }
+
+    /// Open the preset picker popup.
+    pub fn set_presets_mode(&mut self) {
+        match Preset::list() {
+            Ok(names) if names.is_empty() => self.set_warning("No presets saved"),
+            Ok(names) => {
+                self.preset_names = names;
+                self.preset_list_state.select(Some(0));
+                self.mode = Mode::Presets;
             }
+            Err(error) => self.set_warning(&format!("{}", error)),
+        }
+    }
+
+    pub fn next_preset(&mut self) {
+        if self.preset_names.is_empty() {
+            return;
+        }
+        let index = self.preset_list_state.selected().unwrap_or(0);
+        self.preset_list_state
+            .select(Some((index + 1) % self.preset_names.len()));
+    }
+
+    pub fn previous_preset(&mut self) {
+        if self.preset_names.is_empty() {
+            return;
+        }
+        let index = self.preset_list_state.selected().unwrap_or(0);
+        let count = self.preset_names.len();
+        self.preset_list_state
+            .select(Some((index + count - 1) % count));
+    }
+
+    /// Load the preset selected in the picker.
+    pub fn process_preset(&mut self) {
+        let selected = self.preset_list_state.selected().unwrap_or(0);
+        if let Some(name) = self.preset_names.get(selected).cloned() {
+            self.set_normal_mode();
+            self.load_preset(&name);
         }
     }
 
     pub fn set_normal_mode(&mut self) {
         self.mode = Mode::Normal;
+        self.history_search = None;
         self.command.clear();
+        self.keypad.clear();
     }
 
     pub fn set_help_mode(&mut self) {
@@ -171,100 +768,442 @@ impl App {
     pub fn set_command_mode(&mut self) {
         self.mode = Mode::Command;
         self.command.clear();
+        self.completions.clear();
     }
 
-    pub fn push_command_char(&mut self, c: char) {
+    pub fn set_keypad_mode(&mut self) {
+        self.mode = Mode::Keypad;
+        self.keypad.clear();
+    }
+
+    pub fn push_keypad_char(&mut self, c: char) {
+        let has_suffix = self
+            .keypad
+            .chars()
+            .last()
+            .is_some_and(|x| x.is_alphabetic());
         match c {
-            _ if c.is_alphanumeric() => self.command.push(c),
-            ' ' | ',' | '.' | '-' | '+' => self.command.push(c),
+            '0'..='9' if !has_suffix => self.keypad.push(c),
+            '.' if !has_suffix && !self.keypad.contains('.') => self.keypad.push(c),
+            // sign keys toggle the sign, like the +/- key on an instrument
+            '-' | '+' => {
+                if self.keypad.starts_with('-') {
+                    self.keypad.remove(0);
+                } else if c == '-' {
+                    self.keypad.insert(0, '-');
+                }
+            }
+            // unit suffix replaces any previous one
+            'G' | 'M' | 'k' | 'K' | 'm' | 'u' => {
+                if has_suffix {
+                    self.keypad.pop();
+                }
+                if self.keypad.chars().any(|x| x.is_ascii_digit()) {
+                    self.keypad.push(c);
+                }
+            }
             _ => {}
         }
     }
 
+    pub fn pop_keypad_char(&mut self) {
+        self.keypad.pop();
+    }
+
+    /// Value currently entered on the keypad, if it is valid.
+    pub fn keypad_value(&self) -> Option<f32> {
+        parse_value(&self.keypad)
+    }
+
+    pub fn process_keypad(&mut self) {
+        match self.keypad_value() {
+            Some(value) => {
+                self.set_parameter_value(self.selected_parameter, value);
+                self.set_normal_mode();
+            }
+            None => {
+                self.set_warning("Invalid value");
+            }
+        }
+    }
+
+    pub fn push_command_char(&mut self, c: char) {
+        if !c.is_control() {
+            self.command.push(c);
+        }
+        self.completions.clear();
+    }
+
     pub fn pop_command_char(&mut self) {
         self.command.pop();
+        self.completions.clear();
+    }
+
+    /// Complete the word at the end of the command line. An ambiguous word is completed
+    /// up to the longest common prefix and the candidates are kept in `completions`.
+    pub fn complete_command(&mut self) {
+        let context = complete::context(&self.command);
+        let candidates: Vec<String> = match context.argument {
+            Argument::Command => COMMANDS
+                .iter()
+                .map(|x| x.names[0].to_string())
+                .chain(self.macros.names())
+                .collect(),
+            Argument::Channel => (1..=self.channels()).map(|x| x.to_string()).collect(),
+            Argument::Waveform => WAVEFORMS.iter().map(|x| x.to_string()).collect(),
+            Argument::State => vec!["on".to_string(), "off".to_string()],
+            Argument::Routing => ["split".to_string(), "mix".to_string()]
+                .into_iter()
+                .chain((1..=self.channels()).map(|x| format!("ch{}", x)))
+                .collect(),
+            Argument::Query => command::query_names()
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            Argument::Preset if context.word.contains('/') => complete::paths(context.word),
+            Argument::Preset => Preset::list().unwrap_or_default(),
+            Argument::Device => output_devices(),
+            Argument::Path => complete::paths(context.word),
+            Argument::Keyword(words) => words.iter().map(|x| x.to_string()).collect(),
+            Argument::Value | Argument::Duration | Argument::None => Vec::new(),
+        };
+        let matches = complete::matches(context.word, &candidates);
+        if matches.is_empty() {
+            self.completions.clear();
+            return;
+        }
+        let mut completed = complete::common_prefix(&matches);
+        // a single match is a complete word, unless it is a directory to complete further
+        if matches.len() == 1 && !completed.ends_with('/') {
+            completed.push(' ');
+        }
+        self.command.replace_range(context.start.., &completed);
+        self.completions = if matches.len() > 1 {
+            matches
+        } else {
+            Vec::new()
+        };
     }
 
     pub fn process_command(&mut self) {
-        let command = self.command.trim().to_lowercase();
+        let command = self.command.trim().to_string();
         self.command.clear();
 
-        // add to command history
-        if command.len() > 0 {
+        // add to command history, skipping repeated commands
+        if !command.is_empty() && self.command_history.get(1) != Some(&command) {
             self.command_history.insert(1, command.clone());
-            self.command_history_index = 0;
-        }
-
-        // process command
-        let parameters = command
-            .split_whitespace()
-            .filter(|x| x.len() > 0)
-            .collect::<Vec<&str>>();
-        match parameters.first().unwrap_or(&"").as_ref() {
-            "q" | "quit" | "exit" => self.quit(),
-            "h" | "help" => {
-                self.set_help_mode();
-                return;
+            self.command_history.truncate(self.history_limit + 1);
+            if let Err(error) = self.save_history() {
+                self.set_warning(&format!("Failed to save history: {}", error));
             }
-            "f" | "freq" | "frequency" => {
-                if parameters.len() > 1 {
-                    if let Ok(frequency) = parameters.get(1).unwrap_or(&"").parse::<f32>() {
-                        self.set_parameter_value(Parameter::Frequency, frequency);
-                    } else {
-                        self.set_warning("Invalid frequency");
-                    }
-                } else {
-                    self.set_warning("No parameter");
+        }
+        self.command_history_index = 0;
+
+        self.execute_command(&command);
+    }
+
+    /// Execute a command line, from the command mode, a key binding or the config file,
+    /// returning the replies of its queries. Nothing is run if any of its commands is invalid.
+    pub fn execute_command(&mut self, line: &str) -> Vec<String> {
+        self.set_normal_mode();
+        match command::parse_with(line, &self.macros) {
+            // a line with waits is run like a script
+            Ok(commands) if commands.iter().any(|x| matches!(x, Command::Wait(_))) => {
+                self.script = Some(Script::from_commands("command", commands));
+                self.step_script();
+                Vec::new()
+            }
+            Ok(commands) => commands
+                .iter()
+                .filter_map(|x| self.run_command(x))
+                .collect(),
+            Err(error) => {
+                self.set_warning(&error.to_string());
+                Vec::new()
+            }
+        }
+    }
+
+    /// Run a command, returning the reply of a query.
+    pub fn run_command(&mut self, command: &Command) -> Option<String> {
+        match command {
+            Command::SelectChannel(channel) => match *channel < self.channels() {
+                true => self.set_tab(*channel),
+                false => self.set_warning("Invalid channel"),
+            },
+            Command::Set {
+                channel,
+                parameter,
+                value,
+            } => {
+                let channel = channel.unwrap_or(self.selected_waveform);
+                self.set_channel_parameter_value(channel, *parameter, *value);
+            }
+            Command::Toggle {
+                parameter: Parameter::Mute,
+                ..
+            } => self.toggle_mute(),
+            Command::Toggle { channel, parameter } => {
+                let channel = channel.unwrap_or(self.selected_waveform);
+                let enabled = self.channel_parameter_value(channel, *parameter);
+                let value = if enabled == Some(0.0) { 1.0 } else { 0.0 };
+                self.set_channel_parameter_value(channel, *parameter, value);
+            }
+            Command::SavePreset(name) => self.save_preset(name),
+            Command::LoadPreset(name) => self.load_preset(name),
+            Command::Presets => self.set_presets_mode(),
+            Command::Device(name) => self.show_devices(name.as_deref()),
+            Command::Source(path) => self.source_script(Path::new(path)),
+            // waits are handled by the script
+            Command::Wait(_) => {}
+            Command::Learn(enable) => midi::learn(self, *enable),
+            Command::View(view) => self.view = *view,
+            Command::Scope(setting) => match self.scope.apply(*setting, &self.audio.tap) {
+                Ok(message) => self.set_message(&message),
+                Err(warning) => self.set_warning(&warning),
+            },
+            Command::Spectrum(setting) => match self.spectrum.apply(*setting, &self.audio.tap) {
+                Ok(message) => self.set_message(&message),
+                Err(warning) => self.set_warning(&warning),
+            },
+            Command::ResetClips => self.reset_clips(),
+            Command::Preview(setting) => match self.preview.apply(*setting) {
+                Ok(message) => self.set_message(&message),
+                Err(warning) => self.set_warning(&warning),
+            },
+            Command::Xy(setting) => match self.xy.apply(*setting, self.channels()) {
+                Ok(message) => self.set_message(&message),
+                Err(warning) => self.set_warning(&warning),
+            },
+            Command::Alias { name, definition } => {
+                match self.macros.define_alias(name, definition) {
+                    Ok(()) => self.set_message(&format!("Alias '{}' defined", name)),
+                    Err(error) => self.set_warning(&error),
                 }
             }
-            "a" | "amp" | "amplitude" => {
-                if parameters.len() > 1 {
-                    if let Ok(amplitude) = parameters.get(1).unwrap_or(&"").parse::<f32>() {
-                        self.set_parameter_value(Parameter::Amplitude, amplitude);
-                    } else {
-                        self.set_warning("Invalid amplitude");
-                    }
-                } else {
-                    self.set_warning("No parameter");
+            Command::Macro { name, definition } => {
+                match self.macros.define_macro(name, definition) {
+                    Ok(()) => self.set_message(&format!("Macro '{}' defined", name)),
+                    Err(error) => self.set_warning(&error),
                 }
             }
-            "w" | "wave" | "waveform" => {
-                if parameters.len() > 1 {
-                    if let Ok(waveform) = parameters.get(1).unwrap_or(&"").parse::<u8>() {
-                        self.set_parameter_value(Parameter::Waveform, waveform as f32);
-                    } else {
-                        match parameters.get(1).unwrap_or(&"").to_lowercase().as_ref() {
-                            "sine" => {
-                                self.set_parameter_value(Parameter::Waveform, 1.0);
-                            }
-                            "square" => {
-                                self.set_parameter_value(Parameter::Waveform, 2.0);
-                            }
-                            "triangle" => {
-                                self.set_parameter_value(Parameter::Waveform, 3.0);
-                            }
-                            "sawtooth" => {
-                                self.set_parameter_value(Parameter::Waveform, 4.0);
-                            }
-                            "noise" => {
-                                self.set_parameter_value(Parameter::Waveform, 5.0);
-                            }
-                            _ => {
-                                self.set_warning("Invalid waveform");
-                            }
-                        }
-                    }
-                } else {
-                    self.set_warning("No parameter");
+            Command::Unalias(name) => match self.macros.remove(name) {
+                true => self.set_message(&format!("'{}' removed", name)),
+                false => self.set_warning(&format!("Unknown alias '{}'", name)),
+            },
+            Command::Aliases => match self.macros.describe() {
+                lines if lines.is_empty() => self.set_message("No aliases or macros defined"),
+                lines => self.set_message(&lines.join(" | ")),
+            },
+            Command::Get { channel, parameter } => match self.query(*channel, *parameter) {
+                Some(reply) => {
+                    self.set_message(&reply);
+                    return Some(reply);
                 }
+                None => self.set_warning("Invalid channel"),
+            },
+            Command::Help => self.set_help_mode(),
+            Command::Quit => self.quit(),
+        }
+        None
+    }
+
+    /// Current values in machine-readable form, like `ch2:freq=1000 vol=0.5`. Without
+    /// parameter all the values of the channel, or of every channel and the output, are listed.
+    pub fn query(&self, channel: Option<usize>, parameter: Option<Parameter>) -> Option<String> {
+        let channel_parameters = Parameter::CHANNEL.into_iter();
+        let values: Vec<(Option<usize>, Parameter)> = match (channel, parameter) {
+            (_, Some(parameter)) if parameter.is_output() => vec![(None, parameter)],
+            (_, Some(parameter)) => {
+                vec![(Some(channel.unwrap_or(self.selected_waveform)), parameter)]
             }
-            _ => {
-                self.set_warning("Unknown command");
+            (Some(channel), None) => channel_parameters.map(|x| (Some(channel), x)).collect(),
+            (None, None) => (0..self.channels())
+                .flat_map(|i| channel_parameters.clone().map(move |x| (Some(i), x)))
+                .chain(Parameter::OUTPUT.into_iter().map(|x| (None, x)))
+                .collect(),
+        };
+        let mut reply: Vec<String> = Vec::new();
+        for (channel, parameter) in values {
+            let value =
+                self.channel_parameter_value(channel.unwrap_or(self.selected_waveform), parameter)?;
+            let name = command::parameter_name(parameter);
+            let value = command::format_value(parameter, value);
+            match channel {
+                Some(channel) => reply.push(format!("ch{}:{}={}", channel + 1, name, value)),
+                None => reply.push(format!("{}={}", name, value)),
             }
         }
+        Some(reply.join(" "))
+    }
 
-        // reset normal mode
-        self.set_normal_mode();
+    /// Values of the parameters of every channel, then of the output.
+    pub fn parameter_values(&self) -> Vec<ParameterValue> {
+        let channels = (0..self.channels()).flat_map(|channel| {
+            Parameter::CHANNEL
+                .into_iter()
+                .map(move |x| (Some(channel), x))
+        });
+        let output = Parameter::OUTPUT.into_iter().map(|x| (None, x));
+        channels
+            .chain(output)
+            .filter_map(|(channel, parameter)| {
+                let value = self.channel_parameter_value(channel.unwrap_or(0), parameter)?;
+                Some(ParameterValue {
+                    channel,
+                    parameter,
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    pub fn source_script(&mut self, path: &Path) {
+        match Script::load(path, &self.macros) {
+            Ok(script) => {
+                self.script = Some(script);
+                self.step_script();
+            }
+            Err(error) => self.set_warning(&format!("{}", error)),
+        }
+    }
+
+    /// Run the script commands until its next wait. The script stops at the first command
+    /// that fails, reporting its line.
+    pub fn step_script(&mut self) {
+        let Some(mut script) = self.script.take() else {
+            return;
+        };
+        while let Some(command) = script.next_command(Instant::now()) {
+            self.warning = None;
+            self.run_command(&command);
+            if let Some(warning) = self.warning.clone() {
+                self.set_warning(&format!(
+                    "{} line {}: {}",
+                    script.name, script.line, warning
+                ));
+                return;
+            }
+            // quitting or starting another script ends this one
+            if self.should_quit || self.script.is_some() {
+                return;
+            }
+        }
+        if script.is_finished() {
+            self.set_message(&format!("{} finished", script.name));
+        } else {
+            self.script = Some(script);
+        }
+    }
+
+    pub fn stop_script(&mut self) {
+        if let Some(script) = self.script.take() {
+            self.set_message(&format!("{} aborted at line {}", script.name, script.line));
+        }
+    }
+
+    /// Default location of the history file.
+    pub fn default_history_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|x| x.join(env!("CARGO_PKG_NAME")).join("history"))
+    }
+
+    /// Load the command history from a file with one command per line, oldest first.
+    pub fn load_history(&mut self, path: &Path) -> Result<()> {
+        self.history_path = Some(path.to_path_buf());
+        if !path.exists() {
+            return Ok(());
+        }
+        let text = std::fs::read_to_string(path)?;
+        let mut history: Vec<String> = vec!["".to_string()];
+        for line in text
+            .lines()
+            .rev()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+        {
+            if history.last().map(|x| x.as_str()) != Some(line) {
+                history.push(line.to_string());
+            }
+        }
+        history.truncate(self.history_limit + 1);
+        self.command_history = history;
+        self.command_history_index = 0;
+        Ok(())
+    }
+
+    pub fn save_history(&self) -> Result<()> {
+        let Some(path) = &self.history_path else {
+            return Ok(());
+        };
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        let mut text = String::new();
+        for command in self.command_history.iter().skip(1).rev() {
+            text.push_str(command);
+            text.push('\n');
+        }
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Commands of the history matching the search query, most recent first.
+    pub fn history_matches(&self) -> Vec<String> {
+        let query = self.history_search.as_deref().unwrap_or("");
+        let mut matches: Vec<String> = Vec::new();
+        for command in self.command_history.iter().skip(1) {
+            if command.contains(query) && !matches.contains(command) {
+                matches.push(command.clone());
+            }
+        }
+        matches
+    }
+
+    /// Start the reverse history search, or move to the next older match if searching.
+    pub fn search_history(&mut self) {
+        if self.history_search.is_none() {
+            self.history_search = Some(String::new());
+            self.history_search_state.select(Some(0));
+        } else {
+            let count = self.history_matches().len();
+            let index = self.history_search_state.selected().unwrap_or(0);
+            if index + 1 < count {
+                self.history_search_state.select(Some(index + 1));
+            }
+        }
+    }
+
+    pub fn push_search_char(&mut self, c: char) {
+        if let Some(query) = &mut self.history_search {
+            query.push(c);
+            self.history_search_state.select(Some(0));
+        }
+    }
+
+    pub fn pop_search_char(&mut self) {
+        if let Some(query) = &mut self.history_search {
+            query.pop();
+            self.history_search_state.select(Some(0));
+        }
+    }
+
+    pub fn previous_search_match(&mut self) {
+        let index = self.history_search_state.selected().unwrap_or(0);
+        self.history_search_state
+            .select(Some(index.saturating_sub(1)));
+    }
+
+    /// Put the selected match in the command line.
+    pub fn accept_history_search(&mut self) {
+        let index = self.history_search_state.selected().unwrap_or(0);
+        if let Some(command) = self.history_matches().get(index) {
+            self.command = command.clone();
+        }
+        self.history_search = None;
+    }
+
+    pub fn cancel_history_search(&mut self) {
+        self.history_search = None;
     }
 
     pub fn command_history_last(&mut self) {
@@ -293,11 +1232,21 @@ impl App {
         self.warning = Some(warning.to_string());
     }
 
+    pub fn set_message(&mut self, message: &str) {
+        self.message = Some(message.to_string());
+    }
+
     pub fn clear_warning(&mut self) {
         self.warning = None;
+        self.message = None;
     }
 }
 
+/// Display name of a channel, `Channel A` for the first one.
+pub fn channel_name(channel: usize) -> String {
+    format!("Channel {}", (b'A' + channel as u8) as char)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -312,7 +1261,7 @@ mod tests {
 
     #[test]
     fn test_app_next_tab() {
-        let mut app = App::new();
+        let mut app = App::with_channels(3);
         assert_eq!(app.tab_index, 0);
         app.next_tab();
         assert_eq!(app.tab_index, 1);
@@ -326,7 +1275,7 @@ mod tests {
 
     #[test]
     fn test_app_previous_tab() {
-        let mut app = App::new();
+        let mut app = App::with_channels(3);
         assert_eq!(app.tab_index, 0);
         app.previous_tab();
         assert_eq!(app.tab_index, 3);
@@ -347,4 +1296,353 @@ mod tests {
         app.set_tab(5);
         assert_eq!(app.tab_index, 2);
     }
+
+    #[test]
+    fn test_app_output_parameters() {
+        let mut app = App::new();
+        assert_eq!(app.selected_parameter, Parameter::Frequency);
+        app.set_tab(2);
+        assert!(app.is_output_tab());
+        assert_eq!(app.selected_parameter, Parameter::Routing);
+        app.next_parameter();
+        assert_eq!(app.selected_parameter, Parameter::Volume);
+        app.set_parameter_value(Parameter::Volume, 1.5);
+        assert_eq!(app.parameter_value(Parameter::Volume), Some(1.0));
+        app.decrease_parameter_value(Parameter::Volume);
+        assert_eq!(app.parameter_value(Parameter::Volume), Some(0.99));
+        app.increase_parameter_value(Parameter::Mute);
+        assert!(app.audio.settings.lock().unwrap().mute);
+        app.set_parameter_value(Parameter::Routing, 2.0);
+        assert_eq!(app.audio.settings.lock().unwrap().routing, Routing::Channel(0));
+        app.set_tab(0);
+        assert_eq!(app.selected_parameter, Parameter::Waveform);
+    }
+
+    #[test]
+    fn test_app_toggle_output() {
+        let mut app = App::new();
+        app.toggle_output();
+        assert_eq!(app.audio.settings.lock().unwrap().enabled, vec![false, true]);
+        assert_eq!(app.parameter_value(Parameter::Output), Some(0.0));
+        app.set_tab(2);
+        app.toggle_output();
+        assert_eq!(app.audio.settings.lock().unwrap().enabled, vec![false, false]);
+        app.toggle_output();
+        assert_eq!(app.audio.settings.lock().unwrap().enabled, vec![true, true]);
+        app.toggle_mute();
+        assert_eq!(app.audio.settings.lock().unwrap().master_gain(), 0.0);
+    }
+
+    #[test]
+    fn test_app_channels() {
+        let mut app = App::with_channels(4);
+        assert_eq!(app.channels(), 4);
+        assert_eq!(app.audio.channels(), 4);
+        assert_eq!(
+            app.tab_titles(),
+            vec!["Channel A", "Channel B", "Channel C", "Channel D", "Output"]
+        );
+        app.execute_command("ch d out off");
+        assert!(!app.audio.settings.lock().unwrap().enabled[3]);
+        app.execute_command("ch 5 out off");
+        assert_eq!(app.warning, Some("Invalid channel".to_string()));
+        assert_eq!(App::with_channels(0).channels(), 1);
+    }
+
+    #[test]
+    fn test_app_output_channels() {
+        let mut app = App::with_channels(4);
+        assert!(app.check_output_channels().is_ok());
+        // a stereo device can't play four channels split
+        app.audio.info.channels = 2;
+        assert!(app.check_output_channels().is_err());
+        app.set_parameter_value(Parameter::Routing, 1.0);
+        assert!(app.check_output_channels().is_ok());
+        app.set_parameter_value(Parameter::Routing, 0.0);
+        assert_eq!(
+            app.warning,
+            Some("Only 2 output channels available to split".to_string())
+        );
+        assert_eq!(app.audio.settings.lock().unwrap().routing, Routing::Mix);
+    }
+
+    #[test]
+    fn test_app_channel_command() {
+        let mut app = App::with_channels(3);
+        app.command = "ch 3 out off".to_string();
+        app.process_command();
+        assert_eq!(
+            app.audio.settings.lock().unwrap().enabled,
+            vec![true, true, false]
+        );
+        assert_eq!(app.selected_waveform, 0);
+        app.command = "ch 2".to_string();
+        app.process_command();
+        assert_eq!(app.tab_index, 1);
+        app.command = "ch 4 out off".to_string();
+        app.process_command();
+        assert_eq!(app.warning, Some("Invalid channel".to_string()));
+    }
+
+    #[test]
+    fn test_app_preset() {
+        let mut app = App::with_channels(2);
+        let mut preset = app.preset();
+        assert_eq!(preset.channels.len(), 2);
+        assert_eq!(preset.channels[1].frequency, 880.0);
+        preset.channels[0].waveform = "Square".to_string();
+        preset.channels[0].frequency = 1000.0;
+        preset.channels[1].output = false;
+        preset.output.volume = 0.5;
+        let preset = Preset::parse(&preset.to_toml().unwrap()).unwrap();
+        app.apply_preset(&preset).unwrap();
+        assert_eq!(app.channel_parameter_value(0, Parameter::Waveform), Some(2.0));
+        assert_eq!(app.channel_parameter_value(0, Parameter::Frequency), Some(1000.0));
+        assert_eq!(app.channel_parameter_value(1, Parameter::Output), Some(0.0));
+        assert_eq!(app.parameter_value(Parameter::Volume), Some(0.5));
+        assert_eq!(app.preset(), preset);
+
+        // invalid presets are rejected without changing anything
+        let mut invalid = preset.clone();
+        invalid.channels[0].waveform = "sinus".to_string();
+        invalid.channels[0].frequency = 1.0;
+        assert!(app.apply_preset(&invalid).is_err());
+        assert_eq!(app.preset(), preset);
+    }
+
+    #[test]
+    fn test_app_preset_modes() {
+        let mut app = App::with_channels(2);
+        app.execute_command("ch1:pan -0.5; ch2:pan 2; ch2:out off; routing mix; mute on");
+        let preset = Preset::parse(&app.preset().to_toml().unwrap()).unwrap();
+        assert_eq!(preset.channels[0].pan, -0.5);
+        assert_eq!(preset.channels[1].pan, 1.0);
+
+        let mut other = App::with_channels(2);
+        other.apply_preset(&preset).unwrap();
+        assert_eq!(other.channel_parameter_value(0, Parameter::Pan), Some(-0.5));
+        assert_eq!(other.channel_parameter_value(1, Parameter::Pan), Some(1.0));
+        assert!(!other.audio.settings.lock().unwrap().enabled[1]);
+        assert_eq!(other.parameter_value(Parameter::Mute), Some(1.0));
+        assert_eq!(other.parameter_value(Parameter::Routing), Some(1.0));
+        assert_eq!(other.preset(), app.preset());
+    }
+
+    #[test]
+    fn test_app_history() {
+        let path = std::env::temp_dir().join(format!("sg-history-{}", std::process::id()));
+        std::fs::write(&path, "freq 100\nfreq 100\namp 1\nfreq 200\n").unwrap();
+        let mut app = App::new();
+        app.history_limit = 2;
+        app.load_history(&path).unwrap();
+        assert_eq!(app.command_history, vec!["", "freq 200", "amp 1"]);
+
+        for command in ["ch 2", "ch 2", "help"] {
+            app.command = command.to_string();
+            app.process_command();
+        }
+        assert_eq!(app.command_history, vec!["", "help", "ch 2"]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ch 2\nhelp\n");
+
+        app.set_command_mode();
+        app.search_history();
+        app.push_search_char('2');
+        assert_eq!(app.history_matches(), vec!["ch 2"]);
+        app.accept_history_search();
+        assert_eq!(app.command, "ch 2");
+
+        // chords don't type into the query, Ctrl-C cancels the search
+        let key = |code, modifiers| crossterm::event::KeyEvent::new(code, modifiers);
+        app.search_history();
+        crate::update::update(&mut app, key(KeyCode::Char('H'), KeyModifiers::SHIFT));
+        crate::update::update(&mut app, key(KeyCode::Char('x'), KeyModifiers::ALT));
+        assert_eq!(app.history_search.as_deref(), Some("H"));
+        crate::update::update(&mut app, key(KeyCode::Char('c'), KeyModifiers::CONTROL));
+        assert_eq!(app.history_search, None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_app_complete_command() {
+        let mut app = App::new();
+        app.set_command_mode();
+        for c in "ch 2 wav".chars() {
+            app.push_command_char(c);
+        }
+        app.complete_command();
+        assert_eq!(app.command, "ch 2 wave ");
+        app.push_command_char('s');
+        app.complete_command();
+        assert_eq!(app.command, "ch 2 wave s");
+        assert_eq!(app.completions, vec!["sine", "square", "sawtooth"]);
+        app.push_command_char('q');
+        assert!(app.completions.is_empty());
+        app.complete_command();
+        assert_eq!(app.command, "ch 2 wave square ");
+    }
+
+    #[test]
+    fn test_app_query() {
+        let mut app = App::with_channels(2);
+        app.execute_command("ch2:mute?");
+        assert_eq!(
+            app.warning,
+            Some("'mute': does not apply to a channel".to_string())
+        );
+        let replies = app.execute_command("ch2:freq?; vol?; ch 1 out off; ch1:state?");
+        assert_eq!(replies[0], "ch2:freq=880");
+        assert_eq!(app.message, Some(replies[2].clone()));
+        assert_eq!(
+            replies,
+            vec![
+                "ch2:freq=880",
+                "vol=1",
+                "ch1:wave=sine ch1:freq=440 ch1:amp=1 ch1:phase=0 ch1:dc=0 ch1:pan=0 ch1:out=off"
+            ]
+        );
+        assert!(app
+            .query(None, None)
+            .unwrap()
+            .ends_with("routing=split vol=1 mute=off"));
+        assert_eq!(app.query(Some(2), None), None);
+    }
+
+    #[test]
+    fn test_app_script() {
+        let mut app = App::with_channels(2);
+        app.execute_command("ch2:freq 1k; wait 10s; freq 2k");
+        assert_eq!(
+            app.channel_parameter_value(1, Parameter::Frequency),
+            Some(1000.0)
+        );
+        assert!(app.script.is_some());
+        app.stop_script();
+        assert_eq!(app.message, Some("command aborted at line 1".to_string()));
+
+        let path = std::env::temp_dir().join(format!("sg-script-{}.sg", std::process::id()));
+        std::fs::write(&path, "# test\nrepeat 2\n  amp 0.5\nend\nch 3 out off\n").unwrap();
+        app.execute_command(&format!("source {}", path.display()));
+        assert_eq!(
+            app.channel_parameter_value(0, Parameter::Amplitude),
+            Some(0.5)
+        );
+        assert!(app.script.is_none());
+        assert!(app
+            .warning
+            .as_ref()
+            .unwrap()
+            .ends_with("line 5: Invalid channel"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_app_macros() {
+        let mut app = App::with_channels(2);
+        app.execute_command("macro both 'ch1:freq $1; ch2:freq $1'; alias f2 ch2:freq");
+        app.execute_command("both 1k; f2 2k");
+        assert_eq!(
+            app.channel_parameter_value(0, Parameter::Frequency),
+            Some(1000.0)
+        );
+        assert_eq!(
+            app.channel_parameter_value(1, Parameter::Frequency),
+            Some(2000.0)
+        );
+        app.execute_command("aliases");
+        assert_eq!(
+            app.message,
+            Some("alias f2 = ch2:freq | macro both = ch1:freq $1; ch2:freq $1".to_string())
+        );
+        app.execute_command("unalias both");
+        app.execute_command("both 1k");
+        assert_eq!(app.warning, Some("'both': unknown command".to_string()));
+        app.execute_command("alias q freq 1");
+        assert_eq!(app.warning, Some("'q' is a built-in command".to_string()));
+    }
+
+    #[test]
+    fn test_app_keypad_entry() {
+        let mut app = App::new();
+        app.set_keypad_mode();
+        assert_eq!(app.mode, Mode::Keypad);
+        for c in "1.5.0k3".chars() {
+            app.push_keypad_char(c);
+        }
+        assert_eq!(app.keypad, "1.50k");
+        assert_eq!(app.keypad_value(), Some(1500.0));
+        app.push_keypad_char('-');
+        assert_eq!(app.keypad, "-1.50k");
+        app.push_keypad_char('m');
+        assert_eq!(app.keypad, "-1.50m");
+        assert_eq!(app.keypad_value(), Some(-0.0015));
+        app.push_keypad_char('+');
+        assert_eq!(app.keypad, "1.50m");
+        app.set_normal_mode();
+        assert!(app.keypad.is_empty());
+    }
+
+    #[test]
+    fn test_app_keypad_waveform_zero() {
+        let mut app = App::new();
+        app.selected_parameter = Parameter::Waveform;
+        for keys in ["0", "-2"] {
+            app.set_keypad_mode();
+            keys.chars().for_each(|c| app.push_keypad_char(c));
+            app.process_keypad();
+            assert_eq!(app.warning, Some("Invalid waveform".to_string()));
+            assert_eq!(
+                *app.waveform_previews[0].waveform_type(),
+                WaveformType::Sine
+            );
+        }
+    }
+
+    #[test]
+    fn test_app_drag_zoom() {
+        let mut app = App::new();
+        app.areas.preview = Rect::new(0, 0, 102, 20);
+        app.drag(60, 10, false);
+        assert_eq!(app.preview.zoom, 1.0);
+        app.start_drag(50, 10);
+        app.drag(60, 10, false);
+        assert!((app.preview.zoom - 1.0 / 0.9).abs() < 1e-6);
+        app.drag(40, 10, false);
+        assert!((app.preview.zoom - 1.0 / 0.9 / 1.2).abs() < 1e-6);
+        app.stop_drag();
+        app.drag(0, 10, false);
+        assert!((app.preview.zoom - 1.0 / 0.9 / 1.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_app_next_view() {
+        let mut app = App::with_channels(2);
+        let mut views = Vec::new();
+        for _ in command::VIEWS {
+            views.push(app.view);
+            app.next_view();
+        }
+        assert_eq!(
+            views,
+            [View::Preview, View::Scope, View::Spectrum, View::Xy]
+        );
+        assert_eq!(app.view, View::Preview);
+    }
+
+    #[test]
+    fn test_app_drag_clamp() {
+        let mut app = App::new();
+        app.areas.preview = Rect::new(0, 0, 102, 20);
+        // each drag across the canvas moves by more than the whole range
+        app.start_drag(50, 19);
+        app.drag(50, 0, false);
+        assert_eq!(app.parameter_value(Parameter::Amplitude), Some(1.0));
+        app.drag(50, 19, false);
+        assert_eq!(app.parameter_value(Parameter::Amplitude), Some(0.0));
+        app.drag(50, 0, true);
+        assert_eq!(app.parameter_value(Parameter::DcOffset), Some(1.0));
+        app.drag(50, 19, true);
+        assert_eq!(app.parameter_value(Parameter::DcOffset), Some(-1.0));
+        app.set_parameter_value(Parameter::Amplitude, 1.5);
+        assert_eq!(app.parameter_value(Parameter::Amplitude), Some(1.0));
+    }
 }
@@ -1,7 +1,11 @@
-use crate::app::WAVEFORMS_COUNT;
+use crate::app::channel_name;
+use crate::config::AudioConfig;
+use crate::meter::{Levels, Meter};
+use color_eyre::eyre::{OptionExt, Result};
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use waveforms_rs::Waveform;
-use color_eyre::eyre::{Result, OptionExt};
 
 #[cfg(feature = "pulse")]
 extern crate pulseaudio_simple_device as pulse;
@@ -11,16 +15,293 @@ use pulse::{config::Config, device::Device, stream::Stream};
 #[cfg(feature = "cpal")]
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    Stream, Device, StreamConfig
+    BufferSize, Device, SampleRate, Stream, StreamConfig, StreamInstant,
 };
 
+/// How the channel waveforms are routed to the device output channels.
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum Routing {
+    /// Each channel goes to the output channel with the same index.
+    #[default]
+    Split,
+    /// All channels are mixed to every output channel.
+    Mix,
+    /// A single channel goes to every output channel.
+    Channel(usize),
+}
+
+impl TryFrom<usize> for Routing {
+    type Error = ();
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Routing::Split),
+            1 => Ok(Routing::Mix),
+            _ => Ok(Routing::Channel(value - 2)),
+        }
+    }
+}
+
+impl From<Routing> for usize {
+    fn from(routing: Routing) -> Self {
+        match routing {
+            Routing::Split => 0,
+            Routing::Mix => 1,
+            Routing::Channel(channel) => channel + 2,
+        }
+    }
+}
+
+impl fmt::Display for Routing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Routing::Split => write!(f, "Split"),
+            Routing::Mix => write!(f, "Mix"),
+            Routing::Channel(channel) => write!(f, "{} only", channel_name(*channel)),
+        }
+    }
+}
+
+impl Routing {
+    /// Value of an output channel given the values and pans of all waveform channels. The pans
+    /// only apply to the mix, even outputs being left and odd ones right.
+    fn route(&self, output: usize, values: &[f32], pans: &[f32]) -> f32 {
+        match self {
+            Routing::Split => values.get(output).copied().unwrap_or(0.0),
+            Routing::Mix => {
+                let sum: f32 = values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| x * pan_gain(pans.get(i).copied().unwrap_or(0.0), output))
+                    .sum();
+                sum / values.len().max(1) as f32
+            }
+            Routing::Channel(channel) => values.get(*channel).copied().unwrap_or(0.0),
+        }
+    }
+}
+
+/// Gain of a channel panned from -1 (left) to 1 (right) on an output, full in the centre.
+fn pan_gain(pan: f32, output: usize) -> f32 {
+    match output % 2 {
+        0 => (1.0 - pan).min(1.0),
+        _ => (1.0 + pan).min(1.0),
+    }
+}
+
+/// Seconds of output kept by the sample tap.
+const TAP_TIME: f32 = 2.0;
+
+/// Output samples shared by the audio callback with the views. The callback overwrites the
+/// oldest frames without locking, so reading up to half of the capacity gets whole frames.
+#[derive(Debug, Default)]
+pub struct SampleTap {
+    channels: usize,
+    /// Interleaved frames, as the bits of `f32`.
+    samples: Vec<AtomicU32>,
+    /// Frames written since the stream was created.
+    written: AtomicUsize,
+}
+
+impl SampleTap {
+    pub fn new(channels: usize, frames: usize) -> Self {
+        Self {
+            channels,
+            samples: (0..channels * frames).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Number of frames kept.
+    pub fn capacity(&self) -> usize {
+        self.samples.len() / self.channels.max(1)
+    }
+
+    /// Frames written since the stream was created.
+    pub fn written(&self) -> usize {
+        self.written.load(Ordering::Acquire)
+    }
+
+    /// Append interleaved frames, called by the audio callback.
+    pub fn write(&self, data: &[f32]) {
+        if self.samples.is_empty() {
+            return;
+        }
+        let start = self.written.load(Ordering::Relaxed) * self.channels;
+        for (i, sample) in data.iter().enumerate() {
+            let index = (start + i) % self.samples.len();
+            self.samples[index].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.written
+            .fetch_add(data.len() / self.channels, Ordering::Release);
+    }
+
+    /// The latest frames of each channel, up to `frames`, and the number of frames written
+    /// up to the last of them.
+    pub fn read(&self, frames: usize) -> (usize, Vec<Vec<f32>>) {
+        let written = self.written();
+        let frames = frames.min(written).min(self.capacity());
+        let mut channels = vec![Vec::with_capacity(frames); self.channels];
+        for frame in written - frames..written {
+            for (channel, samples) in channels.iter_mut().enumerate() {
+                let index = (frame * self.channels + channel) % self.samples.len();
+                samples.push(f32::from_bits(self.samples[index].load(Ordering::Relaxed)));
+            }
+        }
+        (written, channels)
+    }
+}
+
+/// Duration of the fade applied when muting or toggling an output, in seconds.
+const FADE_TIME: f32 = 0.01;
+
+/// Output settings shared with the audio thread.
+#[derive(Debug, Clone)]
+pub struct OutputSettings {
+    /// Master gain applied after routing.
+    pub volume: f32,
+    pub mute: bool,
+    pub routing: Routing,
+    /// Output enable of each channel.
+    pub enabled: Vec<bool>,
+    /// Pan of each channel in the mix, from -1 (left) to 1 (right).
+    pub pans: Vec<f32>,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl OutputSettings {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            volume: 1.0,
+            mute: false,
+            routing: Routing::default(),
+            enabled: vec![true; channels],
+            pans: vec![0.0; channels],
+        }
+    }
+
+    /// Copy the settings without allocating, for the audio callback.
+    pub fn copy_from(&mut self, other: &Self) {
+        self.volume = other.volume;
+        self.mute = other.mute;
+        self.routing = other.routing;
+        self.enabled.clone_from(&other.enabled);
+        self.pans.clone_from(&other.pans);
+    }
+
+    /// Target gain of the master output.
+    pub fn master_gain(&self) -> f32 {
+        if self.mute {
+            0.0
+        } else {
+            self.volume
+        }
+    }
+
+    /// Target gain of a channel output.
+    pub fn channel_gain(&self, channel: usize) -> f32 {
+        if self.enabled.get(channel).copied().unwrap_or(false) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Linear gain ramp used to fade in and out without clicks.
+#[derive(Debug, Clone, Copy)]
+struct Ramp {
+    value: f32,
+    step: f32,
+}
+
+impl Ramp {
+    fn new(value: f32, sample_rate: f32) -> Self {
+        Self {
+            value,
+            step: 1.0 / (FADE_TIME * sample_rate),
+        }
+    }
+
+    /// Move the gain one sample towards the target.
+    fn process(&mut self, target: f32) -> f32 {
+        if self.value < target {
+            self.value = (self.value + self.step).min(target);
+        } else if self.value > target {
+            self.value = (self.value - self.step).max(target);
+        }
+        self.value
+    }
+}
+
+/// Output status reported by the audio thread.
+#[derive(Default, Debug, Clone)]
+pub struct OutputStatus {
+    /// Levels of each output channel.
+    pub meters: Vec<Levels>,
+    /// Output channels that clipped since the indicators were reset.
+    pub clips: Vec<bool>,
+    /// Number of frames in the last buffer.
+    pub buffer_frames: usize,
+    /// Number of late callbacks and stream errors.
+    pub xruns: usize,
+    pub error: Option<String>,
+}
+
+/// Stream state as seen by the application.
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum StreamState {
+    #[default]
+    Stopped,
+    Running,
+}
+
+/// Information about the opened output device.
+#[derive(Default, Debug, Clone)]
+pub struct StreamInfo {
+    pub host: String,
+    pub device: String,
+    pub sample_rate: u32,
+    /// Requested buffer size in frames, `None` when using the device default.
+    pub buffer_size: Option<u32>,
+    pub channels: usize,
+}
+
 #[derive(Default)]
 pub struct AudioStream {
     pub waveforms: Arc<Mutex<Vec<Waveform>>>,
+    pub settings: Arc<Mutex<OutputSettings>>,
+    pub status: Arc<Mutex<OutputStatus>>,
+    /// Samples sent to the device, for the oscilloscope.
+    pub tap: Arc<SampleTap>,
+    pub info: StreamInfo,
+    pub state: StreamState,
+    /// Requested device, sample rate and buffer size.
+    pub preferences: AudioConfig,
     stream: Option<Stream>,
 }
 
 impl AudioStream {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            settings: Arc::new(Mutex::new(OutputSettings::new(channels))),
+            ..Default::default()
+        }
+    }
+
+    /// Number of waveform channels.
+    pub fn channels(&self) -> usize {
+        self.settings.lock().unwrap().enabled.len()
+    }
+
     pub fn create_stream(&mut self) -> Result<()> {
         let device = self.create_device()?;
         let config = self.create_config(&device)?;
@@ -28,11 +309,22 @@ impl AudioStream {
 
         // Create audio thread waveforms
         let mut waveforms: Vec<Waveform> = Vec::new();
-        for i in 0..WAVEFORMS_COUNT {
+        for i in 0..self.channels() {
             waveforms.push(Waveform::new(sample_rate, 440.0 * (i as f32 + 1.0)));
         }
         self.waveforms = Arc::new(Mutex::new(waveforms));
 
+        self.info = StreamInfo {
+            host: cpal::default_host().id().name().to_string(),
+            device: device.name().unwrap_or_else(|_| "Unknown".to_string()),
+            sample_rate: config.sample_rate.0,
+            buffer_size: match config.buffer_size {
+                BufferSize::Fixed(frames) => Some(frames),
+                BufferSize::Default => None,
+            },
+            channels: config.channels as usize,
+        };
+
         self.create_stream_inner(&device, &config)
     }
 
@@ -45,24 +337,86 @@ impl AudioStream {
     }
 
     fn create_stream_inner(&mut self, device: &Device, config: &StreamConfig) -> Result<()> {
-        println!("Default output config : {:?}", config);
         let channels = config.channels as usize;
+        let sample_rate = config.sample_rate.0 as f32;
 
         // callbacks
-        let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+        let error_status = Arc::clone(&self.status);
+        let err_fn = move |err| {
+            let mut status = error_status.lock().unwrap();
+            status.xruns += 1;
+            status.error = Some(format!("{}", err));
+        };
 
         let thread_waveforms = Arc::clone(&self.waveforms);
+        let thread_settings = Arc::clone(&self.settings);
+        let thread_status = Arc::clone(&self.status);
+        self.tap = Arc::new(SampleTap::new(channels, (TAP_TIME * sample_rate) as usize));
+        let thread_tap = Arc::clone(&self.tap);
+
+        // buffers reused across callbacks
+        let mut values: Vec<f32> = vec![0.0; self.channels()];
+        let mut meters: Vec<Meter> = vec![Meter::new(sample_rate); channels];
+        let mut last_callback: Option<(StreamInstant, usize)> = None;
+
+        // gain ramps start at the current settings so the stream doesn't fade in on creation,
+        // the copy is then updated by the callback when the settings aren't locked
+        let mut settings = self.settings.lock().unwrap().clone();
+        let mut master_ramp = Ramp::new(settings.master_gain(), sample_rate);
+        let mut channel_ramps: Vec<Ramp> = (0..settings.enabled.len())
+            .map(|i| Ramp::new(settings.channel_gain(i), sample_rate))
+            .collect();
 
         #[cfg(feature = "cpal")]
-        let data_fn = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            for frame in data.chunks_mut(channels) {
+        let data_fn = move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+            if let Ok(shared) = thread_settings.try_lock() {
+                settings.copy_from(&shared);
+            }
+            let master_gain = settings.master_gain();
+            let frames = data.len() / channels;
+
+            // a callback arriving much later than the previous buffer lasted is an xrun
+            let callback = info.timestamp().callback;
+            let mut late = false;
+            if let Some((last, last_frames)) = last_callback {
+                if let Some(elapsed) = callback.duration_since(&last) {
+                    late = elapsed.as_secs_f32() > 1.5 * last_frames as f32 / sample_rate;
+                }
+            }
+            last_callback = Some((callback, frames));
+
+            // a single output has nothing to pan between
+            let pans: &[f32] = if channels > 1 { &settings.pans } else { &[] };
+            {
                 let mut waveforms = thread_waveforms.lock().unwrap();
-                // copy the same value to all channels
-                let value = waveforms[0].process();
-                for sample in frame {
-                    *sample = value;
+                for frame in data.chunks_mut(channels) {
+                    for (i, (value, waveform)) in
+                        values.iter_mut().zip(waveforms.iter_mut()).enumerate()
+                    {
+                        let gain = channel_ramps[i].process(settings.channel_gain(i));
+                        *value = waveform.process() * gain;
+                    }
+                    let gain = master_ramp.process(master_gain);
+                    for (i, sample) in frame.iter_mut().enumerate() {
+                        *sample = settings.routing.route(i, &values, pans) * gain;
+                        meters[i].process(*sample);
+                    }
                 }
             }
+
+            thread_tap.write(data);
+
+            let mut status = thread_status.lock().unwrap();
+            status.meters.resize(channels, Levels::default());
+            status.clips.resize(channels, false);
+            for (i, meter) in meters.iter_mut().enumerate() {
+                status.meters[i] = meter.levels();
+                status.clips[i] |= meter.take_clip();
+            }
+            status.buffer_frames = frames;
+            if late {
+                status.xruns += 1;
+            }
         };
 
         self.stream = Some(device.build_output_stream(config, data_fn, err_fn, None)?);
@@ -72,6 +426,7 @@ impl AudioStream {
     pub fn start_stream(&mut self) -> Result<()> {
         if let Some(stream) = &self.stream {
             stream.play()?;
+            self.state = StreamState::Running;
         }
         Ok(())
     }
@@ -81,19 +436,51 @@ impl AudioStream {
             stream.pause()?;
             self.stream = None;
         }
+        self.state = StreamState::Stopped;
         Ok(())
     }
 }
 
+/// Names of the available output devices.
+#[cfg(feature = "cpal")]
+pub fn output_devices() -> Vec<String> {
+    match cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|x| x.name().ok()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
 #[cfg(feature = "cpal")]
 impl AudioStream {
     fn create_device_inner(&self) -> Result<Device> {
         let host = cpal::default_host();
-        host.default_output_device().ok_or_eyre("Failed to create a default output device")
+        match &self.preferences.device {
+            Some(name) => host
+                .output_devices()?
+                .find(|x| x.name().is_ok_and(|x| x.eq_ignore_ascii_case(name)))
+                .ok_or_eyre(format!("Output device '{}' not found", name)),
+            None => host
+                .default_output_device()
+                .ok_or_eyre("Failed to create a default output device"),
+        }
     }
 
     fn create_config_inner(&self, device: &Device) -> Result<StreamConfig> {
-        Ok(device.default_output_config()?.config())
+        let default = device.default_output_config()?;
+        let mut config = match self.preferences.sample_rate {
+            Some(rate) => device
+                .supported_output_configs()?
+                .filter(|x| x.channels() == default.channels())
+                .find(|x| x.min_sample_rate().0 <= rate && rate <= x.max_sample_rate().0)
+                .ok_or_eyre(format!("Sample rate {} Hz not supported", rate))?
+                .with_sample_rate(SampleRate(rate))
+                .config(),
+            None => default.config(),
+        };
+        if let Some(frames) = self.preferences.buffer_size {
+            config.buffer_size = BufferSize::Fixed(frames);
+        }
+        Ok(config)
     }
 }
 
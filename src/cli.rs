@@ -0,0 +1,184 @@
+use crate::parameter::parse_time;
+use color_eyre::eyre::{bail, eyre, Result};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Usage text printed for `--help`.
+pub const USAGE: &str = concat!(
+    "Usage: ",
+    env!("CARGO_PKG_NAME"),
+    " [play] [OPTIONS]\n\n",
+    "Commands:\n",
+    "  play                   Play without the terminal interface, same as --headless\n\n",
+    "Options:\n",
+    "  -c, --channels <N>     Number of waveform channels\n",
+    "      --config <FILE>    Configuration file to use instead of the default\n",
+    "  -s, --script <FILE>    Script to run at startup\n",
+    "      --headless         Play without the terminal interface until Ctrl-C\n",
+    "      --ch<N> <SPEC>     Channel N as <waveform>,<frequency>,<amplitude>,\n",
+    "                         e.g. --ch1 sine,1k,-6dBFS (headless)\n",
+    "  -d, --duration <TIME>  Stop after a time like 10s or 500ms (headless)\n",
+    "      --stdin            Read commands from stdin, replying on stdout (headless)\n",
+    "      --json             Reply to the stdin commands with JSON lines (with --stdin)\n",
+    "      --scpi <ADDRESS>   Listen for SCPI commands, e.g. 127.0.0.1:5025\n",
+    "      --socket <PATH>    Listen for JSON-RPC requests on a Unix socket\n",
+    "      --osc <ADDRESS>    Listen for OSC messages on a UDP address or port\n",
+    "      --http <ADDRESS>   Serve the HTTP API and web panel on an address or port\n",
+    "      --midi <PORT>      Play from a MIDI input port, or from a new virtual one\n",
+    "                         with --midi virtual\n",
+    "  -h, --help             Print help\n",
+    "  -V, --version          Print version\n",
+);
+
+/// Command line arguments.
+#[derive(Default, Debug, PartialEq)]
+pub struct Args {
+    pub channels: Option<usize>,
+    pub config: Option<PathBuf>,
+    pub script: Option<PathBuf>,
+    /// Play without the terminal interface.
+    pub headless: bool,
+    pub duration: Option<Duration>,
+    /// Channel number and `<waveform>,<frequency>,<amplitude>` of each `--ch<N>` option.
+    pub channel_specs: Vec<(usize, String)>,
+    /// Read commands from stdin, implies `headless`.
+    pub stdin: bool,
+    pub json: bool,
+    /// Address of the SCPI listener.
+    pub scpi: Option<String>,
+    /// Path of the JSON-RPC socket.
+    pub socket: Option<PathBuf>,
+    /// UDP address or port of the OSC server.
+    pub osc: Option<String>,
+    /// Address or port of the HTTP server.
+    pub http: Option<String>,
+    /// Name of the MIDI input port, or `virtual`.
+    pub midi: Option<String>,
+    pub help: bool,
+    pub version: bool,
+}
+
+impl Args {
+    /// Parse the arguments, without the program name.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // accept both `--option value` and `--option=value`
+            let (name, mut value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                }
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| {
+                value
+                    .take()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| eyre!("Missing value for '{}'", name))
+            };
+            match name.as_str() {
+                "-c" | "--channels" => {
+                    let channels = value(&name)?;
+                    parsed.channels = Some(
+                        channels
+                            .parse()
+                            .map_err(|_| eyre!("Invalid number of channels '{}'", channels))?,
+                    );
+                }
+                "--config" => parsed.config = Some(PathBuf::from(value(&name)?)),
+                "-s" | "--script" => parsed.script = Some(PathBuf::from(value(&name)?)),
+                "play" | "--headless" => parsed.headless = true,
+                "-d" | "--duration" => {
+                    let duration = value(&name)?;
+                    parsed.duration = Some(
+                        parse_time(&duration)
+                            .ok_or_else(|| eyre!("Invalid duration '{}'", duration))?,
+                    );
+                }
+                "--stdin" => {
+                    parsed.stdin = true;
+                    parsed.headless = true;
+                }
+                "--json" => parsed.json = true,
+                "--scpi" => parsed.scpi = Some(value(&name)?),
+                "--socket" => parsed.socket = Some(PathBuf::from(value(&name)?)),
+                "--osc" => parsed.osc = Some(value(&name)?),
+                "--http" => parsed.http = Some(value(&name)?),
+                "--midi" => parsed.midi = Some(value(&name)?),
+                "-h" | "--help" => parsed.help = true,
+                "-V" | "--version" => parsed.version = true,
+                _ => {
+                    let Ok(channel) = name.strip_prefix("--ch").unwrap_or("").parse::<usize>()
+                    else {
+                        bail!("Unknown argument '{}'\n\n{}", arg, USAGE);
+                    };
+                    if channel == 0 {
+                        bail!("Invalid channel in '{}'", name);
+                    }
+                    parsed.channel_specs.push((channel, value(&name)?));
+                }
+            }
+        }
+        if parsed.json && !parsed.stdin {
+            bail!("--json needs --stdin");
+        }
+        if !parsed.channel_specs.is_empty() && !parsed.headless {
+            bail!("The --ch<N> options need play or --headless");
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn test_args_channels() {
+        assert_eq!(parse(&[]).unwrap().channels, None);
+        assert_eq!(parse(&["-c", "4"]).unwrap().channels, Some(4));
+        assert_eq!(parse(&["--channels=3"]).unwrap().channels, Some(3));
+        assert!(parse(&["--channels"]).is_err());
+        assert!(parse(&["--channels", "two"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+        assert_eq!(
+            parse(&["--config", "test.toml"]).unwrap().config,
+            Some(PathBuf::from("test.toml"))
+        );
+    }
+
+    #[test]
+    fn test_args_headless() {
+        let args = parse(&[
+            "play",
+            "--ch1",
+            "sine,1k,-6dBFS",
+            "--ch2=square,500",
+            "-d",
+            "2s",
+        ]);
+        let args = args.unwrap();
+        assert!(args.headless);
+        assert_eq!(args.duration, Some(Duration::from_secs(2)));
+        assert_eq!(
+            args.channel_specs,
+            vec![
+                (1, "sine,1k,-6dBFS".to_string()),
+                (2, "square,500".to_string())
+            ]
+        );
+        assert!(parse(&["--stdin"]).unwrap().headless);
+        assert!(parse(&["--stdin", "--json"]).unwrap().json);
+        assert!(parse(&["--json"]).is_err());
+        assert!(parse(&["play", "--ch0", "sine"]).is_err());
+        assert!(parse(&["--ch1", "sine"]).is_err());
+        assert!(parse(&["--ch1", "sine", "--headless"]).unwrap().headless);
+        assert!(parse(&["--chx", "sine", "play"]).is_err());
+        assert!(parse(&["--duration", "soon"]).is_err());
+    }
+}
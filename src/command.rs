@@ -0,0 +1,1300 @@
+use crate::app::View;
+use crate::parameter::{
+    parse_level, parse_time, parse_value, parse_waveform, Parameter, WAVEFORMS,
+};
+use crate::preview::PreviewSetting;
+use crate::scope::{ScopeSetting, Slope, TriggerMode};
+use crate::spectrum::{SpectrumSetting, Window};
+use crate::xy::XySetting;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// A parsed command, shared by the command line, key bindings, scripts and remote interfaces.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Select a channel tab, from a channel prefix without command.
+    SelectChannel(usize),
+    /// Set a parameter of a channel, or of the selected channel when `None`.
+    Set {
+        channel: Option<usize>,
+        parameter: Parameter,
+        value: f32,
+    },
+    /// Toggle an on/off parameter.
+    Toggle {
+        channel: Option<usize>,
+        parameter: Parameter,
+    },
+    SavePreset(String),
+    LoadPreset(String),
+    Presets,
+    /// Report a parameter value, or all the values of a channel or the instrument when
+    /// `parameter` is `None`.
+    Get {
+        channel: Option<usize>,
+        parameter: Option<Parameter>,
+    },
+    /// Show the output devices matching a name, or the current one when `None`.
+    Device(Option<String>),
+    /// Run a script file.
+    Source(String),
+    /// Define an alias, whose arguments are appended to its command.
+    Alias {
+        name: String,
+        definition: String,
+    },
+    /// Define a macro, whose arguments replace `$1`, `$2`... and `$*` in its commands.
+    Macro {
+        name: String,
+        definition: String,
+    },
+    /// Remove an alias or macro.
+    Unalias(String),
+    /// List the aliases and macros.
+    Aliases,
+    /// Pause a script.
+    Wait(Duration),
+    /// Map the next MIDI controller to the selected parameter, toggles when `None`.
+    Learn(Option<bool>),
+    /// Show a view next to the parameters.
+    View(View),
+    /// Change a setting of the oscilloscope.
+    Scope(ScopeSetting),
+    /// Change a setting of the spectrum analyzer.
+    Spectrum(SpectrumSetting),
+    /// Reset the clip indicators of the level meters.
+    ResetClips,
+    /// Change the time window of the preview.
+    Preview(PreviewSetting),
+    /// Change a setting of the XY display.
+    Xy(XySetting),
+    Help,
+    Quit,
+}
+
+/// Why a command line failed to parse, and at which token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub token: String,
+    /// Character offset of the token in the line.
+    pub position: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.token.is_empty() {
+            write!(f, "{}", self.reason)
+        } else {
+            write!(f, "'{}': {}", self.token, self.reason)
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Kind of argument a command takes, used for completion and hints.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Argument {
+    /// Nothing more is expected.
+    None,
+    Command,
+    Channel,
+    /// A number with an optional unit suffix.
+    Value,
+    Waveform,
+    /// `on` or `off`.
+    State,
+    /// `split`, `mix` or a channel.
+    Routing,
+    /// A parameter name or `state`.
+    Query,
+    /// A preset name, or a file path when it contains a `/`.
+    Preset,
+    /// An output device name, which may contain spaces.
+    Device,
+    /// A file path.
+    Path,
+    /// A duration in seconds, e.g. `500ms` or `2s`.
+    Duration,
+    /// One of the given words.
+    Keyword(&'static [&'static str]),
+}
+
+/// Description of a command for parsing, completion and hints.
+#[derive(Debug, PartialEq)]
+pub struct CommandInfo {
+    /// Command name followed by its aliases.
+    pub names: &'static [&'static str],
+    pub usage: &'static str,
+    pub argument: Argument,
+    pub description: &'static str,
+}
+
+/// Names of the views, in the order the `v` key cycles through them.
+pub const VIEWS: [&str; 4] = ["preview", "scope", "spectrum", "xy"];
+
+/// Settings of the `scope` command.
+const SCOPE_SETTINGS: [&str; 7] = [
+    "time", "volts", "trace", "trigger", "slope", "level", "source",
+];
+
+/// Settings of the `spectrum` command.
+const SPECTRUM_SETTINGS: [&str; 6] = ["size", "window", "axis", "average", "hold", "source"];
+
+/// Settings of the `xy` command.
+const XY_SETTINGS: [&str; 2] = ["channels", "persistence"];
+
+/// Settings of the `preview` command.
+const PREVIEW_SETTINGS: [&str; 4] = ["periods", "zoom", "pan", "reset"];
+
+pub const COMMANDS: [CommandInfo; 31] = [
+    CommandInfo {
+        names: &["freq", "f", "frequency"],
+        usage: "<frequency>",
+        argument: Argument::Value,
+        description: "Frequency in Hz, e.g. 440 or 1.5k",
+    },
+    CommandInfo {
+        names: &["amp", "a", "amplitude"],
+        usage: "<amplitude>",
+        argument: Argument::Value,
+        description: "Amplitude from 0 to 1, e.g. 0.5, 500m or -6dBFS",
+    },
+    CommandInfo {
+        names: &["wave", "w", "waveform"],
+        usage: "<sine|square|triangle|sawtooth|noise>",
+        argument: Argument::Waveform,
+        description: "Waveform by name or number",
+    },
+    CommandInfo {
+        names: &["phase", "p"],
+        usage: "<offset>",
+        argument: Argument::Value,
+        description: "Phase offset",
+    },
+    CommandInfo {
+        names: &["dc", "offset"],
+        usage: "<offset>",
+        argument: Argument::Value,
+        description: "DC offset",
+    },
+    CommandInfo {
+        names: &["pan"],
+        usage: "<pan>",
+        argument: Argument::Value,
+        description: "Pan in the mix, from -1 (left) to 1 (right)",
+    },
+    CommandInfo {
+        names: &["vol", "v", "volume"],
+        usage: "<volume>",
+        argument: Argument::Value,
+        description: "Master volume from 0 to 1, or in dBFS",
+    },
+    CommandInfo {
+        names: &["mute", "m"],
+        usage: "[on|off]",
+        argument: Argument::State,
+        description: "Mute the output, toggles without argument",
+    },
+    CommandInfo {
+        names: &["out", "o", "output"],
+        usage: "[on|off]",
+        argument: Argument::State,
+        description: "Enable the channel output, toggles without argument",
+    },
+    CommandInfo {
+        names: &["routing", "route"],
+        usage: "<split|mix|channel>",
+        argument: Argument::Routing,
+        description: "Route the channels to the outputs, or one channel to all of them",
+    },
+    CommandInfo {
+        names: &["get"],
+        usage: "<parameter|state>",
+        argument: Argument::Query,
+        description: "Report a value, also written as freq? or ch2:amp?",
+    },
+    CommandInfo {
+        names: &["ch", "channel"],
+        usage: "<channel> [command]",
+        argument: Argument::Channel,
+        description: "Run a command on a channel, selects it without command",
+    },
+    CommandInfo {
+        names: &["save"],
+        usage: "<preset>",
+        argument: Argument::Preset,
+        description: "Save the instrument state as a preset",
+    },
+    CommandInfo {
+        names: &["load"],
+        usage: "<preset>",
+        argument: Argument::Preset,
+        description: "Load a preset",
+    },
+    CommandInfo {
+        names: &["presets"],
+        usage: "",
+        argument: Argument::None,
+        description: "Pick a preset from the saved ones",
+    },
+    CommandInfo {
+        names: &["device"],
+        usage: "[name]",
+        argument: Argument::Device,
+        description: "List the output devices matching a name, or show the current one",
+    },
+    CommandInfo {
+        names: &["source"],
+        usage: "<file>",
+        argument: Argument::Path,
+        description: "Run a script, Esc aborts it",
+    },
+    CommandInfo {
+        names: &["wait"],
+        usage: "<time>",
+        argument: Argument::Duration,
+        description: "Pause a script, e.g. 500ms or 2s",
+    },
+    CommandInfo {
+        names: &["learn"],
+        usage: "[on|off]",
+        argument: Argument::State,
+        description: "Map the next MIDI controller moved to the selected parameter",
+    },
+    CommandInfo {
+        names: &["view"],
+        usage: "<preview|scope|spectrum|xy>",
+        argument: Argument::Keyword(&VIEWS),
+        description: "Show the waveform preview, the oscilloscope, the spectrum or the XY display",
+    },
+    CommandInfo {
+        names: &["clip"],
+        usage: "",
+        argument: Argument::None,
+        description: "Reset the clip indicators of the level meters",
+    },
+    CommandInfo {
+        names: &["preview"],
+        usage: "<periods|zoom|pan|reset> <value>",
+        argument: Argument::Keyword(&PREVIEW_SETTINGS),
+        description: "Preview time window, e.g. periods 4, zoom 2, pan 1ms",
+    },
+    CommandInfo {
+        names: &["scope"],
+        usage: "<time|volts|trace|trigger|slope|level|source> <value>",
+        argument: Argument::Keyword(&SCOPE_SETTINGS),
+        description: "Oscilloscope setting, e.g. time 1ms, volts 0.5, trace 2 off, trigger single",
+    },
+    CommandInfo {
+        names: &["spectrum"],
+        usage: "<size|window|axis|average|hold|source> <value>",
+        argument: Argument::Keyword(&SPECTRUM_SETTINGS),
+        description: "Spectrum setting, e.g. size 8192, window flattop, axis linear, hold on",
+    },
+    CommandInfo {
+        names: &["xy"],
+        usage: "<channels|persistence> <value>",
+        argument: Argument::Keyword(&XY_SETTINGS),
+        description: "XY display setting, e.g. channels a c or persistence 16",
+    },
+    CommandInfo {
+        names: &["alias"],
+        usage: "<name> <command>",
+        argument: Argument::None,
+        description: "Define an alias, its arguments are appended to the command",
+    },
+    CommandInfo {
+        names: &["macro"],
+        usage: "<name> <commands>",
+        argument: Argument::None,
+        description: "Define a macro, its arguments replace $1, $2... and $*",
+    },
+    CommandInfo {
+        names: &["unalias"],
+        usage: "<name>",
+        argument: Argument::None,
+        description: "Remove an alias or macro",
+    },
+    CommandInfo {
+        names: &["aliases"],
+        usage: "",
+        argument: Argument::None,
+        description: "List the aliases and macros",
+    },
+    CommandInfo {
+        names: &["help", "h"],
+        usage: "",
+        argument: Argument::None,
+        description: "Show the help",
+    },
+    CommandInfo {
+        names: &["quit", "q", "exit"],
+        usage: "",
+        argument: Argument::None,
+        description: "Quit the application",
+    },
+];
+
+/// Find a command by name or alias.
+pub fn find_command(name: &str) -> Option<&'static CommandInfo> {
+    COMMANDS
+        .iter()
+        .find(|x| x.names.iter().any(|x| x.eq_ignore_ascii_case(name)))
+}
+
+/// View named in `VIEWS`, like `scope`.
+pub fn parse_view(name: &str) -> Option<View> {
+    match name.to_lowercase().as_str() {
+        "preview" => Some(View::Preview),
+        "scope" => Some(View::Scope),
+        "spectrum" => Some(View::Spectrum),
+        "xy" => Some(View::Xy),
+        _ => None,
+    }
+}
+
+/// Parameter set by a command, like `freq` or `frequency`.
+pub fn command_parameter(name: &str) -> Option<Parameter> {
+    match find_command(name)?.names[0] {
+        "wave" => Some(Parameter::Waveform),
+        "freq" => Some(Parameter::Frequency),
+        "amp" => Some(Parameter::Amplitude),
+        "phase" => Some(Parameter::PhaseOffset),
+        "dc" => Some(Parameter::DcOffset),
+        "pan" => Some(Parameter::Pan),
+        "out" => Some(Parameter::Output),
+        "routing" => Some(Parameter::Routing),
+        "vol" => Some(Parameter::Volume),
+        "mute" => Some(Parameter::Mute),
+        _ => None,
+    }
+}
+
+/// Names accepted by `get`.
+pub fn query_names() -> Vec<&'static str> {
+    let parameters = COMMANDS
+        .iter()
+        .map(|x| x.names[0])
+        .filter(|x| command_parameter(x).is_some());
+    std::iter::once("state").chain(parameters).collect()
+}
+
+/// Name of a parameter in commands and query replies.
+pub fn parameter_name(parameter: Parameter) -> &'static str {
+    match parameter {
+        Parameter::Waveform => "wave",
+        Parameter::Frequency => "freq",
+        Parameter::Amplitude => "amp",
+        Parameter::PhaseOffset => "phase",
+        Parameter::DcOffset => "dc",
+        Parameter::Pan => "pan",
+        Parameter::Output => "out",
+        Parameter::Routing => "routing",
+        Parameter::Volume => "vol",
+        Parameter::Mute => "mute",
+    }
+}
+
+/// Parameter value as accepted by the commands, like `square`, `on` or `1000`.
+pub fn format_value(parameter: Parameter, value: f32) -> String {
+    match parameter {
+        Parameter::Waveform => WAVEFORMS
+            .get((value as usize).wrapping_sub(1))
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| value.to_string()),
+        Parameter::Output | Parameter::Mute => match value > 0.0 {
+            true => "on".to_string(),
+            false => "off".to_string(),
+        },
+        Parameter::Routing => match value as usize {
+            0 => "split".to_string(),
+            1 => "mix".to_string(),
+            n => format!("ch{}", n - 1),
+        },
+        _ => value.to_string(),
+    }
+}
+
+/// Maximum depth of aliases and macros expanding to other aliases and macros.
+const MAX_EXPANSION_DEPTH: usize = 8;
+
+/// User-defined commands, expanded before parsing.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Macros {
+    /// Command of each alias, e.g. `sq = "wave square"`.
+    pub aliases: BTreeMap<String, String>,
+    /// Commands of each macro, e.g. `cal = "ch1:amp $1; ch2:amp $1"`.
+    pub macros: BTreeMap<String, String>,
+}
+
+impl Macros {
+    pub fn define_alias(&mut self, name: &str, definition: &str) -> Result<(), String> {
+        let name = Self::check_name(name)?;
+        self.macros.remove(&name);
+        self.aliases.insert(name, definition.to_string());
+        Ok(())
+    }
+
+    pub fn define_macro(&mut self, name: &str, definition: &str) -> Result<(), String> {
+        let name = Self::check_name(name)?;
+        self.aliases.remove(&name);
+        self.macros.insert(name, definition.to_string());
+        Ok(())
+    }
+
+    /// Remove an alias or macro, returning whether it existed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.aliases.remove(&name).is_some() | self.macros.remove(&name).is_some()
+    }
+
+    /// Names of the aliases and macros.
+    pub fn names(&self) -> Vec<String> {
+        self.aliases
+            .keys()
+            .chain(self.macros.keys())
+            .cloned()
+            .collect()
+    }
+
+    /// One line per alias and macro, for listing.
+    pub fn describe(&self) -> Vec<String> {
+        let aliases = self
+            .aliases
+            .iter()
+            .map(|(name, definition)| format!("alias {} = {}", name, definition));
+        let macros = self
+            .macros
+            .iter()
+            .map(|(name, definition)| format!("macro {} = {}", name, definition));
+        aliases.chain(macros).collect()
+    }
+
+    fn check_name(name: &str) -> Result<String, String> {
+        let valid = name
+            .chars()
+            .all(|x| x.is_alphanumeric() || x == '_' || x == '-');
+        if name.is_empty() || !valid {
+            return Err(format!("Invalid name '{}'", name));
+        }
+        if find_command(name).is_some() || name.eq_ignore_ascii_case("get") {
+            return Err(format!("'{}' is a built-in command", name));
+        }
+        Ok(name.to_lowercase())
+    }
+
+    /// Command line an alias or macro call expands to, `None` for other commands.
+    fn expand(&self, tokens: &[Token]) -> Option<Result<String, ParseError>> {
+        let name = tokens.first().filter(|x| !x.quoted)?;
+        let arguments = &tokens[1..];
+        let key = name.text.to_lowercase();
+        if let Some(definition) = self.aliases.get(&key) {
+            return Some(Ok(format!("{} {}", definition, join_tokens(arguments))));
+        }
+        let definition = self.macros.get(&key)?;
+
+        let mut expanded = String::new();
+        let mut used = 0;
+        let mut chars = definition.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('$', Some('*')) => {
+                    chars.next();
+                    expanded.push_str(&join_tokens(arguments));
+                    used = arguments.len();
+                }
+                ('$', Some(digit)) if digit.is_ascii_digit() => {
+                    let index = digit.to_digit(10).unwrap_or(0) as usize;
+                    chars.next();
+                    match index.checked_sub(1).and_then(|x| arguments.get(x)) {
+                        Some(argument) => {
+                            expanded.push_str(&join_tokens(std::slice::from_ref(argument)))
+                        }
+                        None => {
+                            return Some(Err(name.error(&format!("missing argument ${}", index))))
+                        }
+                    }
+                    used = used.max(index);
+                }
+                _ => expanded.push(c),
+            }
+        }
+        if let Some(extra) = arguments.get(used) {
+            return Some(Err(extra.error("unexpected argument")));
+        }
+        Some(Ok(expanded))
+    }
+}
+
+/// Join tokens back into command text, quoting the ones that need it.
+fn join_tokens(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|x| {
+            if x.text.is_empty() || x.text.contains(|c: char| c.is_whitespace() || c == ';') {
+                format!("\"{}\"", x.text)
+            } else {
+                x.text.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parse a channel by number or letter, returning its index.
+pub fn parse_channel(channel: &str) -> Option<usize> {
+    match channel.parse::<usize>() {
+        Ok(number) => number.checked_sub(1),
+        Err(_) => {
+            let mut chars = channel.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphabetic() => {
+                    Some((c.to_ascii_lowercase() as u8 - b'a') as usize)
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    text: String,
+    position: usize,
+    quoted: bool,
+}
+
+impl Token {
+    fn error(&self, reason: &str) -> ParseError {
+        ParseError {
+            token: self.text.clone(),
+            position: self.position,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// Split a line into the tokens of each `;` separated command. Quotes group words and keep
+/// `;` literal.
+fn tokenize(line: &str) -> Result<Vec<Vec<Token>>, ParseError> {
+    let mut commands: Vec<Vec<Token>> = vec![Vec::new()];
+    let mut token: Option<Token> = None;
+    let mut quote: Option<(char, usize)> = None;
+
+    for (position, c) in line.chars().enumerate() {
+        if let Some((q, _)) = quote {
+            if c == q {
+                quote = None;
+            } else if let Some(token) = &mut token {
+                token.text.push(c);
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => {
+                quote = Some((c, position));
+                token.get_or_insert(Token {
+                    text: String::new(),
+                    position,
+                    quoted: true,
+                });
+            }
+            ';' => {
+                commands.last_mut().unwrap().extend(token.take());
+                commands.push(Vec::new());
+            }
+            _ if c.is_whitespace() => commands.last_mut().unwrap().extend(token.take()),
+            _ => token
+                .get_or_insert(Token {
+                    text: String::new(),
+                    position,
+                    quoted: false,
+                })
+                .text
+                .push(c),
+        }
+    }
+    if let Some((q, position)) = quote {
+        return Err(ParseError {
+            token: line.chars().skip(position).collect(),
+            position,
+            reason: format!("missing closing {}", q),
+        });
+    }
+    commands.last_mut().unwrap().extend(token.take());
+    Ok(commands)
+}
+
+/// Parse a command line into its commands. Nothing is returned if any of them is invalid.
+pub fn parse(line: &str) -> Result<Vec<Command>, ParseError> {
+    parse_with(line, &Macros::default())
+}
+
+/// Parse a command line, expanding the aliases and macros.
+pub fn parse_with(line: &str, macros: &Macros) -> Result<Vec<Command>, ParseError> {
+    parse_line(line, macros, 0)
+}
+
+fn parse_line(line: &str, macros: &Macros, depth: usize) -> Result<Vec<Command>, ParseError> {
+    let mut commands = Vec::new();
+    for tokens in tokenize(line)? {
+        match macros.expand(&tokens) {
+            Some(expanded) => {
+                let name = &tokens[0];
+                if depth >= MAX_EXPANSION_DEPTH {
+                    return Err(name.error("too many nested aliases"));
+                }
+                // errors inside the expansion are located at the alias name
+                let expanded =
+                    parse_line(&expanded?, macros, depth + 1).map_err(|error| ParseError {
+                        position: name.position,
+                        reason: format!("{} (in {})", error.reason, name.text),
+                        ..error
+                    })?;
+                commands.extend(expanded);
+            }
+            None => commands.extend(parse_tokens(tokens)?),
+        }
+    }
+    Ok(commands)
+}
+
+fn parse_tokens(mut tokens: Vec<Token>) -> Result<Option<Command>, ParseError> {
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+
+    // channel prefix: `ch 2 freq 1k`, `ch2 freq 1k` or `ch2:freq 1k`
+    let mut channel = None;
+    if !tokens[0].quoted {
+        let first = tokens[0].clone();
+        let (prefix, rest) = match first.text.split_once(':') {
+            Some((prefix, rest)) => (prefix, Some(rest)),
+            None => (first.text.as_str(), None),
+        };
+        let lower = prefix.to_lowercase();
+        let number = lower
+            .strip_prefix("channel")
+            .or_else(|| lower.strip_prefix("ch"))
+            .filter(|x| !x.is_empty() && x.chars().all(|x| x.is_ascii_digit()));
+        if lower == "ch" || lower == "channel" || number.is_some() {
+            tokens.remove(0);
+            let value = match number {
+                Some(number) => Token {
+                    text: number.to_string(),
+                    ..first.clone()
+                },
+                None if rest.is_none() && !tokens.is_empty() => tokens.remove(0),
+                None => return Err(first.error("missing channel")),
+            };
+            match parse_channel(&value.text) {
+                Some(index) => channel = Some(index),
+                None => return Err(value.error("invalid channel")),
+            }
+            if let Some(rest) = rest.filter(|x| !x.is_empty()) {
+                let offset = prefix.chars().count() + 1;
+                tokens.insert(
+                    0,
+                    Token {
+                        text: rest.to_string(),
+                        position: first.position + offset,
+                        quoted: false,
+                    },
+                );
+            }
+        }
+    }
+
+    let Some(name) = tokens.first().cloned() else {
+        return Ok(channel.map(Command::SelectChannel));
+    };
+    let arguments = &tokens[1..];
+
+    // queries: `get freq` or `freq?`
+    let query = match name.text.strip_suffix('?') {
+        Some(text) if !name.quoted => Some(Token {
+            text: text.to_string(),
+            ..name.clone()
+        }),
+        _ if name.text.eq_ignore_ascii_case("get") => {
+            Some(argument(&name, arguments, "parameter")?.clone())
+        }
+        _ => None,
+    };
+    if let Some(query) = query {
+        if let (true, Some(extra)) = (name.text.ends_with('?'), arguments.first()) {
+            return Err(extra.error("unexpected argument"));
+        }
+        let parameter = match query.text.to_lowercase().as_str() {
+            "state" => None,
+            text => match command_parameter(text) {
+                Some(parameter) => Some(parameter),
+                None => return Err(query.error("unknown parameter")),
+            },
+        };
+        if channel.is_some() && parameter.is_some_and(|x| x.is_output()) {
+            return Err(query.error("does not apply to a channel"));
+        }
+        return Ok(Some(Command::Get { channel, parameter }));
+    }
+
+    let Some(info) = find_command(&name.text).filter(|_| !name.quoted) else {
+        return Err(name.error("unknown command"));
+    };
+
+    let command = match info.names[0] {
+        "freq" => {
+            let value = parse_number(argument(&name, arguments, "frequency")?)?;
+            if value <= 0.0 {
+                return Err(arguments[0].error("frequency must be positive"));
+            }
+            Command::Set {
+                channel,
+                parameter: Parameter::Frequency,
+                value,
+            }
+        }
+        "amp" => Command::Set {
+            channel,
+            parameter: Parameter::Amplitude,
+            value: parse_level_token(argument(&name, arguments, "amplitude")?)?,
+        },
+        "wave" => {
+            let token = argument(&name, arguments, "waveform")?;
+            match parse_waveform(&token.text) {
+                Some(value) => Command::Set {
+                    channel,
+                    parameter: Parameter::Waveform,
+                    value,
+                },
+                None => return Err(token.error("unknown waveform")),
+            }
+        }
+        "phase" => Command::Set {
+            channel,
+            parameter: Parameter::PhaseOffset,
+            value: parse_number(argument(&name, arguments, "phase offset")?)?,
+        },
+        "dc" => Command::Set {
+            channel,
+            parameter: Parameter::DcOffset,
+            value: parse_number(argument(&name, arguments, "DC offset")?)?,
+        },
+        "pan" => Command::Set {
+            channel,
+            parameter: Parameter::Pan,
+            value: parse_number(argument(&name, arguments, "pan")?)?,
+        },
+        "routing" => Command::Set {
+            channel,
+            parameter: Parameter::Routing,
+            value: parse_routing(argument(&name, arguments, "routing")?)?,
+        },
+        "vol" => Command::Set {
+            channel,
+            parameter: Parameter::Volume,
+            value: parse_level_token(argument(&name, arguments, "volume")?)?,
+        },
+        "mute" | "out" => {
+            let parameter = match info.names[0] {
+                "mute" => Parameter::Mute,
+                _ => Parameter::Output,
+            };
+            match optional_argument(arguments)? {
+                None => Command::Toggle { channel, parameter },
+                Some(token) => Command::Set {
+                    channel,
+                    parameter,
+                    value: parse_state(token)?,
+                },
+            }
+        }
+        "save" => Command::SavePreset(argument(&name, arguments, "preset name")?.text.clone()),
+        "load" => Command::LoadPreset(argument(&name, arguments, "preset name")?.text.clone()),
+        "presets" => no_arguments(arguments, Command::Presets)?,
+        // device names may contain spaces, the remaining words are joined
+        "device" => match arguments.is_empty() {
+            true => Command::Device(None),
+            false => Command::Device(Some(
+                arguments
+                    .iter()
+                    .map(|x| x.text.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )),
+        },
+        "source" => Command::Source(argument(&name, arguments, "file")?.text.clone()),
+        "wait" => Command::Wait(parse_duration(argument(&name, arguments, "time")?)?),
+        "learn" => Command::Learn(
+            optional_argument(arguments)?
+                .map(parse_state)
+                .transpose()?
+                .map(|x| x > 0.0),
+        ),
+        "view" => {
+            let token = argument(&name, arguments, "view")?;
+            match parse_view(&token.text) {
+                Some(view) => Command::View(view),
+                None => return Err(token.error("unknown view")),
+            }
+        }
+        "scope" => Command::Scope(parse_scope_setting(&name, arguments)?),
+        "spectrum" => Command::Spectrum(parse_spectrum_setting(&name, arguments)?),
+        "clip" => no_arguments(arguments, Command::ResetClips)?,
+        "preview" => Command::Preview(parse_preview_setting(&name, arguments)?),
+        "xy" => Command::Xy(parse_xy_setting(&name, arguments)?),
+        "alias" | "macro" => {
+            let Some((alias, definition)) = arguments.split_first() else {
+                return Err(name.error("missing name"));
+            };
+            if definition.is_empty() {
+                return Err(alias.error("missing definition"));
+            }
+            // a single quoted definition is taken as is, so it can hold `;`
+            let definition = match definition {
+                [token] => token.text.clone(),
+                _ => join_tokens(definition),
+            };
+            let name = alias.text.clone();
+            match info.names[0] {
+                "alias" => Command::Alias { name, definition },
+                _ => Command::Macro { name, definition },
+            }
+        }
+        "unalias" => Command::Unalias(argument(&name, arguments, "name")?.text.clone()),
+        "aliases" => no_arguments(arguments, Command::Aliases)?,
+        "help" => no_arguments(arguments, Command::Help)?,
+        "quit" => no_arguments(arguments, Command::Quit)?,
+        _ => return Err(name.error("unknown command")),
+    };
+
+    // the channel prefix only applies to channel parameters
+    let channel_command = match &command {
+        Command::Set { parameter, .. } | Command::Toggle { parameter, .. } => {
+            !parameter.is_output()
+        }
+        _ => false,
+    };
+    if channel.is_some() && !channel_command {
+        return Err(name.error("does not apply to a channel"));
+    }
+    Ok(Some(command))
+}
+
+/// The single argument of a command.
+fn argument<'a>(name: &Token, arguments: &'a [Token], what: &str) -> Result<&'a Token, ParseError> {
+    match optional_argument(arguments)? {
+        Some(token) => Ok(token),
+        None => Err(name.error(&format!("missing {}", what))),
+    }
+}
+
+fn optional_argument(arguments: &[Token]) -> Result<Option<&Token>, ParseError> {
+    match arguments.get(1) {
+        Some(extra) => Err(extra.error("unexpected argument")),
+        None => Ok(arguments.first()),
+    }
+}
+
+fn no_arguments(arguments: &[Token], command: Command) -> Result<Command, ParseError> {
+    match arguments.first() {
+        Some(extra) => Err(extra.error("unexpected argument")),
+        None => Ok(command),
+    }
+}
+
+/// Setting of the `scope` command, like `time 1ms` or `trace 2 off`.
+fn parse_scope_setting(name: &Token, arguments: &[Token]) -> Result<ScopeSetting, ParseError> {
+    let Some((setting, values)) = arguments.split_first() else {
+        return Err(name.error("missing setting"));
+    };
+    let setting = match setting.text.to_lowercase().as_str() {
+        "trace" => {
+            let Some((channel, state)) = values.split_first() else {
+                return Err(setting.error("missing output channel"));
+            };
+            let state = optional_argument(state)?.map(parse_state).transpose()?;
+            return Ok(ScopeSetting::Trace(
+                parse_output(channel)?,
+                state.map(|x| x > 0.0),
+            ));
+        }
+        "time" => ScopeSetting::Timebase(
+            parse_duration(argument(setting, values, "time per division")?)?.as_secs_f32(),
+        ),
+        "volts" => ScopeSetting::Scale(parse_number(argument(
+            setting,
+            values,
+            "volts per division",
+        )?)?),
+        "trigger" => {
+            let token = argument(setting, values, "trigger mode")?;
+            ScopeSetting::Mode(match token.text.to_lowercase().as_str() {
+                "auto" => TriggerMode::Auto,
+                "normal" => TriggerMode::Normal,
+                "single" => TriggerMode::Single,
+                _ => return Err(token.error("expected auto, normal or single")),
+            })
+        }
+        "slope" => {
+            let token = argument(setting, values, "slope")?;
+            ScopeSetting::Slope(match token.text.to_lowercase().as_str() {
+                "rising" | "up" => Slope::Rising,
+                "falling" | "down" => Slope::Falling,
+                _ => return Err(token.error("expected rising or falling")),
+            })
+        }
+        "level" => ScopeSetting::Level(parse_level_token(argument(
+            setting,
+            values,
+            "trigger level",
+        )?)?),
+        "source" => {
+            ScopeSetting::Source(parse_output(argument(setting, values, "output channel")?)?)
+        }
+        _ => return Err(setting.error("unknown scope setting")),
+    };
+    Ok(setting)
+}
+
+/// Setting of the `spectrum` command, like `size 8192` or `window flattop`.
+fn parse_spectrum_setting(
+    name: &Token,
+    arguments: &[Token],
+) -> Result<SpectrumSetting, ParseError> {
+    let Some((setting, values)) = arguments.split_first() else {
+        return Err(name.error("missing setting"));
+    };
+    let setting = match setting.text.to_lowercase().as_str() {
+        "size" => {
+            let token = argument(setting, values, "FFT size")?;
+            match token.text.parse::<usize>() {
+                Ok(size) => SpectrumSetting::Size(size),
+                Err(_) => return Err(token.error("invalid FFT size")),
+            }
+        }
+        "window" => {
+            let token = argument(setting, values, "window")?;
+            SpectrumSetting::Window(match token.text.to_lowercase().as_str() {
+                "hann" => Window::Hann,
+                "blackman" | "blackman-harris" => Window::BlackmanHarris,
+                "flattop" | "flat-top" => Window::FlatTop,
+                _ => return Err(token.error("expected hann, blackman or flattop")),
+            })
+        }
+        "axis" => {
+            let token = argument(setting, values, "axis")?;
+            SpectrumSetting::Log(match token.text.to_lowercase().as_str() {
+                "log" => true,
+                "linear" | "lin" => false,
+                _ => return Err(token.error("expected log or linear")),
+            })
+        }
+        "average" => {
+            let token = argument(setting, values, "number of spectra")?;
+            match token.text.parse::<usize>() {
+                Ok(average) if average > 0 => SpectrumSetting::Average(average),
+                _ => return Err(token.error("invalid number of spectra")),
+            }
+        }
+        "hold" => SpectrumSetting::Hold(
+            optional_argument(values)?
+                .map(parse_state)
+                .transpose()?
+                .map(|x| x > 0.0),
+        ),
+        "source" => {
+            SpectrumSetting::Source(parse_output(argument(setting, values, "output channel")?)?)
+        }
+        _ => return Err(setting.error("unknown spectrum setting")),
+    };
+    Ok(setting)
+}
+
+/// Setting of the `preview` command, like `periods 4` or `pan 1ms`.
+fn parse_preview_setting(name: &Token, arguments: &[Token]) -> Result<PreviewSetting, ParseError> {
+    let Some((setting, values)) = arguments.split_first() else {
+        return Err(name.error("missing setting"));
+    };
+    let setting = match setting.text.to_lowercase().as_str() {
+        "periods" => PreviewSetting::Periods(parse_number(argument(
+            setting,
+            values,
+            "number of periods",
+        )?)?),
+        "zoom" => PreviewSetting::Zoom(parse_number(argument(setting, values, "zoom")?)?),
+        "pan" => {
+            PreviewSetting::Pan(parse_duration(argument(setting, values, "time")?)?.as_secs_f32())
+        }
+        "reset" => match values.first() {
+            Some(extra) => return Err(extra.error("unexpected argument")),
+            None => PreviewSetting::Reset,
+        },
+        _ => return Err(setting.error("unknown preview setting")),
+    };
+    Ok(setting)
+}
+
+/// Setting of the `xy` command, like `channels a b` or `persistence 16`.
+fn parse_xy_setting(name: &Token, arguments: &[Token]) -> Result<XySetting, ParseError> {
+    let Some((setting, values)) = arguments.split_first() else {
+        return Err(name.error("missing setting"));
+    };
+    let channel = |token: &Token| {
+        let text = token.text.to_lowercase();
+        parse_channel(text.strip_prefix("ch").unwrap_or(&text))
+            .ok_or_else(|| token.error("invalid channel"))
+    };
+    let setting = match setting.text.to_lowercase().as_str() {
+        "channels" => match values {
+            [x, y] => XySetting::Channels(channel(x)?, channel(y)?),
+            [_, _, extra, ..] => return Err(extra.error("unexpected argument")),
+            _ => return Err(setting.error("expected the X and Y channels")),
+        },
+        "persistence" => {
+            let token = argument(setting, values, "number of frames")?;
+            match token.text.parse::<usize>() {
+                Ok(frames) if frames > 0 => XySetting::Persistence(frames),
+                _ => return Err(token.error("invalid number of frames")),
+            }
+        }
+        _ => return Err(setting.error("unknown xy setting")),
+    };
+    Ok(setting)
+}
+
+/// 1-based output channel of the device.
+fn parse_output(token: &Token) -> Result<usize, ParseError> {
+    match token
+        .text
+        .parse::<usize>()
+        .ok()
+        .and_then(|x| x.checked_sub(1))
+    {
+        Some(output) => Ok(output),
+        None => Err(token.error("invalid output channel")),
+    }
+}
+
+fn parse_number(token: &Token) -> Result<f32, ParseError> {
+    parse_value(&token.text).ok_or_else(|| token.error("invalid number"))
+}
+
+fn parse_level_token(token: &Token) -> Result<f32, ParseError> {
+    parse_level(&token.text).ok_or_else(|| token.error("invalid level"))
+}
+
+fn parse_duration(token: &Token) -> Result<Duration, ParseError> {
+    parse_time(&token.text).ok_or_else(|| token.error("invalid time"))
+}
+
+/// Routing number of `split`, `mix` or a channel, as used by the `Routing` parameter.
+fn parse_routing(token: &Token) -> Result<f32, ParseError> {
+    let text = token.text.to_lowercase();
+    match text.as_str() {
+        "split" => Ok(0.0),
+        "mix" => Ok(1.0),
+        _ => match parse_channel(text.strip_prefix("ch").unwrap_or(&text)) {
+            Some(channel) => Ok(channel as f32 + 2.0),
+            None => Err(token.error("expected split, mix or a channel")),
+        },
+    }
+}
+
+fn parse_state(token: &Token) -> Result<f32, ParseError> {
+    match token.text.to_lowercase().as_str() {
+        "on" | "1" => Ok(1.0),
+        "off" | "0" => Ok(0.0),
+        _ => Err(token.error("expected on or off")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_parse() {
+        assert_eq!(parse("  ").unwrap(), vec![]);
+        assert_eq!(
+            parse("ch 2 freq 1k; CH3:Out OFF;mute").unwrap(),
+            vec![
+                Command::Set {
+                    channel: Some(1),
+                    parameter: Parameter::Frequency,
+                    value: 1000.0
+                },
+                Command::Set {
+                    channel: Some(2),
+                    parameter: Parameter::Output,
+                    value: 0.0
+                },
+                Command::Toggle {
+                    channel: None,
+                    parameter: Parameter::Mute
+                },
+            ]
+        );
+        assert_eq!(parse("ch b").unwrap(), vec![Command::SelectChannel(1)]);
+        assert_eq!(
+            parse("save \"My Preset; 2\"").unwrap(),
+            vec![Command::SavePreset("My Preset; 2".to_string())]
+        );
+        assert_eq!(
+            parse("load ./Presets/Bench.toml").unwrap(),
+            vec![Command::LoadPreset("./Presets/Bench.toml".to_string())]
+        );
+        assert_eq!(parse("learn").unwrap(), vec![Command::Learn(None)]);
+        assert_eq!(parse("learn off").unwrap(), vec![Command::Learn(Some(false))]);
+        assert_eq!(
+            parse("view scope; scope time 500us; scope trace 2 off").unwrap(),
+            vec![
+                Command::View(View::Scope),
+                Command::Scope(ScopeSetting::Timebase(500e-6)),
+                Command::Scope(ScopeSetting::Trace(1, Some(false))),
+            ]
+        );
+        assert_eq!(
+            parse("scope trigger single").unwrap(),
+            vec![Command::Scope(ScopeSetting::Mode(TriggerMode::Single))]
+        );
+        assert_eq!(parse("scope level -6dB").unwrap().len(), 1);
+        assert_eq!(parse("scope slope sideways").unwrap_err().token, "sideways");
+        assert_eq!(parse("scope source 0").unwrap_err().token, "0");
+        assert_eq!(
+            parse("spectrum window flattop; spectrum axis linear; spectrum hold").unwrap(),
+            vec![
+                Command::Spectrum(SpectrumSetting::Window(Window::FlatTop)),
+                Command::Spectrum(SpectrumSetting::Log(false)),
+                Command::Spectrum(SpectrumSetting::Hold(None)),
+            ]
+        );
+        assert_eq!(parse("spectrum average 0").unwrap_err().token, "0");
+        assert_eq!(
+            parse("view xy; xy channels b ch3").unwrap(),
+            vec![
+                Command::View(View::Xy),
+                Command::Xy(XySetting::Channels(1, 2))
+            ]
+        );
+        assert_eq!(parse("xy channels a").unwrap_err().token, "channels");
+        assert_eq!(
+            parse("preview periods 4; preview reset").unwrap(),
+            vec![
+                Command::Preview(PreviewSetting::Periods(4.0)),
+                Command::Preview(PreviewSetting::Reset)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_errors() {
+        let error = parse("freq 1k; amp loud").unwrap_err();
+        assert_eq!((error.token.as_str(), error.position), ("loud", 13));
+        assert_eq!(error.to_string(), "'loud': invalid level");
+        assert_eq!(parse("freq 1k 2k").unwrap_err().token, "2k");
+        assert_eq!(parse("wave").unwrap_err().reason, "missing waveform");
+        assert_eq!(parse("frq 1k").unwrap_err().reason, "unknown command");
+        assert_eq!(parse("ch xy freq 1").unwrap_err().token, "xy");
+        assert_eq!(parse("ch 2 vol 1").unwrap_err().token, "vol");
+        assert_eq!(parse("save 'name").unwrap_err().reason, "missing closing '");
+        assert_eq!(parse("ch2:vol?").unwrap_err().token, "vol");
+        assert_eq!(parse("get speed").unwrap_err().reason, "unknown parameter");
+    }
+
+    #[test]
+    fn test_command_macros() {
+        let mut macros = Macros::default();
+        macros.define_alias("sq", "wave square").unwrap();
+        macros.define_alias("f2", "ch2:freq").unwrap();
+        macros
+            .define_macro("both", "ch1:amp $1; ch2:amp $1; $2")
+            .unwrap();
+        assert!(macros.define_alias("freq", "amp 1").is_err());
+
+        let amplitude = |channel| Command::Set {
+            channel: Some(channel),
+            parameter: Parameter::Amplitude,
+            value: 0.5,
+        };
+        assert_eq!(
+            parse_with("both 500m mute; f2 1k", &macros).unwrap(),
+            vec![
+                amplitude(0),
+                amplitude(1),
+                Command::Toggle {
+                    channel: None,
+                    parameter: Parameter::Mute
+                },
+                Command::Set {
+                    channel: Some(1),
+                    parameter: Parameter::Frequency,
+                    value: 1000.0
+                },
+            ]
+        );
+        assert_eq!(
+            parse_with("both 1", &macros).unwrap_err().reason,
+            "missing argument $2"
+        );
+        assert_eq!(parse_with("both 1 mute x", &macros).unwrap_err().token, "x");
+        let error = parse_with("f2 fast", &macros).unwrap_err();
+        assert_eq!(error.to_string(), "'fast': invalid number (in f2)");
+
+        macros.define_alias("loop", "loop").unwrap();
+        assert!(parse_with("loop", &macros).is_err());
+        assert_eq!(
+            parse("macro cal 'amp $1; freq 1k'").unwrap(),
+            vec![Command::Macro {
+                name: "cal".to_string(),
+                definition: "amp $1; freq 1k".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_command_query() {
+        assert_eq!(
+            parse("freq?; ch2:amp?; get state; routing ch2").unwrap(),
+            vec![
+                Command::Get {
+                    channel: None,
+                    parameter: Some(Parameter::Frequency)
+                },
+                Command::Get {
+                    channel: Some(1),
+                    parameter: Some(Parameter::Amplitude)
+                },
+                Command::Get {
+                    channel: None,
+                    parameter: None
+                },
+                Command::Set {
+                    channel: None,
+                    parameter: Parameter::Routing,
+                    value: 3.0
+                },
+            ]
+        );
+        assert_eq!(
+            parse("wait 250ms; wait 2").unwrap(),
+            vec![
+                Command::Wait(Duration::from_millis(250)),
+                Command::Wait(Duration::from_secs(2))
+            ]
+        );
+        assert_eq!(
+            parse("wait 2min; wait 50us").unwrap(),
+            vec![
+                Command::Wait(Duration::from_secs(120)),
+                Command::Wait(Duration::from_micros(50))
+            ]
+        );
+        assert_eq!(parse("wait 1m").unwrap_err().reason, "invalid time");
+        assert_eq!(format_value(Parameter::Waveform, 2.0), "square");
+        assert_eq!(format_value(Parameter::Routing, 3.0), "ch2");
+    }
+}
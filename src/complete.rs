@@ -0,0 +1,166 @@
+use crate::command::{find_command, Argument, CommandInfo, COMMANDS};
+use std::path::Path;
+
+/// The word being typed at the end of a command line and what is expected there.
+#[derive(Debug, PartialEq)]
+pub struct Context<'a> {
+    pub argument: Argument,
+    /// Command the word is an argument of.
+    pub command: Option<&'static CommandInfo>,
+    /// Byte offset of the word in the line.
+    pub start: usize,
+    pub word: &'a str,
+}
+
+pub fn context(line: &str) -> Context<'_> {
+    // only the last of `;` separated commands is being typed
+    let offset = line.rfind(';').map(|x| x + 1).unwrap_or(0);
+    let mut words: Vec<(usize, &str)> = line[offset..]
+        .split_whitespace()
+        .map(|x| (x.as_ptr() as usize - line.as_ptr() as usize, x))
+        .collect();
+    let (start, word) = match words.is_empty() || line.ends_with(char::is_whitespace) {
+        true => (line.len(), ""),
+        false => words.pop().unwrap_or((0, "")),
+    };
+    let mut context = Context {
+        argument: Argument::None,
+        command: None,
+        start,
+        word,
+    };
+
+    // skip the channel prefix
+    if words
+        .first()
+        .is_some_and(|(_, x)| x.eq_ignore_ascii_case("ch") || x.eq_ignore_ascii_case("channel"))
+    {
+        if words.len() == 1 {
+            context.command = find_command("ch");
+            context.argument = Argument::Channel;
+            return context;
+        }
+        words.drain(..2);
+    }
+
+    let Some((command_start, name)) = words.first() else {
+        context.argument = Argument::Command;
+        return context;
+    };
+    context.command = find_command(name);
+    match context.command {
+        // device names may contain spaces, the argument is the rest of the line
+        Some(command) if command.argument == Argument::Device => {
+            let end = command_start + name.len();
+            context.start = end + (line.len() - end - line[end..].trim_start().len());
+            context.word = &line[context.start..];
+            context.argument = Argument::Device;
+        }
+        Some(command) if words.len() == 1 => context.argument = command.argument,
+        _ => {}
+    }
+    context
+}
+
+/// Candidates starting with `word`, ignoring case.
+pub fn matches(word: &str, candidates: &[String]) -> Vec<String> {
+    let word = word.to_lowercase();
+    candidates
+        .iter()
+        .filter(|x| x.to_lowercase().starts_with(&word))
+        .cloned()
+        .collect()
+}
+
+/// Longest prefix shared by all the words.
+pub fn common_prefix(words: &[String]) -> String {
+    let Some(first) = words.first() else {
+        return String::new();
+    };
+    let mut prefix = first.as_str();
+    for word in words.iter().skip(1) {
+        let length = prefix
+            .char_indices()
+            .zip(word.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((i, _), _)| i)
+            .unwrap_or(prefix.len().min(word.len()));
+        prefix = &prefix[..length];
+    }
+    prefix.to_string()
+}
+
+/// Files and directories starting with `word`, directories ending with `/`.
+pub fn paths(word: &str) -> Vec<String> {
+    let (directory, prefix) = match word.rfind('/') {
+        Some(i) => (&word[..=i], &word[i + 1..]),
+        None => ("", word),
+    };
+    let Ok(entries) = std::fs::read_dir(if directory.is_empty() {
+        Path::new(".")
+    } else {
+        Path::new(directory)
+    }) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<String> = entries
+        .filter_map(|x| x.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let separator = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", directory, name, separator))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Expected arguments and description of the command being typed.
+pub fn hint(line: &str) -> Option<String> {
+    let context = context(line);
+    if context.argument == Argument::Command {
+        let names: Vec<&str> = COMMANDS
+            .iter()
+            .map(|x| x.names[0])
+            .filter(|x| x.starts_with(&context.word.to_lowercase()))
+            .collect();
+        return Some(names.join(" "));
+    }
+    let command = context.command?;
+    let usage = [command.names[0], command.usage].join(" ");
+    Some(format!("{} - {}", usage.trim(), command.description))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameter::WAVEFORMS;
+
+    #[test]
+    fn test_complete_context() {
+        assert_eq!(context("").argument, Argument::Command);
+        assert_eq!(context("fr").word, "fr");
+        assert_eq!(context("ch ").argument, Argument::Channel);
+        let context_wave = context("ch 2 wave sq");
+        assert_eq!(context_wave.argument, Argument::Waveform);
+        assert_eq!((context_wave.start, context_wave.word), (10, "sq"));
+        assert_eq!(context("freq 1k ").argument, Argument::None);
+        assert_eq!(context("freq 1k;w").argument, Argument::Command);
+        let context_device = context("device  HDA Intel");
+        assert_eq!(context_device.argument, Argument::Device);
+        assert_eq!(context_device.word, "HDA Intel");
+    }
+
+    #[test]
+    fn test_complete_matches() {
+        let candidates: Vec<String> = WAVEFORMS.iter().map(|x| x.to_string()).collect();
+        let found = matches("S", &candidates);
+        assert_eq!(found, vec!["sine", "square", "sawtooth"]);
+        assert_eq!(common_prefix(&found), "s");
+        assert_eq!(common_prefix(&matches("sq", &candidates)), "square");
+        assert!(hint("freq ").unwrap().contains("Hz"));
+    }
+}
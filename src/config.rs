@@ -0,0 +1,634 @@
+use crate::command::{self, Macros};
+use crate::midi::{Control, Midi};
+use crate::parameter::{parse_value, parse_waveform_name, Parameter};
+use crate::preview::DEFAULT_PERIODS;
+use crate::target::{parse_target, Route};
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const DEFAULT_TICK_RATE: u64 = 125;
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// Configuration loaded from `config.toml`.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Number of waveform channels.
+    pub channels: Option<usize>,
+    /// Interval between UI ticks in milliseconds.
+    pub tick_rate: u64,
+    /// Number of commands kept in the command history.
+    pub history_size: usize,
+    /// Periods of the selected channel shown in the preview.
+    pub preview_periods: f32,
+    pub audio: AudioConfig,
+    pub remote: RemoteConfig,
+    pub osc: OscConfig,
+    pub midi: MidiConfig,
+    pub theme: ThemeConfig,
+    /// Commands bound to keys, e.g. `F1 = "wave sine"`.
+    pub keys: BTreeMap<String, String>,
+    /// Commands executed at startup.
+    pub startup: Vec<String>,
+    /// Aliases, whose arguments are appended to their command, e.g. `sq = "wave square"`.
+    pub aliases: BTreeMap<String, String>,
+    /// Macros, whose arguments replace `$1`, `$2`... and `$*` in their commands.
+    pub macros: BTreeMap<String, MacroConfig>,
+    /// Default parameters of each channel.
+    #[serde(rename = "channel")]
+    pub channel_defaults: Vec<ChannelConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            channels: None,
+            tick_rate: DEFAULT_TICK_RATE,
+            history_size: DEFAULT_HISTORY_SIZE,
+            preview_periods: DEFAULT_PERIODS,
+            audio: AudioConfig::default(),
+            remote: RemoteConfig::default(),
+            osc: OscConfig::default(),
+            midi: MidiConfig::default(),
+            theme: ThemeConfig::default(),
+            keys: BTreeMap::new(),
+            startup: Vec::new(),
+            aliases: BTreeMap::new(),
+            macros: BTreeMap::new(),
+            channel_defaults: Vec::new(),
+        }
+    }
+}
+
+/// Output device selection.
+#[derive(Default, Debug, Deserialize, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// Output device name, the default device is used when not set.
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    /// Buffer size in frames.
+    pub buffer_size: Option<u32>,
+}
+
+/// Remote control interfaces, all disabled when not set.
+#[derive(Default, Debug, Deserialize, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemoteConfig {
+    /// Address of the SCPI listener, like `127.0.0.1:5025`.
+    pub scpi: Option<String>,
+    /// Path of the JSON-RPC socket.
+    pub socket: Option<PathBuf>,
+    /// Address of the HTTP server and web panel, like `127.0.0.1:8080`, or a port.
+    pub http: Option<String>,
+}
+
+/// OSC server, disabled when `listen` is not set.
+#[derive(Default, Debug, Deserialize, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OscConfig {
+    /// UDP address like `0.0.0.0:9000`, or a port on the loopback interface.
+    pub listen: Option<String>,
+    /// Address the current values are sent to.
+    pub feedback: Option<String>,
+    /// Addresses controlling parameters, e.g. `"/1/fader1" = "ch1:amp"`.
+    pub map: BTreeMap<String, TargetConfig>,
+}
+
+/// MIDI input, disabled when `input` is not set.
+#[derive(Default, Debug, Deserialize, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MidiConfig {
+    /// Input port name or part of it, or `virtual` to create a port.
+    pub input: Option<String>,
+    /// Channel playing all the notes, otherwise MIDI channel n plays channel n.
+    pub channel: Option<usize>,
+    /// Frequency of A4 in Hz, 440 by default.
+    pub tuning: Option<f32>,
+    /// Semitones of a full pitch bend, 2 by default.
+    pub bend_range: Option<f32>,
+    /// Set the amplitude from the note velocity, on by default.
+    pub velocity: Option<bool>,
+    /// Turn the channel output on and off with the notes, on by default.
+    pub gate: Option<bool>,
+    /// Controllers setting parameters, e.g. `74 = "ch1:freq"`.
+    pub cc: BTreeMap<String, TargetConfig>,
+}
+
+/// Parameter controlled by an OSC address or a MIDI controller, like `ch1:amp` or `vol`,
+/// optionally scaling a 0 to 1 argument or a 0 to 127 controller to a range.
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum TargetConfig {
+    Parameter(String),
+    Scaled {
+        parameter: String,
+        min: f32,
+        max: f32,
+    },
+}
+
+/// Channel, parameter and range of a `TargetConfig`.
+type Target = (Option<usize>, Parameter, Option<(f32, f32)>);
+
+impl TargetConfig {
+    /// The channel of output parameters is dropped.
+    fn parse(&self) -> Result<Target> {
+        let (target, range) = match self {
+            TargetConfig::Parameter(target) => (target, None),
+            TargetConfig::Scaled {
+                parameter,
+                min,
+                max,
+            } => (parameter, Some((*min, *max))),
+        };
+        let (channel, parameter) =
+            parse_target(target).ok_or_else(|| eyre!("unknown parameter '{}'", target))?;
+        Ok((channel.filter(|_| !parameter.is_output()), parameter, range))
+    }
+}
+
+/// Colours used by the UI, as color names or `#rrggbb`.
+#[derive(Default, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    /// Colour of each channel tab and preview trace.
+    pub channels: Vec<String>,
+    /// Colour of the output tab.
+    pub output: Option<String>,
+}
+
+/// Default parameters of a channel, all optional.
+#[derive(Default, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelConfig {
+    pub waveform: Option<String>,
+    /// Frequency in Hz, with an optional unit suffix like `1k`.
+    pub frequency: Option<Value>,
+    pub amplitude: Option<Value>,
+    pub phase_offset: Option<Value>,
+    pub dc_offset: Option<Value>,
+    pub output: Option<bool>,
+}
+
+/// Commands of a macro, as a single command line or one per line.
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum MacroConfig {
+    Line(String),
+    Lines(Vec<String>),
+}
+
+/// A number, or a string with a unit suffix like `"1.5k"`.
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum Value {
+    Number(f32),
+    Text(String),
+}
+
+impl Value {
+    pub fn to_f32(&self) -> Option<f32> {
+        match self {
+            Value::Number(value) => Some(*value),
+            Value::Text(value) => parse_value(value),
+        }
+    }
+}
+
+/// Colours used by the UI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub channels: Vec<Color>,
+    pub output: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            channels: vec![
+                Color::Yellow,
+                Color::Cyan,
+                Color::Magenta,
+                Color::Green,
+                Color::LightRed,
+                Color::LightBlue,
+                Color::LightYellow,
+                Color::LightCyan,
+            ],
+            output: Color::White,
+        }
+    }
+}
+
+impl Theme {
+    /// Colour used for a channel tab and preview trace.
+    pub fn channel(&self, channel: usize) -> Color {
+        self.channels[channel % self.channels.len()]
+    }
+}
+
+/// A key bound to a command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+    pub command: String,
+}
+
+impl Config {
+    /// Default location of the configuration file.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|x| x.join(env!("CARGO_PKG_NAME")).join("config.toml"))
+    }
+
+    /// Load the configuration from `path`, or from the default location if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+        let text = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+        let config = Self::parse(&text)
+            .wrap_err_with(|| format!("Invalid config file {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Parse and validate the configuration.
+    pub fn parse(text: &str) -> Result<Self> {
+        let config: Config = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the values that can't be checked while deserializing, reporting all problems.
+    pub fn validate(&self) -> Result<()> {
+        let mut errors: Vec<String> = Vec::new();
+
+        if self.channels == Some(0) {
+            errors.push("channels must be at least 1".to_string());
+        }
+        if self.tick_rate == 0 {
+            errors.push("tick_rate must be at least 1 ms".to_string());
+        }
+        if self.preview_periods <= 0.0 {
+            errors.push("preview_periods must be positive".to_string());
+        }
+        if self.audio.sample_rate == Some(0) {
+            errors.push("audio.sample_rate must be positive".to_string());
+        }
+        if self.audio.buffer_size == Some(0) {
+            errors.push("audio.buffer_size must be positive".to_string());
+        }
+        if let Err(error) = self.theme() {
+            errors.push(error.to_string());
+        }
+        let macros = match self.macros() {
+            Ok(macros) => macros,
+            Err(error) => {
+                errors.push(error.to_string());
+                Macros::default()
+            }
+        };
+        if let Err(error) = self.osc_routes() {
+            errors.push(error.to_string());
+        }
+        if let Err(error) = self.midi() {
+            errors.push(error.to_string());
+        }
+        for (key, command) in self.keys.iter() {
+            if let Err(error) = parse_key(key) {
+                errors.push(error.to_string());
+            }
+            if let Err(error) = command::parse_with(command, &macros) {
+                errors.push(format!("keys: {}: {}", key, error));
+            }
+        }
+        for command in self.startup.iter() {
+            if let Err(error) = command::parse_with(command, &macros) {
+                errors.push(format!("startup: {}", error));
+            }
+        }
+        for (i, channel) in self.channel_defaults.iter().enumerate() {
+            let prefix = format!("channel {}", i + 1);
+            if let Some(waveform) = &channel.waveform {
+                if parse_waveform_name(waveform).is_none() {
+                    errors.push(format!("{}: unknown waveform '{}'", prefix, waveform));
+                }
+            }
+            let values = [
+                ("frequency", &channel.frequency),
+                ("amplitude", &channel.amplitude),
+                ("phase_offset", &channel.phase_offset),
+                ("dc_offset", &channel.dc_offset),
+            ];
+            for (name, value) in values {
+                if let Some(value) = value {
+                    if value.to_f32().is_none() {
+                        errors.push(format!("{}: invalid {} {:?}", prefix, name, value));
+                    }
+                }
+            }
+            if let Some(frequency) = channel.frequency.as_ref().and_then(|x| x.to_f32()) {
+                if frequency <= 0.0 {
+                    errors.push(format!("{}: frequency must be positive", prefix));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            bail!("{}", errors.join("\n"))
+        }
+    }
+
+    pub fn theme(&self) -> Result<Theme> {
+        let mut theme = Theme::default();
+        if !self.theme.channels.is_empty() {
+            theme.channels = self
+                .theme
+                .channels
+                .iter()
+                .map(|x| parse_color(x))
+                .collect::<Result<_>>()?;
+        }
+        if let Some(output) = &self.theme.output {
+            theme.output = parse_color(output)?;
+        }
+        Ok(theme)
+    }
+
+    pub fn macros(&self) -> Result<Macros> {
+        let mut macros = Macros::default();
+        for (name, definition) in self.aliases.iter() {
+            macros
+                .define_alias(name, definition)
+                .map_err(|error| eyre!("aliases: {}", error))?;
+        }
+        for (name, definition) in self.macros.iter() {
+            let definition = match definition {
+                MacroConfig::Line(line) => line.clone(),
+                MacroConfig::Lines(lines) => lines.join("; "),
+            };
+            macros
+                .define_macro(name, &definition)
+                .map_err(|error| eyre!("macros: {}", error))?;
+        }
+        Ok(macros)
+    }
+
+    pub fn osc_routes(&self) -> Result<Vec<Route>> {
+        self.osc
+            .map
+            .iter()
+            .map(|(address, target)| {
+                let (channel, parameter, range) = target
+                    .parse()
+                    .map_err(|error| eyre!("osc.map: {}: {}", address, error))?;
+                if !address.starts_with('/') {
+                    bail!("osc.map: invalid address '{}'", address);
+                }
+                Ok(Route {
+                    address: address.clone(),
+                    channel,
+                    parameter,
+                    range,
+                })
+            })
+            .collect()
+    }
+
+    /// MIDI settings, without input until it is connected.
+    pub fn midi(&self) -> Result<Midi> {
+        let config = &self.midi;
+        let mut midi = Midi::default();
+        if let Some(channel) = config.channel {
+            if channel == 0 {
+                bail!("midi.channel must be at least 1");
+            }
+            midi.channel = Some(channel - 1);
+        }
+        if let Some(tuning) = config.tuning {
+            if tuning <= 0.0 {
+                bail!("midi.tuning must be positive");
+            }
+            midi.tuning = tuning;
+        }
+        if let Some(bend_range) = config.bend_range {
+            if bend_range < 0.0 {
+                bail!("midi.bend_range can't be negative");
+            }
+            midi.bend_range = bend_range;
+        }
+        midi.velocity = config.velocity.unwrap_or(midi.velocity);
+        midi.gate = config.gate.unwrap_or(midi.gate);
+        for (controller, target) in config.cc.iter() {
+            let (channel, parameter, range) = target
+                .parse()
+                .map_err(|error| eyre!("midi.cc: {}: {}", controller, error))?;
+            let controller = controller
+                .parse::<u8>()
+                .ok()
+                .filter(|x| *x < 128)
+                .ok_or_else(|| eyre!("midi.cc: invalid controller '{}'", controller))?;
+            midi.controls.push(Control {
+                controller,
+                channel,
+                parameter,
+                range,
+            });
+        }
+        Ok(midi)
+    }
+
+    pub fn key_bindings(&self) -> Result<Vec<KeyBinding>> {
+        self.keys
+            .iter()
+            .map(|(key, command)| {
+                let (code, modifiers) = parse_key(key)?;
+                Ok(KeyBinding {
+                    code,
+                    modifiers,
+                    command: command.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+fn parse_color(color: &str) -> Result<Color> {
+    Color::from_str(color).map_err(|_| eyre!("theme: unknown colour '{}'", color))
+}
+
+/// Parse a key like `F1`, `ctrl-s` or `shift-up`.
+pub fn parse_key(key: &str) -> Result<(KeyCode, KeyModifiers)> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut parts: Vec<&str> = key.split('-').collect();
+    // a trailing `-` is the minus key itself
+    let name = match parts.pop() {
+        Some("") if key.ends_with("--") || key == "-" => {
+            parts.pop();
+            "-"
+        }
+        Some(name) => name,
+        None => "",
+    };
+    for modifier in parts {
+        modifiers |= match modifier.to_lowercase().as_str() {
+            "ctrl" | "c" => KeyModifiers::CONTROL,
+            "alt" | "a" => KeyModifiers::ALT,
+            "shift" | "s" => KeyModifiers::SHIFT,
+            _ => bail!("keys: unknown modifier '{}' in '{}'", modifier, key),
+        };
+    }
+    let code = match name.to_lowercase().as_str() {
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" | "ins" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        lower => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => match lower.strip_prefix('f').and_then(|x| x.parse::<u8>().ok()) {
+                    Some(number) if (1..=24).contains(&number) => KeyCode::F(number),
+                    _ => bail!("keys: unknown key '{}'", key),
+                },
+            }
+        }
+    };
+    Ok((code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_parse() {
+        let config = Config::parse(
+            r##"
+            channels = 3
+            startup = ["ch 2 out off"]
+
+            [audio]
+            sample_rate = 48000
+
+            [theme]
+            channels = ["red", "#00ff00"]
+
+            [keys]
+            F1 = "wave sine"
+            ctrl-s = "mute"
+            F2 = "cal 0.1"
+
+            [aliases]
+            sq = "wave square"
+
+            [macros]
+            cal = ["ch1:amp $1", "ch2:amp $1"]
+
+            [osc.map]
+            "/1/toggle1" = "mute"
+            "/1/fader1" = { parameter = "ch2:freq", min = 20, max = 2000 }
+
+            [midi]
+            channel = 2
+            tuning = 432
+            [midi.cc]
+            1 = "vol"
+            74 = { parameter = "ch1:freq", min = 100, max = 1000 }
+
+            [[channel]]
+            waveform = "square"
+            frequency = "1k"
+
+            [[channel]]
+            amplitude = 0.5
+            "##,
+        )
+        .unwrap();
+        assert_eq!(config.channels, Some(3));
+        assert_eq!(config.tick_rate, DEFAULT_TICK_RATE);
+        assert_eq!(config.audio.sample_rate, Some(48000));
+        assert_eq!(config.theme().unwrap().channel(1), Color::Rgb(0, 255, 0));
+        assert_eq!(
+            config.channel_defaults[0]
+                .frequency
+                .as_ref()
+                .unwrap()
+                .to_f32(),
+            Some(1000.0)
+        );
+        let bindings = config.key_bindings().unwrap();
+        assert_eq!(bindings[0].code, KeyCode::F(1));
+        assert_eq!(bindings[2].code, KeyCode::Char('s'));
+        assert_eq!(bindings[2].modifiers, KeyModifiers::CONTROL);
+        let macros = config.macros().unwrap();
+        assert_eq!(macros.macros["cal"], "ch1:amp $1; ch2:amp $1");
+        let routes = config.osc_routes().unwrap();
+        assert_eq!(routes[0].address, "/1/fader1");
+        assert_eq!(routes[0].channel, Some(1));
+        assert_eq!(routes[0].range, Some((20.0, 2000.0)));
+        assert_eq!(routes[1].parameter, Parameter::Mute);
+        let midi = config.midi().unwrap();
+        assert_eq!(midi.channel, Some(1));
+        assert_eq!(midi.tuning, 432.0);
+        assert_eq!(midi.bend_range, 2.0);
+        assert_eq!(midi.controls[0].parameter, Parameter::Volume);
+        assert_eq!(midi.controls[1].controller, 74);
+        assert_eq!(midi.controls[1].channel, Some(0));
+    }
+
+    #[test]
+    fn test_config_validate() {
+        let error = Config::parse(
+            r#"
+            tick_rate = 0
+            [keys]
+            hyper-x = "quit"
+            F2 = "freq fast"
+            [osc.map]
+            "/fader" = "ch1:loudness"
+            [midi.cc]
+            128 = "freq"
+            [[channel]]
+            waveform = "sinus"
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("tick_rate"));
+        assert!(error.contains("hyper"));
+        assert!(error.contains("sinus"));
+        assert!(error.contains("loudness"));
+        assert!(error.contains("invalid controller '128'"));
+        assert!(error.contains("'fast': invalid number"));
+        assert!(Config::parse("unknown = 1").is_err());
+        // waveforms are given by name, not number
+        for waveform in ["0", "2"] {
+            let text = format!("[[channel]]\nwaveform = \"{}\"", waveform);
+            assert!(Config::parse(&text).is_err());
+        }
+    }
+}
@@ -79,6 +79,11 @@ impl EventHandler {
         }
     }
 
+    /// Sender of events, to wake the main loop from other threads.
+    pub fn sender(&self) -> mpsc::Sender<Event> {
+        self.sender.clone()
+    }
+
     /// Receive the next event
     pub fn next(&self) -> Result<Event> {
         Ok(self.receiver.recv()?)
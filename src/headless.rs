@@ -0,0 +1,284 @@
+use crate::app::App;
+use crate::cli::Args;
+use crate::command;
+use crate::parameter::Parameter;
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use serde::Serialize;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Command line setting a channel from a `<waveform>,<frequency>,<amplitude>` specification,
+/// like `sine,1k,-6dBFS`. Empty or missing fields keep their current value.
+pub fn channel_commands(channel: usize, spec: &str) -> Result<String> {
+    let names = ["wave", "freq", "amp"];
+    let fields: Vec<&str> = spec.split(',').map(|x| x.trim()).collect();
+    if fields.len() > names.len() {
+        bail!(
+            "--ch{} '{}': expected <waveform>,<frequency>,<amplitude>",
+            channel,
+            spec
+        );
+    }
+    let line = names
+        .iter()
+        .zip(fields)
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!("ch{}:{} {}", channel, name, value))
+        .collect::<Vec<_>>()
+        .join("; ");
+    command::parse(&line).map_err(|error| eyre!("--ch{} '{}': {}", channel, spec, error))?;
+    Ok(line)
+}
+
+/// Play without the terminal interface until interrupted, the duration elapses or a script
+/// quits, logging to stderr. The audio stream should already be created and configured.
+pub fn run(app: &mut App, args: &Args, tick_rate: u64) -> Result<()> {
+    // from the startup commands of the config file
+    if let Some(warning) = report(app) {
+        eprintln!("Warning: {}", warning);
+    }
+    // only the channels given on the command line play when there are any
+    if !args.channel_specs.is_empty() {
+        for channel in 0..app.channels() {
+            app.set_channel_parameter_value(channel, Parameter::Output, 0.0);
+        }
+    }
+    for (channel, spec) in args.channel_specs.iter() {
+        if *channel > app.channels() {
+            bail!("--ch{}: only {} channels", channel, app.channels());
+        }
+        app.execute_command(&channel_commands(*channel, spec)?);
+        app.set_channel_parameter_value(channel - 1, Parameter::Output, 1.0);
+        if let Some(warning) = report(app) {
+            bail!("--ch{} '{}': {}", channel, spec, warning);
+        }
+    }
+
+    app.check_output_channels()?;
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&interrupted))
+            .wrap_err("Failed to register signal handler")?;
+    }
+
+    app.audio.start_stream()?;
+    let info = &app.audio.info;
+    eprintln!(
+        "Playing on {} ({}), {} Hz, {} channels",
+        info.device, info.host, info.sample_rate, info.channels
+    );
+    if let Some(reply) = app.query(None, None) {
+        eprintln!("{}", reply);
+    }
+    if let Some(path) = &args.script {
+        app.source_script(path);
+    }
+
+    // the loop waits for the stdin commands and the remote control requests
+    let (sender, inputs) = mpsc::channel();
+    if args.stdin {
+        read_commands(sender.clone());
+    }
+    app.remote.set_waker(move || {
+        let _ = sender.send(Input::Remote);
+    });
+    let result = play(app, args, inputs, tick_rate, &interrupted);
+    app.audio.stop_stream()?;
+    if result.is_ok() {
+        eprintln!("Stopped");
+    }
+    result
+}
+
+fn play(
+    app: &mut App,
+    args: &Args,
+    inputs: Receiver<Input>,
+    tick_rate: u64,
+    interrupted: &AtomicBool,
+) -> Result<()> {
+    let start = Instant::now();
+    let tick = Duration::from_millis(tick_rate);
+    let mut xruns = 0;
+    while !app.should_quit && !interrupted.load(Ordering::Relaxed) {
+        if args.duration.is_some_and(|x| start.elapsed() >= x) {
+            break;
+        }
+        // wait for the next command line or remote request, or until the next tick
+        if let Ok(Input::Line(line)) = inputs.recv_timeout(tick) {
+            if let Some(warning) = report(app) {
+                eprintln!("Warning: {}", warning);
+            }
+            let reply = Reply::execute(app, &line);
+            println!(
+                "{}",
+                if args.json {
+                    reply.to_json()
+                } else {
+                    reply.to_text()
+                }
+            );
+        }
+        app.tick();
+        if let Some(warning) = report(app) {
+            eprintln!("Warning: {}", warning);
+        }
+
+        let status = app.audio.status.lock().unwrap().clone();
+        if let Some(error) = status.error {
+            bail!("Audio device error: {}", error);
+        }
+        if status.xruns > xruns {
+            eprintln!("Warning: {} xruns", status.xruns);
+            xruns = status.xruns;
+        }
+    }
+    Ok(())
+}
+
+/// What the loop waits for besides the tick.
+enum Input {
+    /// A command line read from stdin.
+    Line(String),
+    /// A job sent by a remote control interface.
+    Remote,
+}
+
+/// Send the lines of stdin from a thread, stopping at its end. Playing goes on.
+fn read_commands(sender: Sender<Input>) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if sender.send(Input::Line(line.to_string())).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Outcome of a command line read from stdin.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Reply {
+    pub command: String,
+    pub ok: bool,
+    /// Replies of the queries.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub result: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Reported while running the commands, which still ran.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    /// Why the line was rejected, nothing ran.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Reply {
+    /// Execute a command line, collecting its query replies and the message or warning it set.
+    /// Only a line that doesn't parse is an error.
+    pub fn execute(app: &mut App, line: &str) -> Self {
+        if let Err(error) = command::parse_with(line, &app.macros) {
+            return Self {
+                command: line.to_string(),
+                error: Some(error.to_string()),
+                ..Default::default()
+            };
+        }
+        app.message = None;
+        app.warning = None;
+        let result = app.execute_command(line);
+        let message = app.message.take().filter(|x| !result.contains(x));
+        Self {
+            command: line.to_string(),
+            ok: true,
+            result,
+            message,
+            warning: app.warning.take(),
+            error: None,
+        }
+    }
+
+    /// `error: <reason>`, or the query replies one per line or `ok` with the message, followed by
+    /// `warning: <reason>`.
+    pub fn to_text(&self) -> String {
+        let mut text = match (&self.error, &self.message) {
+            (Some(error), _) => return format!("error: {}", error),
+            _ if !self.result.is_empty() => self.result.join("\n"),
+            (None, Some(message)) => format!("ok: {}", message),
+            (None, None) => "ok".to_string(),
+        };
+        if let Some(warning) = &self.warning {
+            text.push_str(&format!("\nwarning: {}", warning));
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Log the message of the last commands, returning their warning. Both are cleared.
+fn report(app: &mut App) -> Option<String> {
+    if let Some(message) = app.message.take() {
+        eprintln!("{}", message);
+    }
+    app.warning.take()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headless_channel_commands() {
+        assert_eq!(
+            channel_commands(1, "sine,1k,-6dBFS").unwrap(),
+            "ch1:wave sine; ch1:freq 1k; ch1:amp -6dBFS"
+        );
+        assert_eq!(
+            channel_commands(2, "square,500").unwrap(),
+            "ch2:wave square; ch2:freq 500"
+        );
+        assert_eq!(channel_commands(3, ",220").unwrap(), "ch3:freq 220");
+        assert!(channel_commands(1, "pink,1k").is_err());
+        assert!(channel_commands(1, "sine,1k,-6dBFS,0").is_err());
+    }
+
+    #[test]
+    fn test_headless_reply() {
+        let mut app = App::with_channels(2);
+        let reply = Reply::execute(&mut app, "ch2:freq 880; ch2:freq?");
+        assert_eq!(reply.to_text(), "ch2:freq=880");
+        assert_eq!(
+            reply.to_json(),
+            r#"{"command":"ch2:freq 880; ch2:freq?","ok":true,"result":["ch2:freq=880"]}"#
+        );
+        let reply = Reply::execute(&mut app, "amp loud");
+        assert_eq!(reply.to_text(), "error: 'loud': invalid level");
+        assert!(!reply.ok);
+        let reply = Reply::execute(&mut app, "ch3:freq 1k");
+        assert!(reply.ok);
+        assert_eq!(reply.to_text(), "ok\nwarning: Invalid channel");
+        assert_eq!(
+            reply.to_json(),
+            r#"{"command":"ch3:freq 1k","ok":true,"warning":"Invalid channel"}"#
+        );
+        assert_eq!(Reply::execute(&mut app, "ch2:wave square").to_text(), "ok");
+        assert_eq!(
+            app.query(Some(1), Some(Parameter::Waveform)).unwrap(),
+            "ch2:wave=square"
+        );
+    }
+}
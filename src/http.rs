@@ -0,0 +1,452 @@
+use crate::remote::RemoteHandle;
+use crate::rpc::{self, RpcError};
+use color_eyre::eyre::{Result, WrapErr};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::TryRecvError;
+use std::time::Duration;
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Error, Message, WebSocket};
+
+/// Page operating the generator from a browser.
+const INDEX: &str = include_str!("../assets/index.html");
+/// Largest request line and headers, and largest body.
+const MAX_HEAD_SIZE: u64 = 8192;
+const MAX_BODY_SIZE: usize = 65536;
+/// Time a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Interval between the reads of the WebSocket frames, the longest a change waits.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Method of a request, the ones of the API or any other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Get,
+    Put,
+    Post,
+    Delete,
+    Other,
+}
+
+impl Method {
+    fn parse(name: &str) -> Self {
+        match name {
+            "GET" => Method::Get,
+            "PUT" => Method::Put,
+            "POST" => Method::Post,
+            "DELETE" => Method::Delete,
+            _ => Method::Other,
+        }
+    }
+}
+
+/// Request line, headers and body of a request.
+struct Request {
+    method: Method,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Request {
+    /// Read a request, `None` when the connection closes or the request is malformed.
+    fn read(reader: &mut impl BufRead) -> Option<Self> {
+        let mut head = reader.take(MAX_HEAD_SIZE);
+        let mut line = String::new();
+        head.read_line(&mut line).ok()?;
+        let mut parts = line.split_whitespace();
+        let method = Method::parse(parts.next()?);
+        let url = parts.next()?.to_string();
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if head.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            if line.trim_end().is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':')?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        let mut request = Request {
+            method,
+            url,
+            headers,
+            body: String::new(),
+        };
+        let length: usize = match request.header("Content-Length") {
+            Some(length) => length.parse().ok()?,
+            None => 0,
+        };
+        if length > MAX_BODY_SIZE {
+            return None;
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).ok()?;
+        request.body = String::from_utf8(body).ok()?;
+        Some(request)
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Method of the JSON-RPC interface and its params, or the status of a request that maps to
+/// none of them.
+type Call = std::result::Result<(&'static str, Value), (u16, String)>;
+
+/// Translate a REST request to a JSON-RPC method:
+///
+/// - `GET /api/state`: every channel and the output
+/// - `GET /api/parameters`: names, units and descriptions of the parameters
+/// - `GET /api/channels/<n>`: all the parameters of a channel
+/// - `GET|PUT /api/channels/<n>/<parameter>`: a channel parameter
+/// - `GET|PUT /api/output/<parameter>`: an output parameter
+/// - `POST /api/presets/<name>/load`: load a preset
+///
+/// The body of a `PUT` is the value, as JSON or as a command argument like `1k`, or an
+/// object with a `value`.
+pub fn route(method: &Method, path: &str, body: &str) -> Call {
+    let path = path.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.split('/').filter(|x| !x.is_empty()).collect();
+    let channel = |x: &str| x.parse::<u64>().map(Value::from).unwrap_or(json!(x));
+    let value = || match serde_json::from_str::<Value>(body) {
+        Ok(Value::Object(mut object)) => object.remove("value").unwrap_or(Value::Null),
+        Ok(value) => value,
+        Err(_) => json!(body.trim()),
+    };
+    let call = match (method, segments.as_slice()) {
+        (Method::Get, ["api", "state"]) => ("get", json!({})),
+        (Method::Get, ["api", "parameters"]) => ("list_parameters", json!({})),
+        (Method::Get, ["api", "channels", n]) => ("get", json!({"channel": channel(n)})),
+        (Method::Get, ["api", "channels", n, parameter]) => (
+            "get",
+            json!({"channel": channel(n), "parameter": parameter}),
+        ),
+        (Method::Put, ["api", "channels", n, parameter]) => (
+            "set",
+            json!({"channel": channel(n), "parameter": parameter, "value": value()}),
+        ),
+        (Method::Get, ["api", "output", parameter]) => ("get", json!({"parameter": parameter})),
+        (Method::Put, ["api", "output", parameter]) => {
+            ("set", json!({"parameter": parameter, "value": value()}))
+        }
+        (Method::Post, ["api", "presets", name, "load"]) => ("load_preset", json!({"name": name})),
+        (
+            _,
+            ["api", "state" | "parameters"]
+            | ["api", "channels", _]
+            | ["api", "channels", _, _]
+            | ["api", "output", _]
+            | ["api", "presets", _, "load"],
+        ) => return Err((405, "method not allowed".to_string())),
+        _ => return Err((404, "not found".to_string())),
+    };
+    Ok(call)
+}
+
+/// Status of a failed JSON-RPC call.
+fn status(error: &RpcError) -> u16 {
+    match error.code {
+        rpc::INVALID_PARAMS => 400,
+        rpc::METHOD_NOT_FOUND => 404,
+        _ => 422,
+    }
+}
+
+/// Serve the web panel, the REST API and a WebSocket stream of the changes at `/api/ws`, on
+/// an address like `0.0.0.0:8080` or a port on the loopback interface. The requests are run by
+/// the application on its next tick.
+pub fn listen(address: &str, remote: RemoteHandle) -> Result<SocketAddr> {
+    let address = match address.parse::<u16>() {
+        Ok(port) => format!("127.0.0.1:{}", port),
+        Err(_) => address.to_string(),
+    };
+    let listener = TcpListener::bind(&address)
+        .wrap_err_with(|| format!("Failed to listen for HTTP on {}", address))?;
+    let address = listener.local_addr()?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().filter_map(|x| x.ok()) {
+            let remote = remote.clone();
+            std::thread::spawn(move || serve(stream, remote));
+        }
+    });
+    Ok(address)
+}
+
+/// Answer the request of a connection, which is then closed, or upgrade it to a WebSocket.
+fn serve(stream: TcpStream, remote: RemoteHandle) {
+    let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+    let mut reader = BufReader::new(&stream);
+    let Some(request) = Request::read(&mut reader) else {
+        return;
+    };
+    if request.url == "/api/ws" && request.header("Upgrade").is_some() {
+        // the frames the client sent right after its request
+        let buffered = reader.buffer().to_vec();
+        self::stream(stream, buffered, &request, remote);
+    } else {
+        respond(&stream, &request, &remote);
+    }
+}
+
+/// Answer a request, `None` once the application is gone.
+fn respond(stream: &TcpStream, request: &Request, remote: &RemoteHandle) -> Option<()> {
+    if request.method == Method::Get && matches!(request.url.as_str(), "/" | "/index.html") {
+        return send(stream, 200, "text/html; charset=utf-8", INDEX).ok();
+    }
+    // browsers send JSON to another site only after asking it, which is never allowed, so
+    // a page can't change the settings behind the back of the user
+    let json = request
+        .header("Content-Type")
+        .is_some_and(|x| x.to_ascii_lowercase().starts_with("application/json"));
+    let (status, reply) = match route(&request.method, &request.url, &request.body) {
+        Ok(_) if request.method != Method::Get && !json => (
+            415,
+            json!({"error": "the content type must be application/json"}),
+        ),
+        Ok((method, params)) => match remote.call(move |app| rpc::call(app, method, &params))? {
+            Ok(result) => (200, result),
+            Err(error) => (status(&error), json!({"error": error.message})),
+        },
+        Err((status, message)) => (status, json!({"error": message})),
+    };
+    send(stream, status, "application/json", &reply.to_string()).ok()
+}
+
+fn send(
+    mut stream: &TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        _ => "Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Send the state as `{"state": ...}`, then each change as `{"changed": {"channel": 1,
+/// "parameter": "freq", "value": 440}}` until the client is gone. The frames of the client are
+/// read between the changes to answer its pings and its close, the values are set through the
+/// REST API.
+fn stream(stream: TcpStream, buffered: Vec<u8>, request: &Request, remote: RemoteHandle) {
+    let Some(key) = request.header("Sec-WebSocket-Key") else {
+        let _ = send(&stream, 400, "text/plain", "missing key");
+        return;
+    };
+    let Some((state, changes)) = remote.call(|app| {
+        let state = rpc::get(app, None, None);
+        (state, app.remote.subscribe(app.parameter_values()))
+    }) else {
+        return;
+    };
+    let upgraded = write!(
+        &stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    );
+    if upgraded.is_err() || stream.set_read_timeout(Some(POLL_INTERVAL)).is_err() {
+        return;
+    }
+    let mut socket = WebSocket::from_partially_read(stream, buffered, Role::Server, None);
+    if socket
+        .send(Message::text(json!({"state": state}).to_string()))
+        .is_err()
+    {
+        return;
+    }
+    loop {
+        // pings are answered and a close is replied to by the reads, which fail once closed
+        match socket.read() {
+            Ok(_) => {}
+            Err(Error::Io(error))
+                if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => return,
+        }
+        loop {
+            let values = match changes.try_recv() {
+                Ok(values) => values,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            };
+            for value in values.iter() {
+                let message = json!({"changed": rpc::parameter_json(value)}).to_string();
+                if socket.send(Message::text(message)).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::App;
+    use crate::parameter::Parameter;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use tungstenite::stream::MaybeTlsStream;
+
+    #[test]
+    fn test_http_route() {
+        assert_eq!(
+            route(&Method::Get, "/api/state", ""),
+            Ok(("get", json!({})))
+        );
+        assert_eq!(
+            route(&Method::Put, "/api/channels/2/freq", "1k"),
+            Ok((
+                "set",
+                json!({"channel": 2, "parameter": "freq", "value": "1k"})
+            ))
+        );
+        assert_eq!(
+            route(&Method::Put, "/api/output/vol", r#"{"value": 0.5}"#),
+            Ok(("set", json!({"parameter": "vol", "value": 0.5})))
+        );
+        assert_eq!(
+            route(&Method::Post, "/api/presets/bench/load", ""),
+            Ok(("load_preset", json!({"name": "bench"})))
+        );
+        assert_eq!(route(&Method::Get, "/api/volume", "").unwrap_err().0, 404);
+        assert_eq!(
+            route(&Method::Delete, "/api/output/vol", "").unwrap_err().0,
+            405
+        );
+    }
+
+    /// Send a request, with a JSON content type unless it's a `GET`, and return the status and
+    /// body of the response.
+    fn request(address: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        let content_type = match method {
+            "GET" => "",
+            _ => "Content-Type: application/json\r\n",
+        };
+        raw_request(address, method, path, content_type, body)
+    }
+
+    fn raw_request(
+        address: SocketAddr,
+        method: &str,
+        path: &str,
+        headers: &str,
+        body: &str,
+    ) -> (u16, String) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            headers,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap_or_default();
+        (status, body.to_string())
+    }
+
+    #[test]
+    fn test_http_listen() {
+        let mut app = App::with_channels(2);
+        let address = listen("127.0.0.1:0", app.remote.handle()).unwrap();
+        let client = std::thread::spawn(move || {
+            let (status, body) = request(address, "GET", "/", "");
+            assert_eq!(status, 200);
+            assert!(body.contains("<html"));
+            assert_eq!(
+                request(address, "PUT", "/api/channels/2/freq", "1k"),
+                (200, "1000.0".to_string())
+            );
+            let (status, body) = request(address, "GET", "/api/channels/2", "");
+            assert_eq!(status, 200);
+            assert!(body.contains(r#""freq":1000.0"#));
+            assert_eq!(request(address, "PUT", "/api/output/vol", "loud").0, 400);
+            // as sent by a form on another site
+            let form = "Content-Type: text/plain\r\n";
+            assert_eq!(
+                raw_request(address, "POST", "/api/presets/bench/load", form, "").0,
+                415
+            );
+            assert_eq!(
+                raw_request(address, "PUT", "/api/output/vol", "", "0").0,
+                415
+            );
+
+            let url = format!("ws://{}/api/ws", address);
+            let (mut socket, _) = tungstenite::connect(url).unwrap();
+            if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+                stream
+                    .set_read_timeout(Some(Duration::from_secs(5)))
+                    .unwrap();
+            }
+            let state: Value =
+                serde_json::from_str(socket.read().unwrap().to_text().unwrap()).unwrap();
+            assert_eq!(state["state"]["output"]["vol"], json!(1.0));
+            request(address, "PUT", "/api/channels/1/amp", "0.5");
+            let changed: Value =
+                serde_json::from_str(socket.read().unwrap().to_text().unwrap()).unwrap();
+            assert_eq!(
+                changed,
+                json!({"changed": {"channel": 1, "parameter": "amp", "value": 0.5}})
+            );
+            socket.send(Message::Ping(b"ping".to_vec().into())).unwrap();
+            assert_eq!(
+                socket.read().unwrap(),
+                Message::Pong(b"ping".to_vec().into())
+            );
+            socket.close(None).unwrap();
+            // the close is replied to, not left to time out
+            loop {
+                match socket.read() {
+                    Ok(_) => {}
+                    Err(error) => {
+                        assert!(matches!(error, Error::ConnectionClosed), "{}", error);
+                        break;
+                    }
+                }
+            }
+        });
+        while !client.is_finished() {
+            app.tick();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        client.join().unwrap();
+        assert_eq!(
+            app.channel_parameter_value(1, Parameter::Frequency),
+            Some(1000.0)
+        );
+    }
+}
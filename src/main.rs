@@ -1,31 +1,160 @@
 mod app;
 mod audio;
+mod cli;
+mod command;
+mod complete;
+mod config;
 mod event;
+mod headless;
+#[cfg(feature = "http")]
+mod http;
+mod meter;
+mod midi;
+mod osc;
 mod parameter;
+mod preset;
+mod preview;
+mod remote;
+#[cfg(any(unix, feature = "http"))]
+mod rpc;
+mod scope;
+#[cfg(feature = "scpi")]
+mod scpi;
+mod script;
+mod spectrum;
+mod target;
 mod tui;
 mod ui;
 mod update;
+mod xy;
 
-use app::App;
-use color_eyre::eyre::Result;
+use app::{App, DEFAULT_CHANNELS, MAX_CHANNELS};
+use cli::{Args, USAGE};
+use color_eyre::eyre::{bail, Result};
+use config::Config;
 use event::{Event, EventHandler};
 use ratatui::{backend::CrosstermBackend, Terminal};
 use tui::Tui;
-use update::update;
+use update::{update, update_mouse};
 
 fn main() -> Result<()> {
     color_eyre::install()?;
+    let args = Args::parse(std::env::args().skip(1))?;
+    if args.help {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+    if args.version {
+        println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    let config = Config::load(args.config.as_deref())?;
+    // enough channels for the ones given on the command line
+    let highest = args.channel_specs.iter().map(|(x, _)| *x).max();
+    let channels = args
+        .channels
+        .or(config.channels)
+        .unwrap_or(DEFAULT_CHANNELS.max(highest.unwrap_or(0)));
+    if !(1..=MAX_CHANNELS).contains(&channels) {
+        bail!("Number of channels must be between 1 and {}", MAX_CHANNELS);
+    }
+
     // Create apllication instance
-    let mut app = App::new();
+    let mut app = App::with_channels(channels);
+
+    // Open the audio device before entering the terminal so errors are readable
+    app.audio.preferences = config.audio.clone();
+    app.audio.create_stream()?;
+    app.update_preview_sample_rate();
+    app.apply_config(&config)?;
+    app.check_output_channels()?;
+    if let Some(address) = args.scpi.as_ref().or(config.remote.scpi.as_ref()) {
+        #[cfg(feature = "scpi")]
+        {
+            let address = scpi::listen(address, app.remote.handle())?;
+            app.set_message(&format!("Listening for SCPI on {}", address));
+        }
+        #[cfg(not(feature = "scpi"))]
+        bail!(
+            "Can't listen for SCPI on {}, built without the scpi feature",
+            address
+        );
+    }
+    // the socket is removed when the listener is dropped on exit
+    let socket = args.socket.as_ref().or(config.remote.socket.as_ref());
+    #[cfg(unix)]
+    let _listener = match socket {
+        Some(path) => {
+            let listener = rpc::listen(path, app.remote.handle())?;
+            app.set_message(&format!("Listening for JSON-RPC on {}", path.display()));
+            Some(listener)
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if socket.is_some() {
+        bail!("JSON-RPC sockets are only supported on Unix");
+    }
+    if let Some(address) = args.osc.as_ref().or(config.osc.listen.as_ref()) {
+        let feedback = config.osc.feedback.as_deref();
+        let address = osc::listen(address, feedback, config.osc_routes()?, app.remote.handle())?;
+        app.set_message(&format!("Listening for OSC on {}", address));
+    }
+    if let Some(address) = args.http.as_ref().or(config.remote.http.as_ref()) {
+        #[cfg(feature = "http")]
+        {
+            let address = http::listen(address, app.remote.handle())?;
+            app.set_message(&format!("Serving HTTP on http://{}", address));
+        }
+        #[cfg(not(feature = "http"))]
+        bail!(
+            "Can't serve HTTP on {}, built without the http feature",
+            address
+        );
+    }
+    // the input is closed when the connection is dropped on exit
+    let port = args.midi.as_ref().or(config.midi.input.as_ref());
+    #[cfg(feature = "midi")]
+    let _connection = match port {
+        Some(port) => {
+            let (name, connection) = midi::connect(port, app.remote.handle())?;
+            app.set_message(&format!("Playing from MIDI input {}", name));
+            app.midi.input = Some(name);
+            Some(connection)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "midi"))]
+    if let Some(port) = port {
+        bail!(
+            "Can't play from MIDI input '{}', built without the midi feature",
+            port
+        );
+    }
+    if args.headless {
+        return headless::run(&mut app, &args, config.tick_rate);
+    }
+    if let Some(path) = App::default_history_path() {
+        if let Err(error) = app.load_history(&path) {
+            app.set_warning(&format!("Failed to load history: {}", error));
+        }
+    }
 
     // Initialize terminal
     let backend = CrosstermBackend::new(std::io::stderr());
     let terminal = Terminal::new(backend)?;
-    let events = EventHandler::new(125);
+    let events = EventHandler::new(config.tick_rate);
     let mut tui = Tui::new(terminal, events);
+    // remote control requests are run right away with an extra tick
+    let sender = tui.event_handler.sender();
+    app.remote.set_waker(move || {
+        let _ = sender.send(Event::Tick);
+    });
     tui.enter()?;
-    app.audio.create_stream()?;
     app.audio.start_stream()?;
+    if let Some(path) = &args.script {
+        app.source_script(path);
+    }
 
     // Start the main loop
     while !app.should_quit {
@@ -34,9 +163,9 @@ fn main() -> Result<()> {
 
         // Handle events
         match tui.event_handler.next()? {
-            Event::Tick => {}
+            Event::Tick => app.tick(),
             Event::Key(key_event) => update(&mut app, key_event),
-            Event::Mouse(_) => {}
+            Event::Mouse(mouse_event) => update_mouse(&mut app, mouse_event),
             Event::Resize(_, _) => {}
         };
     }
@@ -0,0 +1,142 @@
+use std::f64::consts::PI;
+
+/// Oversampling of the true peak measurement.
+const OVERSAMPLING: usize = 4;
+/// Taps of each phase of the interpolation filter.
+const TAPS: usize = 12;
+/// Fall of the peak levels, in dB per second.
+const PEAK_DECAY: f32 = 20.0;
+/// Time constant of the RMS level, in seconds.
+const RMS_TIME: f32 = 0.3;
+
+/// Levels of an output channel, 1 being full scale.
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub struct Levels {
+    /// Highest sample, falling slowly.
+    pub peak: f32,
+    pub rms: f32,
+    /// Highest level between the samples as well, falling slowly.
+    pub true_peak: f32,
+}
+
+/// Level meter of an output channel, fed by the audio callback.
+#[derive(Debug, Clone)]
+pub struct Meter {
+    /// Peak fall for each sample.
+    decay: f32,
+    /// Weight of each sample in the mean square.
+    weight: f32,
+    peak: f32,
+    true_peak: f32,
+    mean_square: f32,
+    /// A sample beyond full scale was seen since the last `take_clip`.
+    clipped: bool,
+    /// Phases of the interpolation filter.
+    filter: [[f32; TAPS]; OVERSAMPLING],
+    /// Last samples, `position` being the newest.
+    history: [f32; TAPS],
+    position: usize,
+}
+
+impl Meter {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            decay: 10f32.powf(-PEAK_DECAY / 20.0 / sample_rate),
+            weight: 1.0 - (-1.0 / (RMS_TIME * sample_rate)).exp(),
+            peak: 0.0,
+            true_peak: 0.0,
+            mean_square: 0.0,
+            clipped: false,
+            filter: interpolation_filter(),
+            history: [0.0; TAPS],
+            position: 0,
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) {
+        let level = sample.abs();
+        self.peak = (self.peak * self.decay).max(level);
+        self.mean_square += (sample * sample - self.mean_square) * self.weight;
+        self.clipped |= level > 1.0;
+
+        self.position = (self.position + 1) % TAPS;
+        self.history[self.position] = sample;
+        let mut true_peak = level;
+        for phase in self.filter.iter() {
+            let value: f32 = phase
+                .iter()
+                .enumerate()
+                .map(|(k, h)| h * self.history[(self.position + TAPS - k) % TAPS])
+                .sum();
+            true_peak = true_peak.max(value.abs());
+        }
+        self.true_peak = (self.true_peak * self.decay).max(true_peak);
+    }
+
+    pub fn levels(&self) -> Levels {
+        Levels {
+            peak: self.peak,
+            rms: self.mean_square.sqrt(),
+            true_peak: self.true_peak,
+        }
+    }
+
+    /// Whether the channel clipped since the last call.
+    pub fn take_clip(&mut self) -> bool {
+        std::mem::take(&mut self.clipped)
+    }
+}
+
+/// Windowed sinc interpolating between the samples at quarters of a sample, split in phases
+/// each with a gain of 1.
+fn interpolation_filter() -> [[f32; TAPS]; OVERSAMPLING] {
+    let length = (OVERSAMPLING * TAPS) as f64;
+    let mut filter = [[0.0; TAPS]; OVERSAMPLING];
+    for n in 0..OVERSAMPLING * TAPS {
+        let t = (n as f64 - length / 2.0) / OVERSAMPLING as f64;
+        let sinc = match t == 0.0 {
+            true => 1.0,
+            false => (PI * t).sin() / (PI * t),
+        };
+        // Blackman window, zero at both ends of `length + 1` taps
+        let x = 2.0 * PI * n as f64 / length;
+        let window = 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos();
+        filter[n % OVERSAMPLING][n / OVERSAMPLING] = (sinc * window) as f32;
+    }
+    for phase in filter.iter_mut() {
+        let sum: f32 = phase.iter().sum();
+        phase.iter_mut().for_each(|x| *x /= sum);
+    }
+    filter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_2, FRAC_PI_4};
+
+    #[test]
+    fn test_meter_levels() {
+        // a quarter of the sample rate sampled away from its peaks
+        let mut meter = Meter::new(48000.0);
+        for i in 0..96000 {
+            let phase = FRAC_PI_2 * (i % 4) as f32 + FRAC_PI_4;
+            meter.process(phase.sin());
+        }
+        let levels = meter.levels();
+        assert!((levels.peak - FRAC_1_SQRT_2).abs() < 1e-3);
+        assert!((levels.rms - FRAC_1_SQRT_2).abs() < 1e-2);
+        assert!(levels.true_peak > 0.97 && levels.true_peak < 1.03);
+        assert!(!meter.take_clip());
+
+        meter.process(1.5);
+        assert_eq!(meter.levels().peak, 1.5);
+        assert!(meter.take_clip());
+        assert!(!meter.take_clip());
+        // the peak falls by 20 dB in a second of silence
+        for _ in 0..48000 {
+            meter.process(0.0);
+        }
+        assert!((meter.levels().peak - 0.15).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,467 @@
+use crate::app::{channel_name, App};
+use crate::command;
+use crate::parameter::{Parameter, WAVEFORMS};
+#[cfg(feature = "midi")]
+use crate::remote::RemoteHandle;
+use std::collections::BTreeMap;
+
+#[cfg(feature = "midi")]
+use color_eyre::eyre::{bail, eyre, Result};
+#[cfg(feature = "midi")]
+use midir::{Ignore, MidiInput, MidiInputConnection};
+
+/// Frequency of A4, note 69.
+pub const DEFAULT_TUNING: f32 = 440.0;
+/// Semitones of a full pitch bend, the General MIDI default.
+pub const DEFAULT_BEND_RANGE: f32 = 2.0;
+/// Name of the client and of its input port, as listed by `aconnect`.
+#[cfg(feature = "midi")]
+const PORT_NAME: &str = "signal-generator";
+
+/// Channel voice messages the generator responds to, with 0-based MIDI channels.
+#[cfg_attr(not(feature = "midi"), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiMessage {
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+    },
+    Control {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// Pitch bend from -8192 to 8191.
+    PitchBend {
+        channel: u8,
+        value: i16,
+    },
+}
+
+#[cfg_attr(not(feature = "midi"), allow(dead_code))]
+impl MidiMessage {
+    /// Parse a message, `None` for the ones that are ignored. A note on with velocity 0 is a
+    /// note off, as sent by keyboards using running status.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (&status, data) = bytes.split_first()?;
+        let channel = status & 0x0f;
+        match (status & 0xf0, data) {
+            (0x90, &[note, velocity, ..]) if velocity > 0 => Some(MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            }),
+            (0x80 | 0x90, &[note, _, ..]) => Some(MidiMessage::NoteOff { channel, note }),
+            (0xb0, &[controller, value, ..]) => Some(MidiMessage::Control {
+                channel,
+                controller,
+                value,
+            }),
+            (0xe0, &[lsb, msb, ..]) => Some(MidiMessage::PitchBend {
+                channel,
+                value: ((msb as i16) << 7 | lsb as i16) - 8192,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Parameter set by a controller, of a channel or of the selected channel when `None`. The
+/// 0 to 127 value is scaled to the range, or to the usual values of the parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Control {
+    pub controller: u8,
+    pub channel: Option<usize>,
+    pub parameter: Parameter,
+    pub range: Option<(f32, f32)>,
+}
+
+#[cfg_attr(not(feature = "midi"), allow(dead_code))]
+impl Control {
+    /// Parameter value of a controller value.
+    pub fn value(&self, value: u8) -> f32 {
+        let x = value.min(127) as f32 / 127.0;
+        match (self.range, self.parameter) {
+            (Some((min, max)), _) => min + x * (max - min),
+            (None, Parameter::Output | Parameter::Mute) => (value >= 64) as u8 as f32,
+            // split or mix
+            (None, Parameter::Routing) => (value >= 64) as u8 as f32,
+            (None, Parameter::Waveform) => 1.0 + (x * (WAVEFORMS.len() - 1) as f32).round(),
+            // 20 Hz to 20 kHz, evenly spread over the octaves
+            (None, Parameter::Frequency) => 20.0 * 1000f32.powf(x),
+            (None, Parameter::DcOffset) => x * 2.0 - 1.0,
+            (None, _) => x,
+        }
+    }
+
+    /// Name of the parameter as the commands write it, like `ch1:freq` or `vol`.
+    pub fn target(&self) -> String {
+        let name = command::parameter_name(self.parameter);
+        match self.channel {
+            Some(channel) if !self.parameter.is_output() => {
+                format!("ch{}:{}", channel + 1, name)
+            }
+            _ => name.to_string(),
+        }
+    }
+}
+
+/// How the MIDI messages play the channels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Midi {
+    /// Name of the connected input port, `None` when not connected.
+    pub input: Option<String>,
+    /// Channel playing all the notes, otherwise MIDI channel n plays channel n.
+    pub channel: Option<usize>,
+    /// Frequency of A4 in Hz.
+    pub tuning: f32,
+    /// Semitones of a full pitch bend.
+    pub bend_range: f32,
+    /// Set the amplitude from the note velocity.
+    pub velocity: bool,
+    /// Turn the channel output on with a note, and off when its last note is released.
+    pub gate: bool,
+    pub controls: Vec<Control>,
+    /// Channel and parameter mapped to the next controller moved.
+    pub learn: Option<(usize, Parameter)>,
+    /// Notes held on each channel, the last one is playing.
+    notes: BTreeMap<usize, Vec<u8>>,
+    /// Pitch bend of each channel in semitones.
+    bends: BTreeMap<usize, f32>,
+}
+
+impl Default for Midi {
+    fn default() -> Self {
+        Self {
+            input: None,
+            channel: None,
+            tuning: DEFAULT_TUNING,
+            bend_range: DEFAULT_BEND_RANGE,
+            velocity: true,
+            gate: true,
+            controls: Vec::new(),
+            learn: None,
+            notes: BTreeMap::new(),
+            bends: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "midi"), allow(dead_code))]
+impl Midi {
+    /// Frequency of a note, bent by a number of semitones.
+    pub fn frequency(&self, note: u8, bend: f32) -> f32 {
+        self.tuning * 2f32.powf((note as f32 - 69.0 + bend) / 12.0)
+    }
+
+    /// Frequency of the note playing on a channel, if any.
+    fn playing(&self, channel: usize) -> Option<f32> {
+        let note = *self.notes.get(&channel)?.last()?;
+        let bend = self.bends.get(&channel).copied().unwrap_or(0.0);
+        Some(self.frequency(note, bend))
+    }
+
+    /// Map a controller to the parameter being learned, replacing its previous mapping.
+    fn learn(&mut self, controller: u8) -> Option<Control> {
+        let (channel, parameter) = self.learn.take()?;
+        let control = Control {
+            controller,
+            channel: Some(channel).filter(|_| !parameter.is_output()),
+            parameter,
+            range: None,
+        };
+        self.controls.retain(|x| x.controller != controller);
+        self.controls.push(control.clone());
+        Some(control)
+    }
+}
+
+/// Play a message on the application. The last note held on a channel is the one playing.
+#[cfg_attr(not(feature = "midi"), allow(dead_code))]
+pub fn handle(app: &mut App, message: MidiMessage) {
+    // taken so the parameters can be set while it is borrowed
+    let mut midi = std::mem::take(&mut app.midi);
+    let fixed = midi.channel;
+    let channel = |x: u8| fixed.unwrap_or(x as usize);
+    match message {
+        MidiMessage::NoteOn {
+            channel: midi_channel,
+            note,
+            velocity,
+        } => {
+            let channel = channel(midi_channel);
+            if channel < app.channels() {
+                let notes = midi.notes.entry(channel).or_default();
+                notes.retain(|x| *x != note);
+                notes.push(note);
+                if let Some(frequency) = midi.playing(channel) {
+                    app.set_channel_parameter_value(channel, Parameter::Frequency, frequency);
+                }
+                if midi.velocity {
+                    let amplitude = velocity as f32 / 127.0;
+                    app.set_channel_parameter_value(channel, Parameter::Amplitude, amplitude);
+                }
+                if midi.gate {
+                    app.set_channel_parameter_value(channel, Parameter::Output, 1.0);
+                }
+            }
+        }
+        MidiMessage::NoteOff {
+            channel: midi_channel,
+            note,
+        } => {
+            let channel = channel(midi_channel);
+            if let Some(notes) = midi.notes.get_mut(&channel) {
+                let playing = notes.last() == Some(&note);
+                notes.retain(|x| *x != note);
+                match midi.playing(channel) {
+                    Some(frequency) if playing => {
+                        app.set_channel_parameter_value(channel, Parameter::Frequency, frequency)
+                    }
+                    None if playing && midi.gate => {
+                        app.set_channel_parameter_value(channel, Parameter::Output, 0.0)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        MidiMessage::PitchBend {
+            channel: midi_channel,
+            value,
+        } => {
+            let channel = channel(midi_channel);
+            let bend = value as f32 / 8192.0 * midi.bend_range;
+            midi.bends.insert(channel, bend);
+            if let Some(frequency) = midi.playing(channel) {
+                app.set_channel_parameter_value(channel, Parameter::Frequency, frequency);
+            }
+        }
+        // controllers are mapped regardless of the MIDI channel
+        MidiMessage::Control {
+            controller, value, ..
+        } => {
+            if let Some(control) = midi.learn(controller) {
+                app.set_message(&format!("CC {} mapped to {}", controller, control.target()));
+            }
+            for control in midi.controls.iter().filter(|x| x.controller == controller) {
+                let channel = control.channel.unwrap_or(app.selected_waveform);
+                app.set_channel_parameter_value(channel, control.parameter, control.value(value));
+            }
+        }
+    }
+    app.midi = midi;
+}
+
+/// Start or cancel learning the controller of the selected parameter, toggles when `None`.
+pub fn learn(app: &mut App, enable: Option<bool>) {
+    if !enable.unwrap_or(app.midi.learn.is_none()) {
+        app.midi.learn = None;
+        app.set_message("MIDI learn cancelled");
+    } else if app.midi.input.is_none() {
+        app.set_warning("No MIDI input connected");
+    } else {
+        let parameter = app.selected_parameter;
+        app.midi.learn = Some((app.selected_waveform, parameter));
+        let target = match parameter.is_output() {
+            true => parameter.to_string(),
+            false => format!("{} {}", channel_name(app.selected_waveform), parameter),
+        };
+        app.set_message(&format!("Move a MIDI controller to map {}", target));
+    }
+}
+
+/// Receive the messages of the input port whose name contains `port`, or of a new virtual
+/// port other programs connect to when `port` is `virtual`. Returns the name of the port and
+/// the connection, which is closed when dropped.
+#[cfg(feature = "midi")]
+pub fn connect(port: &str, remote: RemoteHandle) -> Result<(String, MidiInputConnection<()>)> {
+    let mut input =
+        MidiInput::new(PORT_NAME).map_err(|e| eyre!("Failed to open the MIDI input: {}", e))?;
+    input.ignore(Ignore::All);
+    let callback = move |_: u64, bytes: &[u8], _: &mut ()| {
+        if let Some(message) = MidiMessage::parse(bytes) {
+            // don't wait for the tick, notes should play right away
+            let _ = remote.run(move |app| handle(app, message));
+        }
+    };
+
+    if port == "virtual" {
+        #[cfg(unix)]
+        {
+            use midir::os::unix::VirtualInput;
+            let connection = input
+                .create_virtual(PORT_NAME, callback, ())
+                .map_err(|e| eyre!("Failed to create the MIDI port: {}", e))?;
+            return Ok((format!("{} (virtual)", PORT_NAME), connection));
+        }
+        #[cfg(not(unix))]
+        bail!("Virtual MIDI ports are not supported on this platform");
+    }
+
+    let ports = input.ports();
+    let names: Vec<String> = ports
+        .iter()
+        .map(|x| input.port_name(x).unwrap_or_default())
+        .collect();
+    let Some(index) = names
+        .iter()
+        .position(|x| x.to_lowercase().contains(&port.to_lowercase()))
+    else {
+        bail!(
+            "MIDI input '{}' not found, available: {}",
+            port,
+            match names.is_empty() {
+                true => "none".to_string(),
+                false => names.join(", "),
+            }
+        );
+    };
+    let connection = input
+        .connect(&ports[index], PORT_NAME, callback, ())
+        .map_err(|e| eyre!("Failed to connect to MIDI input '{}': {}", names[index], e))?;
+    Ok((names[index].clone(), connection))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_midi_parse() {
+        assert_eq!(
+            MidiMessage::parse(&[0x91, 60, 100]),
+            Some(MidiMessage::NoteOn {
+                channel: 1,
+                note: 60,
+                velocity: 100
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0x90, 60, 0]),
+            Some(MidiMessage::NoteOff {
+                channel: 0,
+                note: 60
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0xe0, 0, 0x40]),
+            Some(MidiMessage::PitchBend {
+                channel: 0,
+                value: 0
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0xe0, 0, 0]),
+            Some(MidiMessage::PitchBend {
+                channel: 0,
+                value: -8192
+            })
+        );
+        assert_eq!(MidiMessage::parse(&[0xf8]), None);
+        assert_eq!(MidiMessage::parse(&[0xb0, 7]), None);
+    }
+
+    #[test]
+    fn test_midi_notes() {
+        let mut app = App::with_channels(2);
+        let frequency = |app: &App| {
+            app.channel_parameter_value(1, Parameter::Frequency)
+                .unwrap()
+        };
+        let output = |app: &App| app.channel_parameter_value(1, Parameter::Output).unwrap();
+        app.set_channel_parameter_value(1, Parameter::Output, 0.0);
+
+        let note_on = |note| MidiMessage::NoteOn {
+            channel: 1,
+            note,
+            velocity: 127,
+        };
+        handle(&mut app, note_on(69));
+        assert_eq!(frequency(&app), 440.0);
+        assert_eq!(output(&app), 1.0);
+        assert_eq!(
+            app.channel_parameter_value(1, Parameter::Amplitude),
+            Some(1.0)
+        );
+
+        handle(&mut app, note_on(81));
+        assert_eq!(frequency(&app), 880.0);
+        handle(
+            &mut app,
+            MidiMessage::PitchBend {
+                channel: 1,
+                value: -8192,
+            },
+        );
+        assert!((frequency(&app) - app.midi.frequency(79, 0.0)).abs() < 0.01);
+
+        // the previous note plays again when the last one is released
+        handle(
+            &mut app,
+            MidiMessage::NoteOff {
+                channel: 1,
+                note: 81,
+            },
+        );
+        assert!((frequency(&app) - app.midi.frequency(67, 0.0)).abs() < 0.01);
+        handle(
+            &mut app,
+            MidiMessage::NoteOff {
+                channel: 1,
+                note: 69,
+            },
+        );
+        assert_eq!(output(&app), 0.0);
+
+        // notes of channels that don't exist are ignored
+        handle(
+            &mut app,
+            MidiMessage::NoteOn {
+                channel: 5,
+                note: 60,
+                velocity: 1,
+            },
+        );
+        assert_eq!(app.warning, None);
+    }
+
+    #[test]
+    fn test_midi_controls() {
+        let mut app = App::with_channels(2);
+        learn(&mut app, None);
+        assert!(app.warning.is_some());
+
+        app.midi.input = Some("test".to_string());
+        app.set_tab(1);
+        app.select_parameter(Parameter::Frequency);
+        learn(&mut app, None);
+        assert_eq!(app.midi.learn, Some((1, Parameter::Frequency)));
+        let control = |controller, value| MidiMessage::Control {
+            channel: 0,
+            controller,
+            value,
+        };
+        handle(&mut app, control(74, 127));
+        assert_eq!(app.message.as_deref(), Some("CC 74 mapped to ch2:freq"));
+        assert_eq!(
+            app.channel_parameter_value(1, Parameter::Frequency),
+            Some(20000.0)
+        );
+
+        app.midi.controls.push(Control {
+            controller: 7,
+            channel: None,
+            parameter: Parameter::Volume,
+            range: Some((0.0, 0.5)),
+        });
+        handle(&mut app, control(7, 127));
+        assert_eq!(app.channel_parameter_value(0, Parameter::Volume), Some(0.5));
+        handle(&mut app, control(1, 127));
+        assert_eq!(app.midi.learn, None);
+    }
+}
@@ -0,0 +1,485 @@
+use crate::app::App;
+use crate::command;
+use crate::parameter::Parameter;
+use crate::remote::{ParameterValue, RemoteHandle};
+use crate::target::Route;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+/// Largest packet received, OSC over UDP fits in a datagram.
+const MAX_PACKET_SIZE: usize = 65536;
+/// Deepest bundle nesting decoded, deeper packets are rejected before they exhaust the stack.
+const MAX_BUNDLE_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArgument {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Bool(bool),
+    Long(i64),
+    Double(f64),
+    Nil,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub arguments: Vec<OscArgument>,
+}
+
+/// Messages of a packet, the messages of bundles are executed immediately.
+pub fn decode(packet: &[u8]) -> Result<Vec<OscMessage>, String> {
+    decode_nested(packet, 0)
+}
+
+/// Messages of a packet inside `depth` bundles.
+fn decode_nested(packet: &[u8], depth: usize) -> Result<Vec<OscMessage>, String> {
+    let mut reader = Reader {
+        packet,
+        position: 0,
+    };
+    if packet.starts_with(b"#bundle\0") {
+        if depth >= MAX_BUNDLE_DEPTH {
+            return Err("bundles nested too deeply".to_string());
+        }
+        reader.position = 16; // identifier and time tag
+        let mut messages = Vec::new();
+        while reader.position < packet.len() {
+            let size = reader.size()?;
+            let element = reader.bytes(size)?;
+            messages.extend(decode_nested(element, depth + 1)?);
+        }
+        return Ok(messages);
+    }
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return Err(format!("invalid address '{}'", address));
+    }
+    // the type tags are optional in old implementations
+    let tags = match reader.position < packet.len() {
+        true => reader.string()?,
+        false => ",".to_string(),
+    };
+    let Some(tags) = tags.strip_prefix(',') else {
+        return Err(format!("{}: invalid type tags '{}'", address, tags));
+    };
+    let mut arguments = Vec::new();
+    for tag in tags.chars() {
+        let argument = match tag {
+            'i' => OscArgument::Int(reader.int()?),
+            'f' => OscArgument::Float(f32::from_bits(reader.int()? as u32)),
+            's' | 'S' => OscArgument::String(reader.string()?),
+            'h' => OscArgument::Long(i64::from_be_bytes(reader.array()?)),
+            'd' => OscArgument::Double(f64::from_be_bytes(reader.array()?)),
+            'T' => OscArgument::Bool(true),
+            'F' => OscArgument::Bool(false),
+            'N' | 'I' => OscArgument::Nil,
+            'b' => {
+                let size = reader.size()?;
+                reader.bytes(size.next_multiple_of(4))?;
+                OscArgument::Nil
+            }
+            _ => return Err(format!("{}: unsupported type '{}'", address, tag)),
+        };
+        arguments.push(argument);
+    }
+    Ok(vec![OscMessage { address, arguments }])
+}
+
+pub fn encode(message: &OscMessage) -> Vec<u8> {
+    let mut packet = Vec::new();
+    let mut tags = ",".to_string();
+    let mut data = Vec::new();
+    for argument in message.arguments.iter() {
+        match argument {
+            OscArgument::Int(value) => {
+                tags.push('i');
+                data.extend(value.to_be_bytes());
+            }
+            OscArgument::Float(value) => {
+                tags.push('f');
+                data.extend(value.to_be_bytes());
+            }
+            OscArgument::String(value) => {
+                tags.push('s');
+                push_string(&mut data, value);
+            }
+            OscArgument::Bool(value) => tags.push(if *value { 'T' } else { 'F' }),
+            OscArgument::Long(value) => {
+                tags.push('h');
+                data.extend(value.to_be_bytes());
+            }
+            OscArgument::Double(value) => {
+                tags.push('d');
+                data.extend(value.to_be_bytes());
+            }
+            OscArgument::Nil => tags.push('N'),
+        }
+    }
+    push_string(&mut packet, &message.address);
+    push_string(&mut packet, &tags);
+    packet.extend(data);
+    packet
+}
+
+/// Null terminated string padded to 4 bytes.
+fn push_string(packet: &mut Vec<u8>, value: &str) {
+    packet.extend(value.as_bytes());
+    packet.resize((packet.len() + 1).next_multiple_of(4), 0);
+}
+
+struct Reader<'a> {
+    packet: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, size: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .position
+            .checked_add(size)
+            .and_then(|end| self.packet.get(self.position..end))
+            .ok_or("truncated packet")?;
+        self.position += size;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.bytes(N)?.try_into().unwrap_or([0; N]))
+    }
+
+    fn int(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    /// Size of a bundle element or blob, which can't be negative.
+    fn size(&mut self) -> Result<usize, String> {
+        usize::try_from(self.int()?).map_err(|_| "negative size".to_string())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let rest = &self.packet[self.position.min(self.packet.len())..];
+        let length = rest
+            .iter()
+            .position(|x| *x == 0)
+            .ok_or("unterminated string")?;
+        let value = String::from_utf8_lossy(&rest[..length]).to_string();
+        self.bytes((length + 1).next_multiple_of(4))?;
+        Ok(value)
+    }
+}
+
+/// Route of an address, from the map or `/ch/<n>/<parameter>` and `/master/<parameter>`.
+fn resolve(routes: &[Route], address: &str) -> Option<Route> {
+    if let Some(route) = routes.iter().find(|x| x.address == address) {
+        return Some(route.clone());
+    }
+    let parts: Vec<&str> = address.split('/').skip(1).collect();
+    let (channel, parameter) = match parts.as_slice() {
+        ["ch", channel, name] => {
+            let channel = channel.parse::<usize>().ok().filter(|x| *x > 0)?;
+            let parameter = command::command_parameter(name).filter(|x| !x.is_output())?;
+            (Some(channel - 1), parameter)
+        }
+        ["master", name] => {
+            let parameter = command::command_parameter(name).filter(|x| x.is_output())?;
+            (None, parameter)
+        }
+        _ => return None,
+    };
+    Some(Route {
+        address: address.to_string(),
+        channel,
+        parameter,
+        range: None,
+    })
+}
+
+/// Command line setting the parameter of a message. `out` and `mute` without argument
+/// toggle, and strings are parsed like the command arguments, e.g. `square` or `1k`.
+pub fn command_line(routes: &[Route], message: &OscMessage) -> Result<String, String> {
+    let route = resolve(routes, &message.address).ok_or_else(|| "unknown address".to_string())?;
+    let (channel, parameter) = (route.channel, route.parameter);
+    let number = match message.arguments.first() {
+        Some(OscArgument::Int(value)) => Some(*value as f32),
+        Some(OscArgument::Float(value)) => Some(*value),
+        Some(OscArgument::Long(value)) => Some(*value as f32),
+        Some(OscArgument::Double(value)) => Some(*value as f32),
+        _ => None,
+    };
+    let number = number.map(|x| match route.range {
+        Some((min, max)) => min + x.clamp(0.0, 1.0) * (max - min),
+        None => x,
+    });
+    let value = match (message.arguments.first(), number) {
+        (_, Some(number)) => match parameter {
+            Parameter::Output | Parameter::Mute => {
+                if number >= 0.5 { "on" } else { "off" }.to_string()
+            }
+            Parameter::Waveform | Parameter::Routing => number.round().to_string(),
+            _ => number.to_string(),
+        },
+        (Some(OscArgument::String(value)), _) => value.clone(),
+        (Some(OscArgument::Bool(value)), _) => if *value { "on" } else { "off" }.to_string(),
+        _ => String::new(),
+    };
+    let name = command::parameter_name(parameter);
+    let line = match channel {
+        Some(channel) if !parameter.is_output() => {
+            format!("ch{}:{} {}", channel + 1, name, value)
+        }
+        _ => format!("{} {}", name, value),
+    };
+    Ok(line.trim_end().to_string())
+}
+
+/// Messages reporting a value, to its `/ch` or `/master` address and the mapped addresses.
+pub fn feedback(routes: &[Route], value: &ParameterValue) -> Vec<OscMessage> {
+    let name = command::parameter_name(value.parameter);
+    let argument = |range: Option<&(f32, f32)>| match value.parameter {
+        Parameter::Waveform | Parameter::Routing => {
+            OscArgument::String(command::format_value(value.parameter, value.value))
+        }
+        Parameter::Output | Parameter::Mute => OscArgument::Int((value.value > 0.0) as i32),
+        _ => match range {
+            Some((min, max)) if max != min => OscArgument::Float((value.value - min) / (max - min)),
+            _ => OscArgument::Float(value.value),
+        },
+    };
+    let address = match value.channel {
+        Some(channel) => format!("/ch/{}/{}", channel + 1, name),
+        None => format!("/master/{}", name),
+    };
+    let mut messages = vec![OscMessage {
+        address,
+        arguments: vec![argument(None)],
+    }];
+    for route in routes.iter() {
+        let channel = value.channel.filter(|_| !value.parameter.is_output());
+        if route.parameter == value.parameter && route.channel == channel {
+            messages.push(OscMessage {
+                address: route.address.clone(),
+                arguments: vec![argument(route.range.as_ref())],
+            });
+        }
+    }
+    messages
+}
+
+/// Receive OSC messages on a UDP address, or a port on the loopback interface, returning the
+/// bound address. With a feedback address the current values are sent there, then each change.
+pub fn listen(
+    address: &str,
+    feedback_address: Option<&str>,
+    routes: Vec<Route>,
+    remote: RemoteHandle,
+) -> Result<SocketAddr> {
+    let address = match address.parse::<u16>() {
+        Ok(port) => format!("127.0.0.1:{}", port),
+        Err(_) => address.to_string(),
+    };
+    let socket = UdpSocket::bind(&address)
+        .wrap_err_with(|| format!("Failed to listen for OSC on {}", address))?;
+    let local_address = socket.local_addr()?;
+
+    if let Some(feedback_address) = feedback_address {
+        let target = feedback_address
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut x| x.next())
+            .ok_or_else(|| eyre!("Invalid OSC feedback address '{}'", feedback_address))?;
+        // sent from the listening port, which is where controllers expect replies from
+        let socket = socket.try_clone()?;
+        let routes = routes.clone();
+        let remote = remote.clone();
+        std::thread::spawn(move || {
+            let Some((values, changes)) = remote.call(|app| {
+                let values = app.parameter_values();
+                (values.clone(), app.remote.subscribe(values))
+            }) else {
+                return;
+            };
+            for values in std::iter::once(values).chain(changes) {
+                for message in values.iter().flat_map(|x| feedback(&routes, x)) {
+                    let _ = socket.send_to(&encode(&message), target);
+                }
+            }
+        });
+    }
+
+    std::thread::spawn(move || {
+        let mut buffer = vec![0; MAX_PACKET_SIZE];
+        while let Ok((size, _)) = socket.recv_from(&mut buffer) {
+            let lines = match decode(&buffer[..size]) {
+                Ok(messages) => messages
+                    .iter()
+                    .map(|x| command_line(&routes, x).map_err(|e| format!("{}: {}", x.address, e)))
+                    .collect(),
+                Err(error) => vec![Err(error)],
+            };
+            // don't wait for the tick, a fader sends many messages
+            let sent = remote.run(move |app| {
+                for line in lines {
+                    apply(app, line);
+                }
+            });
+            if sent.is_none() {
+                break;
+            }
+        }
+    });
+    Ok(local_address)
+}
+
+fn apply(app: &mut App, line: Result<String, String>) {
+    let commands = line.and_then(|x| command::parse(&x).map_err(|e| format!("{}: {}", x, e)));
+    match commands {
+        Ok(commands) => {
+            for command in commands.iter() {
+                app.run_command(command);
+            }
+        }
+        Err(error) => app.set_warning(&format!("OSC {}", error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(address: &str, arguments: Vec<OscArgument>) -> OscMessage {
+        OscMessage {
+            address: address.to_string(),
+            arguments,
+        }
+    }
+
+    #[test]
+    fn test_osc_decode() {
+        let freq = message("/ch/1/freq", vec![OscArgument::Float(440.0)]);
+        let packet = encode(&freq);
+        assert_eq!(&packet[..16], b"/ch/1/freq\0\0,f\0\0");
+        assert_eq!(decode(&packet), Ok(vec![freq.clone()]));
+
+        let wave = message(
+            "/ch/2/wave",
+            vec![
+                OscArgument::String("square".to_string()),
+                OscArgument::Int(1),
+            ],
+        );
+        let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        for element in [encode(&freq), encode(&wave)] {
+            bundle.extend((element.len() as i32).to_be_bytes());
+            bundle.extend(element);
+        }
+        assert_eq!(decode(&bundle), Ok(vec![freq, wave]));
+        assert!(decode(b"/ch/1/freq\0\0,f\0\0\0\0").is_err());
+        assert!(decode(b"ch\0\0").is_err());
+    }
+
+    #[test]
+    fn test_osc_decode_malformed() {
+        let bundle = b"#bundle\0\0\0\0\0\0\0\0\x01\xff\xff\xff\xff";
+        assert_eq!(decode(bundle), Err("negative size".to_string()));
+        let blob = b"/ch/1/freq\0\0,b\0\0\xff\xff\xff\xff";
+        assert_eq!(decode(blob), Err("negative size".to_string()));
+        let blob = b"/ch/1/freq\0\0,b\0\0\x7f\xff\xff\xff";
+        assert_eq!(decode(blob), Err("truncated packet".to_string()));
+
+        let mut packet = encode(&message("/ch/1/freq", vec![OscArgument::Float(440.0)]));
+        for depth in 1..=MAX_BUNDLE_DEPTH + 1 {
+            let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+            bundle.extend((packet.len() as i32).to_be_bytes());
+            bundle.extend(packet);
+            packet = bundle;
+            assert_eq!(decode(&packet).is_ok(), depth <= MAX_BUNDLE_DEPTH);
+        }
+        assert_eq!(
+            decode(&packet),
+            Err("bundles nested too deeply".to_string())
+        );
+    }
+
+    #[test]
+    fn test_osc_command_line() {
+        let routes = vec![Route {
+            address: "/1/fader1".to_string(),
+            channel: Some(0),
+            parameter: Parameter::Frequency,
+            range: Some((20.0, 20020.0)),
+        }];
+        let line = |address: &str, arguments| command_line(&routes, &message(address, arguments));
+        assert_eq!(
+            line("/ch/1/freq", vec![OscArgument::Float(440.0)]),
+            Ok("ch1:freq 440".to_string())
+        );
+        assert_eq!(
+            line(
+                "/ch/2/wave",
+                vec![OscArgument::String("square".to_string())]
+            ),
+            Ok("ch2:wave square".to_string())
+        );
+        assert_eq!(
+            line("/master/mute", vec![OscArgument::Int(1)]),
+            Ok("mute on".to_string())
+        );
+        assert_eq!(line("/ch/1/out", vec![]), Ok("ch1:out".to_string()));
+        assert_eq!(
+            line("/1/fader1", vec![OscArgument::Float(0.5)]),
+            Ok("ch1:freq 10020".to_string())
+        );
+        assert!(line("/ch/0/freq", vec![OscArgument::Float(1.0)]).is_err());
+        assert!(line("/master/freq", vec![OscArgument::Float(1.0)]).is_err());
+
+        let value = ParameterValue {
+            channel: Some(0),
+            parameter: Parameter::Frequency,
+            value: 5020.0,
+        };
+        assert_eq!(
+            feedback(&routes, &value),
+            vec![
+                message("/ch/1/freq", vec![OscArgument::Float(5020.0)]),
+                message("/1/fader1", vec![OscArgument::Float(0.25)])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_osc_listen() {
+        let mut app = App::with_channels(2);
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let feedback_address = client.local_addr().unwrap().to_string();
+        let address = listen(
+            "127.0.0.1:0",
+            Some(&feedback_address),
+            Vec::new(),
+            app.remote.handle(),
+        )
+        .unwrap();
+        let packet = encode(&message("/ch/2/freq", vec![OscArgument::Float(880.5)]));
+        client.send_to(&packet, address).unwrap();
+        client
+            .set_read_timeout(Some(std::time::Duration::from_millis(10)))
+            .unwrap();
+
+        let expected = message("/ch/2/freq", vec![OscArgument::Float(880.5)]);
+        let mut buffer = vec![0; MAX_PACKET_SIZE];
+        let start = std::time::Instant::now();
+        let mut received = false;
+        while !received && start.elapsed() < std::time::Duration::from_secs(5) {
+            app.tick();
+            while let Ok(size) = client.recv(&mut buffer) {
+                received |= decode(&buffer[..size]) == Ok(vec![expected.clone()]);
+            }
+        }
+        assert!(received);
+        assert_eq!(
+            app.channel_parameter_value(1, Parameter::Frequency),
+            Some(880.5)
+        );
+    }
+}
@@ -1,46 +1,209 @@
+use std::fmt;
+use std::time::Duration;
+
 #[derive(Default, Debug, Copy, Clone, PartialEq, PartialOrd)]
 pub enum Parameter {
+    Waveform,
     #[default]
     Frequency,
     Amplitude,
-    Waveform,
     PhaseOffset,
     DcOffset,
     Pan,
+    Output,
+    Routing,
+    Volume,
+    Mute,
 }
 
 impl TryFrom<usize> for Parameter {
     type Error = ();
     fn try_from(value: usize) -> Result<Self, Self::Error> {
         match value {
-            0 => Ok(Parameter::Frequency),
-            1 => Ok(Parameter::Amplitude),
-            2 => Ok(Parameter::Waveform),
+            0 => Ok(Parameter::Waveform),
+            1 => Ok(Parameter::Frequency),
+            2 => Ok(Parameter::Amplitude),
             3 => Ok(Parameter::PhaseOffset),
             4 => Ok(Parameter::DcOffset),
             5 => Ok(Parameter::Pan),
+            6 => Ok(Parameter::Output),
+            7 => Ok(Parameter::Routing),
+            8 => Ok(Parameter::Volume),
+            9 => Ok(Parameter::Mute),
             _ => Err(()),
         }
     }
 }
 
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Parameter::Waveform => "Waveform",
+            Parameter::Frequency => "Frequency",
+            Parameter::Amplitude => "Amplitude",
+            Parameter::PhaseOffset => "Phase offset",
+            Parameter::DcOffset => "DC offset",
+            Parameter::Pan => "Pan",
+            Parameter::Output => "Output",
+            Parameter::Routing => "Routing",
+            Parameter::Volume => "Volume",
+            Parameter::Mute => "Mute",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 impl Parameter {
-    pub fn count() -> usize {
-        6
+    /// Parameters of a waveform channel, in the order they are listed.
+    pub const CHANNEL: [Parameter; 7] = [
+        Parameter::Waveform,
+        Parameter::Frequency,
+        Parameter::Amplitude,
+        Parameter::PhaseOffset,
+        Parameter::DcOffset,
+        Parameter::Pan,
+        Parameter::Output,
+    ];
+
+    /// Parameters of the output, in the order they are listed.
+    pub const OUTPUT: [Parameter; 3] = [Parameter::Routing, Parameter::Volume, Parameter::Mute];
+
+    pub fn is_output(&self) -> bool {
+        Self::OUTPUT.contains(self)
+    }
+
+    /// Parameters listed together with this one.
+    fn group(&self) -> &'static [Parameter] {
+        if self.is_output() {
+            &Self::OUTPUT
+        } else {
+            &Self::CHANNEL
+        }
+    }
+
+    /// Position of the parameter in its list.
+    pub fn index(&self) -> usize {
+        self.group().iter().position(|x| x == self).unwrap_or(0)
     }
 
     pub fn next(&self) -> Self {
-        let value = (*self as usize + 1) % Self::count();
-        value.try_into().unwrap_or_default()
+        let group = self.group();
+        group[(self.index() + 1) % group.len()]
     }
 
     pub fn previous(&self) -> Self {
-        let mut value = *self as usize;
+        let group = self.group();
+        let mut value = self.index();
         if value > 0 {
             value = value - 1;
         } else {
-            value = Self::count() - 1;
+            value = group.len() - 1;
+        }
+        group[value]
+    }
+
+    /// Increment used when stepping the parameter value.
+    pub fn step(&self) -> f32 {
+        match self {
+            Parameter::Waveform | Parameter::Output | Parameter::Routing | Parameter::Mute => 1.0,
+            Parameter::Frequency => 1.0,
+            _ => 0.01,
+        }
+    }
+
+    /// Lowest and highest values of the parameter, `None` when it isn't bounded.
+    pub fn range(&self) -> Option<(f32, f32)> {
+        match self {
+            Parameter::Amplitude | Parameter::Volume => Some((0.0, 1.0)),
+            Parameter::DcOffset | Parameter::Pan => Some((-1.0, 1.0)),
+            _ => None,
+        }
+    }
+
+    /// Unit the parameter value is displayed in.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Parameter::Frequency => "Hz",
+            _ => "",
         }
-        value.try_into().unwrap_or_default()
     }
 }
+
+/// Parse a numeric value with an optional SI unit suffix, like the numeric
+/// entry of a bench instrument: `1.5k` is 1500, `250m` is 0.25.
+pub fn parse_value(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last()? {
+        'G' => (&value[..value.len() - 1], 1e9),
+        'M' => (&value[..value.len() - 1], 1e6),
+        'k' | 'K' => (&value[..value.len() - 1], 1e3),
+        'm' => (&value[..value.len() - 1], 1e-3),
+        'u' => (&value[..value.len() - 1], 1e-6),
+        _ => (value, 1.0),
+    };
+    number
+        .parse::<f32>()
+        .ok()
+        .map(|x| x * multiplier)
+        .filter(|x| x.is_finite())
+}
+
+/// Parse a level as a linear value or in decibels relative to full scale: `-6dBFS` or `-6dB`
+/// is about 0.5.
+pub fn parse_level(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let lower = value.to_lowercase();
+    match lower
+        .strip_suffix("dbfs")
+        .or_else(|| lower.strip_suffix("db"))
+    {
+        Some(decibels) => decibels
+            .trim()
+            .parse::<f32>()
+            .ok()
+            .map(|x| 10f32.powf(x / 20.0))
+            .filter(|x| x.is_finite()),
+        None => parse_value(value),
+    }
+}
+
+/// Parse a duration with an optional unit: `500us`, `500ms`, `1.5s`, `2min`, or `2` seconds.
+/// A bare `m` is rejected, it could mean milliseconds or minutes.
+pub fn parse_time(value: &str) -> Option<Duration> {
+    const UNITS: [(&str, f32); 5] = [
+        ("min", 60.0),
+        ("ms", 1e-3),
+        ("us", 1e-6),
+        ("µs", 1e-6),
+        ("s", 1.0),
+    ];
+    let value = value.trim();
+    let (number, scale) = UNITS
+        .iter()
+        .find_map(|(unit, scale)| value.strip_suffix(unit).map(|x| (x, *scale)))
+        .unwrap_or((value, 1.0));
+    number
+        .parse::<f32>()
+        .ok()
+        .and_then(|x| Duration::try_from_secs_f32(x * scale).ok())
+}
+
+/// Waveform names, in the order of their numbers.
+pub const WAVEFORMS: [&str; 5] = ["sine", "square", "triangle", "sawtooth", "noise"];
+
+/// Parse a waveform by name or number, returning the 1-based waveform number.
+pub fn parse_waveform(value: &str) -> Option<f32> {
+    if let Ok(waveform) = value.parse::<u8>() {
+        return Some(waveform as f32);
+    }
+    parse_waveform_name(value)
+}
+
+/// Parse a waveform by name only, returning the 1-based waveform number.
+pub fn parse_waveform_name(value: &str) -> Option<f32> {
+    let value = value.to_lowercase();
+    WAVEFORMS
+        .iter()
+        .position(|x| *x == value)
+        .map(|x| x as f32 + 1.0)
+}
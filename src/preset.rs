@@ -0,0 +1,123 @@
+use color_eyre::eyre::{bail, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Saved state of all channels and the output.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    pub output: OutputPreset,
+    #[serde(rename = "channel")]
+    pub channels: Vec<ChannelPreset>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelPreset {
+    pub waveform: String,
+    pub frequency: f32,
+    pub amplitude: f32,
+    pub phase_offset: f32,
+    pub dc_offset: f32,
+    pub pan: f32,
+    pub output: bool,
+}
+
+impl Default for ChannelPreset {
+    fn default() -> Self {
+        Self {
+            waveform: "Sine".to_string(),
+            frequency: 440.0,
+            amplitude: 1.0,
+            phase_offset: 0.0,
+            dc_offset: 0.0,
+            pan: 0.0,
+            output: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputPreset {
+    pub volume: f32,
+    pub mute: bool,
+    /// Routing number, as used by the `Routing` parameter.
+    pub routing: usize,
+}
+
+impl Default for OutputPreset {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            mute: false,
+            routing: 0,
+        }
+    }
+}
+
+impl Preset {
+    /// Directory the presets are stored in.
+    pub fn directory() -> Option<PathBuf> {
+        dirs::data_dir().map(|x| x.join(env!("CARGO_PKG_NAME")).join("presets"))
+    }
+
+    /// File of a preset, names containing a `/` are file paths.
+    fn path(name: &str) -> Result<PathBuf> {
+        if name.contains('/') {
+            return Ok(PathBuf::from(name));
+        }
+        if name.is_empty() || name.contains('\\') || name.starts_with('.') {
+            bail!("Invalid preset name '{}'", name);
+        }
+        match Self::directory() {
+            Some(directory) => Ok(directory.join(format!("{}.toml", name))),
+            None => bail!("No data directory for presets"),
+        }
+    }
+
+    /// Names of the saved presets, sorted.
+    pub fn list() -> Result<Vec<String>> {
+        let Some(directory) = Self::directory() else {
+            return Ok(Vec::new());
+        };
+        if !directory.exists() {
+            return Ok(Vec::new());
+        }
+        let mut names: Vec<String> = std::fs::read_dir(&directory)?
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| x.extension().is_some_and(|x| x == "toml"))
+            .filter_map(|x| x.file_stem().map(|x| x.to_string_lossy().to_string()))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn load(name: &str) -> Result<Self> {
+        let path = Self::path(name)?;
+        let text = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Preset '{}' not found", name))?;
+        Self::parse(&text).wrap_err_with(|| format!("Invalid preset '{}'", name))
+    }
+
+    pub fn save(&self, name: &str) -> Result<()> {
+        let path = Self::path(name)?;
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        // write to a temporary file first so a failed save doesn't corrupt the preset
+        let temporary = path.with_extension("toml.tmp");
+        std::fs::write(&temporary, self.to_toml()?)?;
+        std::fs::rename(&temporary, &path)?;
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+}
@@ -0,0 +1,151 @@
+use crate::scope::format_time;
+use waveforms_rs::Waveform;
+
+/// Periods of the selected channel shown by default.
+pub const DEFAULT_PERIODS: f32 = 2.0;
+/// Magnification limits of the time axis.
+pub const ZOOM_MIN: f32 = 0.01;
+pub const ZOOM_MAX: f32 = 100.0;
+/// Longest time shown or skipped, in seconds.
+pub const DURATION_MAX: f32 = 1.0;
+/// Points drawn at most, longer windows are decimated.
+pub const PREVIEW_POINTS: usize = 1024;
+
+/// Setting changed by the `preview` command.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PreviewSetting {
+    /// Periods of the selected channel shown without zoom.
+    Periods(f32),
+    Zoom(f32),
+    /// Time skipped at the left edge, in seconds.
+    Pan(f32),
+    /// Back to the periods, without zoom or pan.
+    Reset,
+}
+
+/// Time window of the waveform preview, following the frequency of the selected channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preview {
+    pub periods: f32,
+    /// Magnification of the time axis, 1 showing `periods`.
+    pub zoom: f32,
+    /// Time skipped at the left edge, in seconds.
+    pub pan: f32,
+}
+
+impl Default for Preview {
+    fn default() -> Self {
+        Self {
+            periods: DEFAULT_PERIODS,
+            zoom: 1.0,
+            pan: 0.0,
+        }
+    }
+}
+
+impl Preview {
+    pub fn apply(&mut self, setting: PreviewSetting) -> Result<String, String> {
+        match setting {
+            PreviewSetting::Periods(periods) if periods > 0.0 => self.periods = periods,
+            PreviewSetting::Periods(_) => return Err("Periods must be positive".to_string()),
+            PreviewSetting::Zoom(zoom) => self.zoom = zoom.clamp(ZOOM_MIN, ZOOM_MAX),
+            PreviewSetting::Pan(pan) => self.pan = pan.clamp(0.0, DURATION_MAX),
+            PreviewSetting::Reset => {
+                self.zoom = 1.0;
+                self.pan = 0.0;
+            }
+        }
+        Ok(format!(
+            "Preview: {} periods, zoom {:.2}, pan {}",
+            self.periods,
+            self.zoom,
+            format_time(self.pan)
+        ))
+    }
+
+    /// Seconds shown for a channel at `frequency` Hz.
+    pub fn duration(&self, frequency: f32) -> f32 {
+        let period = match frequency > 0.0 {
+            true => 1.0 / frequency,
+            false => DURATION_MAX,
+        };
+        (self.periods * period / self.zoom).min(DURATION_MAX)
+    }
+
+    /// Samples skipped and shown at `sample_rate`, and the step between the points drawn.
+    pub fn samples(&self, frequency: f32, sample_rate: f32) -> (usize, usize, usize) {
+        let skip = (self.pan * sample_rate).round() as usize;
+        let samples = ((self.duration(frequency) * sample_rate).round() as usize).max(2);
+        let step = samples.div_ceil(PREVIEW_POINTS).max(1);
+        (skip, samples, step)
+    }
+
+    /// Zoom by a factor, above 1 to zoom in.
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.zoom = (self.zoom * factor).clamp(ZOOM_MIN, ZOOM_MAX);
+    }
+
+    /// Pan by a fraction of the window, positive to go later in time.
+    pub fn pan_by(&mut self, fraction: f32, frequency: f32) {
+        let pan = self.pan + fraction * self.duration(frequency);
+        self.pan = pan.clamp(0.0, DURATION_MAX);
+    }
+}
+
+/// Time and value of every `step` sample from `skip`, as in `Preview::samples`. Each point is
+/// generated alone by moving the phase offset to it, so the pan and the samples not drawn cost
+/// nothing. The phase offset is kept.
+pub fn trace(waveform: &mut Waveform, skip: usize, samples: usize, step: usize) -> Vec<(f64, f64)> {
+    let sample_rate = waveform.sample_rate() as f64;
+    let offset = waveform.phase_offset();
+    let cycles = waveform.frequency() as f64 / sample_rate;
+    let points = (skip..skip + samples)
+        .step_by(step)
+        .map(|i| {
+            waveform.reset();
+            waveform.set_phase_offset((offset as f64 + i as f64 * cycles).rem_euclid(1.0) as f32);
+            (i as f64 / sample_rate, waveform.process() as f64)
+        })
+        .collect();
+    waveform.set_phase_offset(offset);
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_samples() {
+        let mut preview = Preview::default();
+        // two periods of 1 kHz at 48 kHz
+        assert_eq!(preview.samples(1000.0, 48000.0), (0, 96, 1));
+        // slow channels are decimated and capped to a second
+        assert_eq!(preview.samples(20.0, 48000.0), (0, 4800, 5));
+        assert_eq!(preview.samples(0.5, 48000.0).1, 48000);
+
+        preview.zoom_by(2.0);
+        preview.pan_by(0.5, 1000.0);
+        assert_eq!(preview.samples(1000.0, 48000.0), (24, 48, 1));
+        preview.pan_by(-10.0, 1000.0);
+        assert_eq!(preview.pan, 0.0);
+        preview.apply(PreviewSetting::Reset).unwrap();
+        assert_eq!(preview.zoom, 1.0);
+        assert!(preview.apply(PreviewSetting::Periods(0.0)).is_err());
+    }
+
+    #[test]
+    fn test_preview_trace() {
+        let mut waveform = Waveform::new(48000.0, 1000.0);
+        waveform.set_phase_offset(0.25);
+        waveform.reset();
+        let generated: Vec<f64> = (0..400).map(|_| waveform.process() as f64).collect();
+        let points = trace(&mut waveform, 100, 300, 7);
+        assert_eq!(points.len(), 43);
+        for (i, (time, value)) in (100..400).step_by(7).zip(points) {
+            assert_eq!(time, i as f64 / 48000.0);
+            assert!((value - generated[i]).abs() < 1e-3);
+        }
+        assert_eq!(waveform.phase_offset(), 0.25);
+    }
+}
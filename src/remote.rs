@@ -0,0 +1,170 @@
+use crate::app::App;
+use crate::parameter::Parameter;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Work sent by a remote control thread, run on the application.
+type Job = Box<dyn FnOnce(&mut App) + Send>;
+
+/// Called when a job is sent, so the main loop runs it before its next tick.
+type Waker = Arc<Mutex<Option<Box<dyn Fn() + Send>>>>;
+
+/// Value of a channel parameter, or of the output when `channel` is `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParameterValue {
+    pub channel: Option<usize>,
+    pub parameter: Parameter,
+    pub value: f32,
+}
+
+/// Jobs of the remote control threads, run by the application on each tick so they never
+/// race with the user interface, and the subscribers to parameter changes.
+pub struct Remote {
+    sender: Sender<Job>,
+    receiver: Receiver<Job>,
+    waker: Waker,
+    subscribers: Vec<Sender<Vec<ParameterValue>>>,
+    /// Values last sent to the subscribers.
+    values: Vec<ParameterValue>,
+}
+
+impl Default for Remote {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            waker: Waker::default(),
+            subscribers: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl Remote {
+    /// Handle for a remote control thread to reach the application.
+    pub fn handle(&self) -> RemoteHandle {
+        RemoteHandle {
+            sender: self.sender.clone(),
+            waker: Arc::clone(&self.waker),
+        }
+    }
+
+    /// Wake the main loop when a job is sent, instead of waiting for the tick.
+    pub fn set_waker(&self, waker: impl Fn() + Send + 'static) {
+        *self.waker.lock().unwrap() = Some(Box::new(waker));
+    }
+
+    /// Jobs received since the last call.
+    pub fn pending(&self) -> Vec<Job> {
+        self.receiver.try_iter().collect()
+    }
+
+    /// Receive the values that changed since the current `values`, on each tick.
+    pub fn subscribe(&mut self, values: Vec<ParameterValue>) -> Receiver<Vec<ParameterValue>> {
+        if self.subscribers.is_empty() {
+            self.values = values;
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        !self.subscribers.is_empty()
+    }
+
+    /// Send the changed values to the subscribers, forgetting the ones that are gone.
+    pub fn notify(&mut self, values: Vec<ParameterValue>) {
+        let changes: Vec<ParameterValue> = values
+            .iter()
+            .filter(|x| !self.values.contains(x))
+            .copied()
+            .collect();
+        self.values = values;
+        if !changes.is_empty() {
+            self.subscribers.retain(|x| x.send(changes.clone()).is_ok());
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RemoteHandle {
+    sender: Sender<Job>,
+    waker: Waker,
+}
+
+impl RemoteHandle {
+    /// Run a function on the application at its next tick and wait for the result, `None`
+    /// once the application is gone.
+    pub fn call<T: Send + 'static>(
+        &self,
+        function: impl FnOnce(&mut App) -> T + Send + 'static,
+    ) -> Option<T> {
+        let (sender, receiver) = mpsc::channel();
+        self.run(move |app| {
+            let _ = sender.send(function(app));
+        })?;
+        receiver.recv().ok()
+    }
+
+    /// Run a function on the application at its next tick without waiting, `None` once the
+    /// application is gone.
+    pub fn run(&self, function: impl FnOnce(&mut App) + Send + 'static) -> Option<()> {
+        self.sender.send(Box::new(function)).ok()?;
+        if let Some(waker) = self.waker.lock().unwrap().as_ref() {
+            waker();
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameter::Parameter;
+
+    #[test]
+    fn test_remote_call() {
+        let mut app = App::with_channels(2);
+        let remote = app.remote.handle();
+        let thread = std::thread::spawn(move || {
+            remote.call(|app| app.set_channel_parameter_value(1, Parameter::Frequency, 1000.0));
+            remote.call(|app| app.channel_parameter_value(1, Parameter::Frequency))
+        });
+        while !thread.is_finished() {
+            app.tick();
+        }
+        assert_eq!(thread.join().unwrap(), Some(Some(1000.0)));
+    }
+
+    #[test]
+    fn test_remote_subscribe() {
+        let mut app = App::with_channels(2);
+        let changes = app.remote.subscribe(app.parameter_values());
+        app.tick();
+        assert!(changes.try_recv().is_err());
+        app.set_channel_parameter_value(0, Parameter::Amplitude, 0.5);
+        app.set_channel_parameter_value(0, Parameter::Volume, 0.25);
+        app.tick();
+        assert_eq!(
+            changes.try_recv().unwrap(),
+            vec![
+                ParameterValue {
+                    channel: Some(0),
+                    parameter: Parameter::Amplitude,
+                    value: 0.5
+                },
+                ParameterValue {
+                    channel: None,
+                    parameter: Parameter::Volume,
+                    value: 0.25
+                }
+            ]
+        );
+        drop(changes);
+        app.set_channel_parameter_value(0, Parameter::Amplitude, 1.0);
+        app.tick();
+        assert!(!app.remote.has_subscribers());
+    }
+}
@@ -0,0 +1,461 @@
+use crate::app::App;
+use crate::command::{self, find_command};
+use crate::parameter::Parameter;
+use crate::remote::{ParameterValue, RemoteHandle};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::sync::mpsc::Receiver;
+#[cfg(unix)]
+use {
+    color_eyre::eyre::{bail, Result, WrapErr},
+    std::io::{BufRead, BufReader, Write},
+    std::net::Shutdown,
+    std::os::unix::net::{UnixListener, UnixStream},
+    std::path::{Path, PathBuf},
+    std::sync::{Arc, Mutex},
+};
+
+/// JSON-RPC error codes.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// A valid request that failed, like loading a missing preset.
+pub const APPLICATION_ERROR: i64 = -32000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    /// Missing for notifications, which get no response.
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Outcome of a request line.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// Response to send back, `None` for notifications.
+    pub response: Option<String>,
+    /// Parameter changes to forward after a `subscribe`.
+    pub subscription: Option<Receiver<Vec<ParameterValue>>>,
+}
+
+/// Execute a JSON-RPC 2.0 request line.
+pub fn execute(app: &mut App, line: &str) -> Outcome {
+    let request = match serde_json::from_str::<Value>(line) {
+        Ok(request) => request,
+        Err(error) => return error_outcome(PARSE_ERROR, &error.to_string()),
+    };
+    let request: Request = match serde_json::from_value(request) {
+        Ok(request) => request,
+        Err(error) => return error_outcome(INVALID_REQUEST, &error.to_string()),
+    };
+    let mut outcome = Outcome::default();
+    let result = match request.method.as_str() {
+        "subscribe" => {
+            let values = app.parameter_values();
+            outcome.subscription = Some(app.remote.subscribe(values));
+            Ok(json!(true))
+        }
+        method => call(app, method, &request.params),
+    };
+    outcome.response = request.id.map(|id| response(id, result));
+    outcome
+}
+
+fn error_outcome(code: i64, message: &str) -> Outcome {
+    Outcome {
+        response: Some(response(Value::Null, Err(RpcError::new(code, message)))),
+        subscription: None,
+    }
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> String {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(error) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": error.code, "message": error.message},
+        }),
+    }
+    .to_string()
+}
+
+/// Notification of a changed parameter sent to the subscribers.
+pub fn notification(value: &ParameterValue) -> String {
+    json!({"jsonrpc": "2.0", "method": "changed", "params": parameter_json(value)}).to_string()
+}
+
+/// Run a method other than `subscribe`, also used by the HTTP API.
+pub fn call(app: &mut App, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        "set" => {
+            let parameter =
+                parameter(params)?.ok_or_else(|| invalid_params("missing parameter"))?;
+            let channel = channel(app, params)?;
+            let value = match params.get("value") {
+                Some(Value::String(value)) => value.clone(),
+                Some(Value::Number(value)) => value.to_string(),
+                Some(Value::Bool(value)) => if *value { "on" } else { "off" }.to_string(),
+                _ => return Err(invalid_params("missing value")),
+            };
+            let name = command::parameter_name(parameter);
+            let line = match channel {
+                Some(channel) if !parameter.is_output() => {
+                    format!("ch{}:{} {}", channel + 1, name, value)
+                }
+                _ => format!("{} {}", name, value),
+            };
+            let commands = command::parse(&line).map_err(|x| invalid_params(&x.to_string()))?;
+            checked(app, |app| {
+                for command in commands.iter() {
+                    app.run_command(command);
+                }
+            })?;
+            Ok(get(app, channel, Some(parameter)))
+        }
+        "get" => {
+            let parameter = parameter(params)?;
+            let channel = channel(app, params)?;
+            Ok(get(app, channel, parameter))
+        }
+        "list_parameters" => {
+            let channel = Parameter::CHANNEL.into_iter().map(|x| (x, "channel"));
+            let output = Parameter::OUTPUT.into_iter().map(|x| (x, "output"));
+            let parameters = channel
+                .chain(output)
+                .filter_map(|(parameter, scope)| {
+                    let name = command::parameter_name(parameter);
+                    let command = find_command(name)?;
+                    Some(json!({
+                        "name": name,
+                        "scope": scope,
+                        "unit": parameter.unit(),
+                        "description": command.description,
+                    }))
+                })
+                .collect();
+            Ok(Value::Array(parameters))
+        }
+        "load_preset" => {
+            let name = params
+                .get("name")
+                .and_then(|x| x.as_str())
+                .ok_or_else(|| invalid_params("missing name"))?;
+            checked(app, |app| app.load_preset(name))?;
+            Ok(json!(true))
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, "method not found")),
+    }
+}
+
+fn invalid_params(message: &str) -> RpcError {
+    RpcError::new(INVALID_PARAMS, message)
+}
+
+/// Run an action on the application, failing with the warning it set. The warning shown by
+/// the interface is kept.
+fn checked(app: &mut App, action: impl FnOnce(&mut App)) -> Result<(), RpcError> {
+    let previous = app.warning.take();
+    action(app);
+    match std::mem::replace(&mut app.warning, previous) {
+        Some(warning) => Err(RpcError::new(APPLICATION_ERROR, &warning)),
+        None => Ok(()),
+    }
+}
+
+/// The `parameter` param, by command name.
+fn parameter(params: &Value) -> Result<Option<Parameter>, RpcError> {
+    let Some(name) = params.get("parameter") else {
+        return Ok(None);
+    };
+    name.as_str()
+        .and_then(command::command_parameter)
+        .map(Some)
+        .ok_or_else(|| invalid_params(&format!("unknown parameter {}", name)))
+}
+
+/// The `channel` param, a channel number starting at 1.
+fn channel(app: &App, params: &Value) -> Result<Option<usize>, RpcError> {
+    let Some(channel) = params.get("channel") else {
+        return Ok(None);
+    };
+    match channel.as_u64().map(|x| x as usize) {
+        Some(channel) if (1..=app.channels()).contains(&channel) => Ok(Some(channel - 1)),
+        _ => Err(invalid_params(&format!("invalid channel {}", channel))),
+    }
+}
+
+/// Value of a parameter, all the parameters of a channel, or everything.
+pub fn get(app: &App, channel: Option<usize>, parameter: Option<Parameter>) -> Value {
+    let values = app.parameter_values();
+    let selected = channel.unwrap_or(app.selected_waveform);
+    let object = |values: &mut dyn Iterator<Item = &ParameterValue>| {
+        let map: Map<String, Value> = values
+            .map(|x| {
+                let name = command::parameter_name(x.parameter);
+                (name.to_string(), json_value(x.parameter, x.value))
+            })
+            .collect();
+        Value::Object(map)
+    };
+    match (channel, parameter) {
+        (_, Some(parameter)) => values
+            .iter()
+            .find(|x| {
+                x.parameter == parameter && (parameter.is_output() || x.channel == Some(selected))
+            })
+            .map(|x| json_value(parameter, x.value))
+            .unwrap_or(Value::Null),
+        (Some(channel), None) => object(&mut values.iter().filter(|x| x.channel == Some(channel))),
+        (None, None) => {
+            let channels = (0..app.channels())
+                .map(|channel| object(&mut values.iter().filter(|x| x.channel == Some(channel))))
+                .collect();
+            json!({
+                "channels": Value::Array(channels),
+                "output": object(&mut values.iter().filter(|x| x.channel.is_none())),
+            })
+        }
+    }
+}
+
+/// Waveform and routing names, on/off as booleans, and numbers.
+fn json_value(parameter: Parameter, value: f32) -> Value {
+    match parameter {
+        Parameter::Waveform | Parameter::Routing => json!(command::format_value(parameter, value)),
+        Parameter::Output | Parameter::Mute => json!(value > 0.0),
+        // through the shortest text so 0.1 isn't sent as 0.10000000149011612
+        _ => value
+            .to_string()
+            .parse::<f64>()
+            .map(Value::from)
+            .unwrap_or(Value::Null),
+    }
+}
+
+/// Channel, starting at 1, parameter name and value of a changed parameter.
+pub fn parameter_json(value: &ParameterValue) -> Value {
+    let mut params = Map::new();
+    if let Some(channel) = value.channel {
+        params.insert("channel".to_string(), json!(channel + 1));
+    }
+    let name = command::parameter_name(value.parameter);
+    params.insert("parameter".to_string(), json!(name));
+    params.insert(
+        "value".to_string(),
+        json_value(value.parameter, value.value),
+    );
+    Value::Object(params)
+}
+
+#[cfg(unix)]
+/// Socket accepting JSON-RPC connections, removed when dropped.
+pub struct Listener {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+/// Accept connections on a Unix socket, one request per line. The requests are run by the
+/// application on its next tick.
+pub fn listen(path: &Path, remote: RemoteHandle) -> Result<Listener> {
+    if path.exists() {
+        // left over by an instance that didn't exit cleanly, unless it still answers
+        if UnixStream::connect(path).is_ok() {
+            bail!("Socket {} is used by another instance", path.display());
+        }
+        std::fs::remove_file(path)
+            .wrap_err_with(|| format!("Failed to remove socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(path)
+        .wrap_err_with(|| format!("Failed to listen on socket {}", path.display()))?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().filter_map(|x| x.ok()) {
+            let remote = remote.clone();
+            std::thread::spawn(move || serve(stream, remote));
+        }
+    });
+    Ok(Listener {
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(unix)]
+fn serve(stream: UnixStream, remote: RemoteHandle) -> std::io::Result<()> {
+    // shared with the thread forwarding the changes after a subscribe
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut subscribed = false;
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some(outcome) = remote.call(move |app| execute(app, &line)) else {
+            break;
+        };
+        if let Some(response) = outcome.response {
+            writeln!(writer.lock().unwrap(), "{}", response)?;
+        }
+        match outcome.subscription {
+            Some(changes) if !subscribed => {
+                subscribed = true;
+                let writer = Arc::clone(&writer);
+                std::thread::spawn(move || forward(changes, writer));
+            }
+            _ => {}
+        }
+    }
+    // ends the forwarding thread at its next write
+    stream.shutdown(Shutdown::Both)
+}
+
+#[cfg(unix)]
+fn forward(changes: Receiver<Vec<ParameterValue>>, writer: Arc<Mutex<UnixStream>>) {
+    for values in changes {
+        let mut writer = writer.lock().unwrap();
+        for value in values.iter() {
+            if writeln!(writer, "{}", notification(value)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(app: &mut App, line: &str) -> Value {
+        let response = execute(app, line).response.unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[test]
+    fn test_rpc_execute() {
+        let mut app = App::with_channels(2);
+        let response = request(
+            &mut app,
+            r#"{"jsonrpc":"2.0","id":1,"method":"set","params":{"channel":2,"parameter":"freq","value":"1k"}}"#,
+        );
+        assert_eq!(
+            response,
+            json!({"jsonrpc": "2.0", "id": 1, "result": 1000.0})
+        );
+        assert_eq!(
+            app.channel_parameter_value(1, Parameter::Frequency),
+            Some(1000.0)
+        );
+        let response = request(
+            &mut app,
+            r#"{"jsonrpc":"2.0","id":2,"method":"get","params":{"channel":2}}"#,
+        );
+        assert_eq!(response["result"]["wave"], json!("sine"));
+        assert_eq!(response["result"]["out"], json!(true));
+        let response = request(&mut app, r#"{"jsonrpc":"2.0","id":3,"method":"get"}"#);
+        assert_eq!(response["result"]["output"]["vol"], json!(1.0));
+        let response = request(
+            &mut app,
+            r#"{"jsonrpc":"2.0","id":4,"method":"list_parameters"}"#,
+        );
+        assert_eq!(response["result"][1]["unit"], json!("Hz"));
+
+        let error = |app: &mut App, line: &str| request(app, line)["error"]["code"].clone();
+        assert_eq!(error(&mut app, "{"), json!(PARSE_ERROR));
+        assert_eq!(error(&mut app, r#"{"id":5}"#), json!(INVALID_REQUEST));
+        assert_eq!(
+            error(&mut app, r#"{"id":6,"method":"play"}"#),
+            json!(METHOD_NOT_FOUND)
+        );
+        assert_eq!(
+            error(
+                &mut app,
+                r#"{"id":7,"method":"set","params":{"parameter":"amp","value":"loud"}}"#
+            ),
+            json!(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error(
+                &mut app,
+                r#"{"id":8,"method":"set","params":{"channel":3,"parameter":"amp","value":1}}"#
+            ),
+            json!(INVALID_PARAMS)
+        );
+        // notifications get no response
+        assert!(execute(
+            &mut app,
+            r#"{"method":"set","params":{"parameter":"vol","value":0.5}}"#
+        )
+        .response
+        .is_none());
+        assert_eq!(app.channel_parameter_value(0, Parameter::Volume), Some(0.5));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_rpc_listen() {
+        let mut app = App::with_channels(2);
+        let path =
+            std::env::temp_dir().join(format!("signal-generator-{}.sock", std::process::id()));
+        let listener = listen(&path, app.remote.handle()).unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = UnixStream::connect(path).unwrap();
+            stream
+                .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+                .unwrap();
+            let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+            writeln!(stream, r#"{{"jsonrpc":"2.0","id":1,"method":"subscribe"}}"#).unwrap();
+            let subscribed = lines.next().unwrap().unwrap();
+            writeln!(
+                stream,
+                r#"{{"jsonrpc":"2.0","id":2,"method":"set","params":{{"parameter":"wave","value":"square"}}}}"#
+            )
+            .unwrap();
+            let mut replies = vec![subscribed];
+            replies.extend(lines.take(2).map(|x| x.unwrap()));
+            replies
+        });
+        while !client.is_finished() {
+            app.tick();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let mut replies = client.join().unwrap();
+        replies.sort();
+        assert_eq!(
+            replies,
+            vec![
+                r#"{"id":1,"jsonrpc":"2.0","result":true}"#,
+                r#"{"id":2,"jsonrpc":"2.0","result":"square"}"#,
+                r#"{"jsonrpc":"2.0","method":"changed","params":{"channel":1,"parameter":"wave","value":"square"}}"#,
+            ]
+        );
+        drop(listener);
+        assert!(!std::env::temp_dir()
+            .join(format!("signal-generator-{}.sock", std::process::id()))
+            .exists());
+    }
+}
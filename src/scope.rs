@@ -0,0 +1,334 @@
+use crate::audio::SampleTap;
+use std::fmt;
+
+/// Horizontal divisions of the screen.
+pub const DIVISIONS_X: f32 = 10.0;
+/// Vertical divisions of the screen, half above zero.
+pub const DIVISIONS_Y: f32 = 8.0;
+/// Time per division limits in seconds, the longest screen is half the tap.
+pub const TIMEBASE_MIN: f32 = 10e-6;
+pub const TIMEBASE_MAX: f32 = 50e-3;
+/// Volts per division limits, full scale being 1.
+pub const SCALE_MIN: f32 = 1e-3;
+pub const SCALE_MAX: f32 = 10.0;
+
+/// What the trigger does when no edge is found.
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum TriggerMode {
+    /// Show the latest samples untriggered.
+    #[default]
+    Auto,
+    /// Keep the last triggered trace.
+    Normal,
+    /// Capture the next edge once, then stop.
+    Single,
+}
+
+impl fmt::Display for TriggerMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TriggerMode::Auto => write!(f, "Auto"),
+            TriggerMode::Normal => write!(f, "Normal"),
+            TriggerMode::Single => write!(f, "Single"),
+        }
+    }
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum Slope {
+    #[default]
+    Rising,
+    Falling,
+}
+
+impl fmt::Display for Slope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Slope::Rising => write!(f, "↑"),
+            Slope::Falling => write!(f, "↓"),
+        }
+    }
+}
+
+/// State of the acquisition, shown in the title.
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum Acquisition {
+    /// Waiting for the first samples or edge.
+    #[default]
+    Waiting,
+    Triggered,
+    /// Free running in auto mode.
+    Untriggered,
+    /// Single capture done.
+    Stopped,
+}
+
+impl fmt::Display for Acquisition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Acquisition::Waiting => write!(f, "Wait"),
+            Acquisition::Triggered => write!(f, "Trig'd"),
+            Acquisition::Untriggered => write!(f, "Auto"),
+            Acquisition::Stopped => write!(f, "Stop"),
+        }
+    }
+}
+
+/// Setting changed by the `scope` command.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ScopeSetting {
+    /// Time per division in seconds.
+    Timebase(f32),
+    /// Volts per division.
+    Scale(f32),
+    /// Show an output channel, toggles when `None`.
+    Trace(usize, Option<bool>),
+    Mode(TriggerMode),
+    Slope(Slope),
+    Level(f32),
+    /// Output channel the trigger looks at.
+    Source(usize),
+}
+
+/// Oscilloscope of the output samples, with the trigger point at the left edge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    /// Time per division in seconds.
+    pub timebase: f32,
+    /// Volts per division.
+    pub scale: f32,
+    /// Shown output channels, all of them until the tap is read.
+    pub enabled: Vec<bool>,
+    pub mode: TriggerMode,
+    pub slope: Slope,
+    pub level: f32,
+    pub source: usize,
+    pub acquisition: Acquisition,
+    /// Samples of each output channel on screen.
+    pub trace: Vec<Vec<f32>>,
+    /// Frames written to the tap when a single capture was armed, older edges are ignored.
+    armed: usize,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self {
+            timebase: 1e-3,
+            scale: 0.25,
+            enabled: Vec::new(),
+            mode: TriggerMode::default(),
+            slope: Slope::default(),
+            level: 0.0,
+            source: 0,
+            acquisition: Acquisition::default(),
+            trace: Vec::new(),
+            armed: 0,
+        }
+    }
+}
+
+impl Scope {
+    /// Change the timebase, scale, trigger or traces, the timebase and scale are clamped to
+    /// their ranges. Changing the mode arms a single capture again. Warns of an output the tap
+    /// doesn't have.
+    pub fn apply(&mut self, setting: ScopeSetting, tap: &SampleTap) -> Result<String, String> {
+        let channels = tap.channels();
+        let channel = |x: usize| match x < channels {
+            true => Ok(x),
+            false => Err("Invalid output channel".to_string()),
+        };
+        match setting {
+            ScopeSetting::Timebase(timebase) => {
+                self.timebase = timebase.clamp(TIMEBASE_MIN, TIMEBASE_MAX);
+            }
+            ScopeSetting::Scale(scale) => self.scale = scale.clamp(SCALE_MIN, SCALE_MAX),
+            ScopeSetting::Trace(output, enabled) => {
+                channel(output)?;
+                self.enabled.resize(channels, true);
+                self.enabled[output] = enabled.unwrap_or(!self.enabled[output]);
+            }
+            ScopeSetting::Mode(mode) => {
+                self.mode = mode;
+                // a single capture is armed again
+                self.armed = tap.written();
+                self.acquisition = Acquisition::Waiting;
+            }
+            ScopeSetting::Slope(slope) => self.slope = slope,
+            ScopeSetting::Level(level) => self.level = level,
+            ScopeSetting::Source(output) => self.source = channel(output)?,
+        }
+        Ok(format!("Scope: {}", self.describe()))
+    }
+
+    /// Settings as shown in the title, like `1ms/div 0.25V/div Auto ch1 ↑ 0.00`.
+    pub fn describe(&self) -> String {
+        format!(
+            "{}/div {}V/div {} ch{} {} {:.2}",
+            format_time(self.timebase),
+            self.scale,
+            self.mode,
+            self.source + 1,
+            self.slope,
+            self.level
+        )
+    }
+
+    /// Frames on screen.
+    pub fn frames(&self, sample_rate: f32) -> usize {
+        (self.timebase * DIVISIONS_X * sample_rate).round() as usize
+    }
+
+    /// Update the trace from the latest samples of the tap.
+    pub fn capture(&mut self, tap: &SampleTap, sample_rate: f32) {
+        let frames = self.frames(sample_rate);
+        if frames < 2 {
+            return;
+        }
+        self.enabled.resize(tap.channels(), true);
+        if self.acquisition == Acquisition::Stopped {
+            return;
+        }
+        // twice the screen, so an edge is found in any signal slower than the screen
+        let (written, samples) = tap.read(frames * 2);
+        let length = samples.first().map(|x| x.len()).unwrap_or(0);
+        if length < frames {
+            return;
+        }
+        // the first frame read was written after this many frames
+        let start = written - length;
+        let edge = samples
+            .get(self.source)
+            .and_then(|x| self.find_edge(x, frames))
+            .filter(|x| self.mode != TriggerMode::Single || start + x > self.armed);
+        match (edge, self.mode) {
+            (Some(edge), mode) => {
+                self.trace = samples
+                    .iter()
+                    .map(|x| x[edge..edge + frames].to_vec())
+                    .collect();
+                self.acquisition = match mode {
+                    TriggerMode::Single => Acquisition::Stopped,
+                    _ => Acquisition::Triggered,
+                };
+            }
+            (None, TriggerMode::Auto) => {
+                self.trace = samples
+                    .iter()
+                    .map(|x| x[length - frames..].to_vec())
+                    .collect();
+                self.acquisition = Acquisition::Untriggered;
+            }
+            // the last trace stays on screen
+            (None, _) => self.acquisition = Acquisition::Waiting,
+        }
+    }
+
+    /// Index of the latest edge crossing the level, followed by a full screen of samples.
+    fn find_edge(&self, samples: &[f32], frames: usize) -> Option<usize> {
+        (1..=samples.len() - frames).rev().find(|&i| {
+            let (previous, current) = (samples[i - 1], samples[i]);
+            match self.slope {
+                Slope::Rising => previous < self.level && current >= self.level,
+                Slope::Falling => previous > self.level && current <= self.level,
+            }
+        })
+    }
+}
+
+/// Time with the unit it reads best in, like `500µs` or `2ms`.
+pub fn format_time(seconds: f32) -> String {
+    let (value, unit) = match seconds.abs() {
+        x if x >= 1.0 || x == 0.0 => (seconds, "s"),
+        x if x >= 1e-3 => (seconds * 1e3, "ms"),
+        _ => (seconds * 1e6, "µs"),
+    };
+    // up to two decimals, without trailing zeros
+    let value = format!("{:.2}", value);
+    let value = value.trim_end_matches('0').trim_end_matches('.');
+    format!("{}{}", value, unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tap of two channels with a ramp from -1 to 1 every 10 frames, and its negation.
+    fn tap(frames: usize) -> SampleTap {
+        let tap = SampleTap::new(2, 100);
+        let data: Vec<f32> = (0..frames)
+            .flat_map(|i| {
+                let value = (i % 10) as f32 / 4.5 - 1.0;
+                [value, -value]
+            })
+            .collect();
+        tap.write(&data);
+        tap
+    }
+
+    #[test]
+    fn test_scope_tap() {
+        let tap = tap(250);
+        assert_eq!(tap.written(), 250);
+        let (written, samples) = tap.read(3);
+        assert_eq!(written, 250);
+        assert_eq!(samples[0], vec![(7.0 / 4.5) - 1.0, (8.0 / 4.5) - 1.0, 1.0]);
+        assert_eq!(samples[1][2], -1.0);
+        // no more than the capacity
+        assert_eq!(tap.read(1000).1[0].len(), 100);
+    }
+
+    #[test]
+    fn test_scope_trigger() {
+        let mut scope = Scope::default();
+        let tap = tap(250);
+        // 20 frames on screen at 1 kHz
+        scope.capture(&tap, 2000.0);
+        assert_eq!(scope.acquisition, Acquisition::Triggered);
+        assert_eq!(scope.trace[0].len(), 20);
+        assert!(scope.trace[0][0] >= 0.0 && scope.trace[0][0] < 0.25);
+
+        scope
+            .apply(ScopeSetting::Slope(Slope::Falling), &tap)
+            .unwrap();
+        scope.apply(ScopeSetting::Level(0.5), &tap).unwrap();
+        scope.capture(&tap, 2000.0);
+        // the ramp only falls from 1 to -1
+        assert_eq!(scope.trace[0][0], -1.0);
+
+        // no edge above full scale
+        scope.apply(ScopeSetting::Level(2.0), &tap).unwrap();
+        scope.capture(&tap, 2000.0);
+        assert_eq!(scope.acquisition, Acquisition::Untriggered);
+        scope
+            .apply(ScopeSetting::Mode(TriggerMode::Normal), &tap)
+            .unwrap();
+        scope.capture(&tap, 2000.0);
+        assert_eq!(scope.acquisition, Acquisition::Waiting);
+
+        // a single capture waits for new samples, then stops
+        scope.apply(ScopeSetting::Level(0.0), &tap).unwrap();
+        scope
+            .apply(ScopeSetting::Mode(TriggerMode::Single), &tap)
+            .unwrap();
+        scope.capture(&tap, 2000.0);
+        assert_eq!(scope.acquisition, Acquisition::Waiting);
+        let data: Vec<f32> = (0..30)
+            .flat_map(|i| [(i % 10) as f32 / 4.5 - 1.0, 0.0])
+            .collect();
+        tap.write(&data);
+        scope.capture(&tap, 2000.0);
+        assert_eq!(scope.acquisition, Acquisition::Stopped);
+
+        assert!(scope.apply(ScopeSetting::Source(2), &tap).is_err());
+        scope.apply(ScopeSetting::Trace(1, None), &tap).unwrap();
+        assert_eq!(scope.enabled, vec![true, false]);
+    }
+
+    #[test]
+    fn test_scope_format_time() {
+        assert_eq!(format_time(1e-3), "1ms");
+        assert_eq!(format_time(0.5e-3), "500µs");
+        assert_eq!(format_time(2.5e-3), "2.5ms");
+        assert_eq!(format_time(1.0), "1s");
+    }
+}
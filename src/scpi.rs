@@ -0,0 +1,490 @@
+use crate::app::App;
+use crate::parameter::Parameter;
+use crate::remote::RemoteHandle;
+use color_eyre::eyre::{Result, WrapErr};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+/// Reply to `*IDN?`: manufacturer, model, serial number and firmware version.
+const IDENTIFICATION: &str = concat!(
+    "ghztomash,",
+    env!("CARGO_PKG_NAME"),
+    ",0,",
+    env!("CARGO_PKG_VERSION")
+);
+
+/// Errors kept by a connection until read with `SYSTem:ERRor?`.
+const ERROR_QUEUE_SIZE: usize = 16;
+
+/// Arguments of `FUNCtion`, in the order of the waveform numbers.
+const FUNCTIONS: [&str; 5] = ["SINusoid", "SQUare", "TRIangle", "RAMP", "NOISe"];
+
+/// Error reported by `SYSTem:ERRor?`, with the standard SCPI error codes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScpiError {
+    pub code: i32,
+    pub description: String,
+}
+
+impl ScpiError {
+    fn new(code: i32, description: &str) -> Self {
+        Self {
+            code,
+            description: description.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ScpiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},\"{}\"", self.code, self.description)
+    }
+}
+
+/// A SCPI command, channels starting at 0.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scpi {
+    /// `*IDN?`
+    Identify,
+    /// `*RST`
+    Reset,
+    /// `*CLS`
+    Clear,
+    /// `*OPC?`
+    OperationComplete,
+    /// `*WAI`
+    Wait,
+    /// `SYSTem:ERRor?`
+    Error,
+    /// `SYSTem:VERSion?`
+    Version,
+    Set {
+        channel: usize,
+        parameter: Parameter,
+        value: f32,
+    },
+    Query {
+        channel: usize,
+        parameter: Parameter,
+    },
+}
+
+/// State of a connection: its error queue, and the path of the last header which the
+/// headers following a `;` are relative to.
+#[derive(Debug, Default)]
+pub struct Session {
+    errors: VecDeque<ScpiError>,
+    branch: Vec<String>,
+}
+
+impl Session {
+    /// Execute a line of `;` separated commands, returning the replies of its queries
+    /// joined by `;`.
+    pub fn execute(&mut self, app: &mut App, line: &str) -> Option<String> {
+        // each line starts from the root
+        self.branch.clear();
+        let mut replies = Vec::new();
+        for message in line.split(';').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            match self.parse(message).and_then(|x| self.run(app, x)) {
+                Ok(Some(reply)) => replies.push(reply),
+                Ok(None) => {}
+                Err(error) => self.push_error(error),
+            }
+        }
+        (!replies.is_empty()).then(|| replies.join(";"))
+    }
+
+    fn push_error(&mut self, error: ScpiError) {
+        if self.errors.len() + 1 < ERROR_QUEUE_SIZE {
+            self.errors.push_back(error);
+        } else if self.errors.len() + 1 == ERROR_QUEUE_SIZE {
+            self.errors
+                .push_back(ScpiError::new(-350, "Queue overflow"));
+        }
+    }
+
+    pub fn parse(&mut self, message: &str) -> Result<Scpi, ScpiError> {
+        let (header, argument) = match message.split_once(char::is_whitespace) {
+            Some((header, argument)) => (header, Some(argument.trim())),
+            None => (message, None),
+        };
+        let (header, query) = match header.strip_suffix('?') {
+            Some(header) => (header, true),
+            None => (header, false),
+        };
+        if let Some(name) = header.strip_prefix('*') {
+            return parse_common(name, query, argument);
+        }
+        let nodes: Vec<String> = match header.strip_prefix(':') {
+            Some(header) => header.split(':').map(|x| x.to_uppercase()).collect(),
+            None => self
+                .branch
+                .iter()
+                .cloned()
+                .chain(header.split(':').map(|x| x.to_uppercase()))
+                .collect(),
+        };
+        self.branch = nodes[..nodes.len() - 1].to_vec();
+        parse_command(&nodes, query, argument)
+    }
+
+    fn run(&mut self, app: &mut App, command: Scpi) -> Result<Option<String>, ScpiError> {
+        let reply = match command {
+            Scpi::Identify => IDENTIFICATION.to_string(),
+            Scpi::Reset => {
+                let preset = App::with_channels(app.channels()).preset();
+                app.apply_preset(&preset)
+                    .map_err(|error| ScpiError::new(-300, &error.to_string()))?;
+                return Ok(None);
+            }
+            Scpi::Clear => {
+                self.errors.clear();
+                return Ok(None);
+            }
+            Scpi::OperationComplete => "1".to_string(),
+            Scpi::Wait => return Ok(None),
+            Scpi::Error => self
+                .errors
+                .pop_front()
+                .unwrap_or(ScpiError::new(0, "No error"))
+                .to_string(),
+            Scpi::Version => "1999.0".to_string(),
+            Scpi::Set { channel, .. } | Scpi::Query { channel, .. }
+                if channel >= app.channels() =>
+            {
+                return Err(ScpiError::new(-114, "Header suffix out of range"));
+            }
+            Scpi::Set {
+                channel,
+                parameter,
+                value,
+            } => {
+                // keep the warning shown by the interface, reporting the new one here
+                let previous = app.warning.take();
+                app.set_channel_parameter_value(channel, parameter, value);
+                return match std::mem::replace(&mut app.warning, previous) {
+                    Some(warning) => Err(ScpiError::new(
+                        -222,
+                        &format!("Data out of range;{}", warning),
+                    )),
+                    None => Ok(None),
+                };
+            }
+            Scpi::Query { channel, parameter } => {
+                let value = app
+                    .channel_parameter_value(channel, parameter)
+                    .ok_or_else(|| ScpiError::new(-113, "Undefined header"))?;
+                format_value(parameter, value)
+            }
+        };
+        Ok(Some(reply))
+    }
+}
+
+/// Whether a header node is the short form, the upper case part, or the long form of a
+/// keyword like `FREQuency`.
+fn is(node: &str, keyword: &str) -> bool {
+    let short: String = keyword.chars().take_while(|x| x.is_uppercase()).collect();
+    node == short || node == keyword.to_uppercase()
+}
+
+/// Whether all the nodes are among the optional keywords.
+fn optional(nodes: &[(String, Option<usize>)], keywords: &[&str]) -> bool {
+    nodes
+        .iter()
+        .all(|(node, _)| keywords.iter().any(|x| is(node, x)))
+}
+
+/// Split the numeric suffix of a header node, like the channel of `SOUR2`.
+fn split_suffix(node: &str) -> (String, Option<usize>) {
+    let name = node.trim_end_matches(|x: char| x.is_ascii_digit());
+    (name.to_string(), node[name.len()..].parse().ok())
+}
+
+fn parse_common(name: &str, query: bool, argument: Option<&str>) -> Result<Scpi, ScpiError> {
+    let command = match (name.to_uppercase().as_str(), query) {
+        ("IDN", true) => Scpi::Identify,
+        ("RST", false) => Scpi::Reset,
+        ("CLS", false) => Scpi::Clear,
+        ("OPC", true) => Scpi::OperationComplete,
+        ("WAI", false) => Scpi::Wait,
+        _ => return Err(ScpiError::new(-113, "Undefined header")),
+    };
+    match argument {
+        Some(_) => Err(ScpiError::new(-108, "Parameter not allowed")),
+        None => Ok(command),
+    }
+}
+
+/// Parse the header nodes and the argument of a command, the channel suffix defaulting to 1.
+fn parse_command(nodes: &[String], query: bool, argument: Option<&str>) -> Result<Scpi, ScpiError> {
+    let undefined = ScpiError::new(-113, "Undefined header");
+    let nodes: Vec<(String, Option<usize>)> = nodes.iter().map(|x| split_suffix(x)).collect();
+    let (suffix, parameter) = match nodes.as_slice() {
+        [(system, _), rest @ ..] if is(system, "SYSTem") => {
+            return match rest {
+                [(error, _), next @ ..]
+                    if query && is(error, "ERRor") && optional(next, &["NEXT"]) =>
+                {
+                    Ok(Scpi::Error)
+                }
+                [(version, _)] if query && is(version, "VERSion") => Ok(Scpi::Version),
+                _ => Err(undefined),
+            };
+        }
+        [(output, suffix), state @ ..] if is(output, "OUTPut") && optional(state, &["STATe"]) => {
+            (*suffix, Parameter::Output)
+        }
+        [(source, suffix), path @ ..] if is(source, "SOURce") => {
+            (*suffix, source_parameter(path).ok_or(undefined)?)
+        }
+        // the source node is optional
+        path => (None, source_parameter(path).ok_or(undefined)?),
+    };
+    let channel = match suffix.unwrap_or(1) {
+        0 => return Err(ScpiError::new(-114, "Header suffix out of range")),
+        channel => channel - 1,
+    };
+    if query {
+        return match argument {
+            Some(_) => Err(ScpiError::new(-108, "Parameter not allowed")),
+            None => Ok(Scpi::Query { channel, parameter }),
+        };
+    }
+    let argument = argument.ok_or_else(|| ScpiError::new(-109, "Missing parameter"))?;
+    Ok(Scpi::Set {
+        channel,
+        parameter,
+        value: parse_argument(parameter, argument)?,
+    })
+}
+
+/// Parameter of the nodes following `SOURce`.
+fn source_parameter(path: &[(String, Option<usize>)]) -> Option<Parameter> {
+    let parameter = match path {
+        [(node, _), rest @ ..] if is(node, "FREQuency") && optional(rest, &["CW", "FIXed"]) => {
+            Parameter::Frequency
+        }
+        [(node, _), rest @ ..] if is(node, "FUNCtion") && optional(rest, &["SHAPe"]) => {
+            Parameter::Waveform
+        }
+        [(node, _), rest @ ..] if is(node, "PHASe") && optional(rest, &["ADJust"]) => {
+            Parameter::PhaseOffset
+        }
+        [(node, _), rest @ .., (last, _)] if is(node, "VOLTage") && is(last, "OFFSet") => {
+            if !optional(rest, &["LEVel", "IMMediate"]) {
+                return None;
+            }
+            Parameter::DcOffset
+        }
+        [(node, _), rest @ ..]
+            if is(node, "VOLTage") && optional(rest, &["LEVel", "IMMediate", "AMPLitude"]) =>
+        {
+            Parameter::Amplitude
+        }
+        _ => return None,
+    };
+    Some(parameter)
+}
+
+fn parse_argument(parameter: Parameter, argument: &str) -> Result<f32, ScpiError> {
+    let illegal = ScpiError::new(-224, "Illegal parameter value");
+    let argument = argument.to_uppercase();
+    let value = match parameter {
+        Parameter::Waveform => FUNCTIONS
+            .iter()
+            .position(|x| is(&argument, x))
+            .map(|x| x as f32 + 1.0)
+            .ok_or(illegal)?,
+        Parameter::Output => match argument.as_str() {
+            "ON" | "1" => 1.0,
+            "OFF" | "0" => 0.0,
+            _ => return Err(illegal),
+        },
+        _ => parse_number(&argument).ok_or(illegal)?,
+    };
+    if parameter == Parameter::Frequency && value <= 0.0 {
+        return Err(ScpiError::new(-222, "Data out of range"));
+    }
+    Ok(value)
+}
+
+/// Number with an optional unit, like `1E3`, `1KHZ` or `500MV`.
+fn parse_number(argument: &str) -> Option<f32> {
+    let units = [
+        ("KHZ", 1e3),
+        ("MHZ", 1e6),
+        ("HZ", 1.0),
+        ("MV", 1e-3),
+        ("V", 1.0),
+        ("DEG", 1.0),
+    ];
+    let (number, multiplier) = units
+        .iter()
+        .find_map(|(unit, multiplier)| Some((argument.strip_suffix(unit)?, *multiplier)))
+        .unwrap_or((argument, 1.0));
+    number
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .map(|x| x * multiplier)
+        .filter(|x| x.is_finite())
+}
+
+fn format_value(parameter: Parameter, value: f32) -> String {
+    match parameter {
+        Parameter::Waveform => {
+            let function = FUNCTIONS[(value as usize).saturating_sub(1) % FUNCTIONS.len()];
+            function.chars().take_while(|x| x.is_uppercase()).collect()
+        }
+        Parameter::Output => ((value > 0.0) as u8).to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Accept SCPI connections on a TCP address, one command line per line, returning the
+/// bound address. The commands are run by the application on its next tick.
+pub fn listen(address: &str, remote: RemoteHandle) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(address)
+        .wrap_err_with(|| format!("Failed to listen for SCPI on {}", address))?;
+    let address = listener.local_addr()?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().filter_map(|x| x.ok()) {
+            let remote = remote.clone();
+            std::thread::spawn(move || serve(stream, remote));
+        }
+    });
+    Ok(address)
+}
+
+fn serve(stream: TcpStream, remote: RemoteHandle) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut session = Session::default();
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let moved = std::mem::take(&mut session);
+        // the session moves to the application thread with the line and back
+        let Some((reply, moved)) = remote.call(move |app| {
+            let mut session = moved;
+            (session.execute(app, &line), session)
+        }) else {
+            break;
+        };
+        session = moved;
+        if let Some(reply) = reply {
+            writeln!(writer, "{}", reply)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scpi_parse() {
+        let mut session = Session::default();
+        assert_eq!(session.parse("*idn?"), Ok(Scpi::Identify));
+        assert_eq!(
+            session.parse("SOUR1:FREQ 1000"),
+            Ok(Scpi::Set {
+                channel: 0,
+                parameter: Parameter::Frequency,
+                value: 1000.0
+            })
+        );
+        assert_eq!(
+            session.parse(":source2:function squ"),
+            Ok(Scpi::Set {
+                channel: 1,
+                parameter: Parameter::Waveform,
+                value: 2.0
+            })
+        );
+        // relative to the branch of the previous header
+        assert_eq!(
+            session.parse("VOLT:OFFS?"),
+            Ok(Scpi::Query {
+                channel: 1,
+                parameter: Parameter::DcOffset
+            })
+        );
+        assert_eq!(
+            session.parse(":OUTP2 ON"),
+            Ok(Scpi::Set {
+                channel: 1,
+                parameter: Parameter::Output,
+                value: 1.0
+            })
+        );
+        assert_eq!(
+            session.parse(":FREQ 1.5KHZ"),
+            Ok(Scpi::Set {
+                channel: 0,
+                parameter: Parameter::Frequency,
+                value: 1500.0
+            })
+        );
+        assert_eq!(session.parse(":FREQU 1").unwrap_err().code, -113);
+        assert_eq!(session.parse(":SOUR0:FREQ 1").unwrap_err().code, -114);
+        assert_eq!(session.parse(":FREQ").unwrap_err().code, -109);
+        assert_eq!(session.parse(":FUNC SAW").unwrap_err().code, -224);
+        assert_eq!(session.parse(":FREQ -1").unwrap_err().code, -222);
+    }
+
+    #[test]
+    fn test_scpi_execute() {
+        let mut app = App::with_channels(2);
+        let mut session = Session::default();
+        session.execute(&mut app, "SOUR2:FREQ 1000;FUNC SQU;:OUTP1 OFF");
+        assert_eq!(
+            app.channel_parameter_value(1, Parameter::Frequency),
+            Some(1000.0)
+        );
+        assert_eq!(
+            session.execute(&mut app, "SOUR2:FREQ?;FUNC?;:OUTP1?"),
+            Some("1000;SQU;0".to_string())
+        );
+        session.execute(&mut app, "SOUR3:FREQ 1;BOGUS");
+        assert_eq!(
+            session.execute(&mut app, "SYST:ERR?;ERR?;ERR?"),
+            Some(
+                "-114,\"Header suffix out of range\";-113,\"Undefined header\";0,\"No error\""
+                    .to_string()
+            )
+        );
+        session.execute(&mut app, "*RST");
+        assert_eq!(
+            app.channel_parameter_value(1, Parameter::Frequency),
+            Some(880.0)
+        );
+    }
+
+    #[test]
+    fn test_scpi_listen() {
+        let mut app = App::with_channels(2);
+        let address = listen("127.0.0.1:0", app.remote.handle()).unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let timeout = std::time::Duration::from_secs(5);
+            stream.set_read_timeout(Some(timeout)).unwrap();
+            stream
+                .write_all(b"*IDN?\nSOUR1:FREQ 1000\nSOUR1:FREQ?\n")
+                .unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            let identification = lines.next().unwrap().unwrap();
+            let frequency = lines.next().unwrap().unwrap();
+            (identification, frequency)
+        });
+        while !client.is_finished() {
+            app.tick();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let (identification, frequency) = client.join().unwrap();
+        assert_eq!(identification, IDENTIFICATION);
+        assert_eq!(frequency, "1000");
+    }
+}
@@ -0,0 +1,300 @@
+use crate::command::{self, Command, Macros};
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use std::path::Path;
+use std::time::Instant;
+
+/// Maximum number of steps of a script once its loops are expanded.
+const MAX_STEPS: usize = 100_000;
+
+/// A command line of a script, with its line number for error reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub line: usize,
+    pub commands: Vec<Command>,
+}
+
+/// A sequence of commands run from the tick loop, pausing on `wait`.
+///
+/// Scripts hold one command line per line, `#` starts a comment line, and blocks are repeated
+/// with `repeat <count>` or `for <name> in <values>`, closed by `end`. In a `for` block `$name`
+/// is replaced by each value in turn.
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub name: String,
+    steps: Vec<Step>,
+    /// Index of the next step.
+    position: usize,
+    /// Step being run, split at its waits.
+    pending: Vec<Command>,
+    /// Line of the step being run.
+    pub line: usize,
+    /// End of the current wait.
+    resume: Option<Instant>,
+}
+
+/// Lines of a script with their blocks.
+#[derive(Debug)]
+enum Node {
+    Line(usize, String),
+    Repeat(usize, Vec<Node>),
+    For(String, Vec<String>, Vec<Node>),
+}
+
+impl Script {
+    pub fn load(path: &Path, macros: &Macros) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read script {}", path.display()))?;
+        let name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Self::parse(&name, &text, macros).map_err(|error| eyre!("{}: {}", name, error))
+    }
+
+    /// Parse a script, expanding the aliases and macros, including the ones it defines.
+    pub fn parse(name: &str, text: &str, macros: &Macros) -> Result<Self> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, x)| (i + 1, x.trim()))
+            .filter(|(_, x)| !x.is_empty() && !x.starts_with('#'));
+        let nodes = parse_block(&mut lines, None)?;
+        let mut steps = Vec::new();
+        expand(&nodes, &mut Vec::new(), &mut macros.clone(), &mut steps)?;
+        Ok(Self::new(name, steps))
+    }
+
+    /// A script of a single command line.
+    pub fn from_commands(name: &str, commands: Vec<Command>) -> Self {
+        Self::new(name, vec![Step { line: 1, commands }])
+    }
+
+    fn new(name: &str, steps: Vec<Step>) -> Self {
+        Self {
+            name: name.to_string(),
+            steps,
+            position: 0,
+            pending: Vec::new(),
+            line: 0,
+            resume: None,
+        }
+    }
+
+    /// Next command to run, `None` while waiting or once finished.
+    pub fn next_command(&mut self, now: Instant) -> Option<Command> {
+        if let Some(resume) = self.resume {
+            if now < resume {
+                return None;
+            }
+            self.resume = None;
+        }
+        while self.pending.is_empty() {
+            let step = self.steps.get(self.position)?;
+            self.line = step.line;
+            self.pending = step.commands.iter().rev().cloned().collect();
+            self.position += 1;
+        }
+        let command = self.pending.pop()?;
+        if let Command::Wait(duration) = command {
+            self.resume = Some(now + duration);
+        }
+        Some(command)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.resume.is_none() && self.pending.is_empty() && self.position >= self.steps.len()
+    }
+}
+
+fn parse_block<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    start: Option<usize>,
+) -> Result<Vec<Node>> {
+    let mut nodes = Vec::new();
+    while let Some((line, text)) = lines.next() {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words[0].to_lowercase().as_str() {
+            "end" if words.len() == 1 => match start {
+                Some(_) => return Ok(nodes),
+                None => bail!("line {}: 'end' without a loop", line),
+            },
+            "repeat" => {
+                let count = match words[1..] {
+                    [count] => count
+                        .parse()
+                        .map_err(|_| eyre!("line {}: '{}': invalid count", line, count))?,
+                    _ => bail!("line {}: expected 'repeat <count>'", line),
+                };
+                nodes.push(Node::Repeat(count, parse_block(lines, Some(line))?));
+            }
+            "for" => match words[1..] {
+                [name, "in", ref values @ ..] if !values.is_empty() => {
+                    let values = values.iter().map(|x| x.to_string()).collect();
+                    let block = parse_block(lines, Some(line))?;
+                    nodes.push(Node::For(name.to_string(), values, block));
+                }
+                _ => bail!("line {}: expected 'for <name> in <values>'", line),
+            },
+            _ => nodes.push(Node::Line(line, text.to_string())),
+        }
+    }
+    match start {
+        Some(line) => bail!("line {}: loop without 'end'", line),
+        None => Ok(nodes),
+    }
+}
+
+/// Replace the `$name` references to loop variables, the innermost loops shadowing outer
+/// variables of the same name. Other references, like the `$1` of macros, are kept.
+fn substitute(text: &str, variables: &[(String, String)]) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let length = after
+            .find(|x: char| !x.is_ascii_alphanumeric() && x != '_')
+            .unwrap_or(after.len());
+        let name = &after[..length];
+        match variables.iter().rev().find(|(x, _)| x == name) {
+            Some((_, value)) => result.push_str(value),
+            None => result.push_str(&rest[start..start + 1 + length]),
+        }
+        rest = &after[length..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Unroll the loops into steps, parsing each line with the loop variables replaced and the
+/// aliases defined so far.
+fn expand(
+    nodes: &[Node],
+    variables: &mut Vec<(String, String)>,
+    macros: &mut Macros,
+    steps: &mut Vec<Step>,
+) -> Result<()> {
+    for node in nodes {
+        match node {
+            Node::Line(line, text) => {
+                let text = substitute(text, variables);
+                let commands = command::parse_with(&text, macros)
+                    .map_err(|error| eyre!("line {}: {}", line, error))?;
+                for command in commands.iter() {
+                    let defined = match command {
+                        Command::Source(_) => {
+                            bail!("line {}: scripts can't source other scripts", line)
+                        }
+                        Command::Alias { name, definition } => {
+                            macros.define_alias(name, definition)
+                        }
+                        Command::Macro { name, definition } => {
+                            macros.define_macro(name, definition)
+                        }
+                        _ => Ok(()),
+                    };
+                    defined.map_err(|error| eyre!("line {}: {}", line, error))?;
+                }
+                steps.push(Step {
+                    line: *line,
+                    commands,
+                });
+                if steps.len() > MAX_STEPS {
+                    bail!("line {}: more than {} steps", line, MAX_STEPS);
+                }
+            }
+            Node::Repeat(count, block) => {
+                for _ in 0..*count {
+                    expand(block, variables, macros, steps)?;
+                }
+            }
+            Node::For(name, values, block) => {
+                for value in values {
+                    variables.push((name.clone(), value.clone()));
+                    let result = expand(block, variables, macros, steps);
+                    variables.pop();
+                    result?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameter::Parameter;
+    use std::time::Duration;
+
+    #[test]
+    fn test_script_parse() {
+        let mut script = Script::parse(
+            "test.sg",
+            "
+            # frequency sweep
+            alias sweep freq
+            wave sine
+            for f in 100 1k
+                repeat 2
+                    sweep $f; wait 1s
+                end
+            end
+            ",
+            &Macros::default(),
+        )
+        .unwrap();
+        assert_eq!(script.steps.len(), 6);
+        assert_eq!(script.steps[5].line, 7);
+
+        let now = Instant::now();
+        assert!(matches!(
+            script.next_command(now),
+            Some(Command::Alias { .. })
+        ));
+        assert!(matches!(
+            script.next_command(now),
+            Some(Command::Set { .. })
+        ));
+        assert_eq!(
+            script.next_command(now),
+            Some(Command::Set {
+                channel: None,
+                parameter: Parameter::Frequency,
+                value: 100.0
+            })
+        );
+        assert_eq!(
+            script.next_command(now),
+            Some(Command::Wait(Duration::from_secs(1)))
+        );
+        assert_eq!(script.next_command(now), None);
+        assert!(!script.is_finished());
+        assert!(script.next_command(now + Duration::from_secs(1)).is_some());
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let macros = Macros::default();
+        let error = Script::parse("test.sg", "freq 1k\n\nrepeat 2\n  amp loud\nend", &macros)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "line 4: 'loud': invalid level");
+        assert!(Script::parse("test.sg", "repeat 2\nfreq 1k", &macros).is_err());
+        assert!(Script::parse("test.sg", "end", &macros).is_err());
+        assert!(Script::parse("test.sg", "source other.sg", &macros).is_err());
+    }
+
+    #[test]
+    fn test_script_substitute() {
+        let variables = vec![
+            ("f".to_string(), "1k".to_string()),
+            ("freq".to_string(), "2k".to_string()),
+            ("f".to_string(), "3k".to_string()),
+        ];
+        assert_eq!(
+            substitute("freq $f; amp $freq; $fr $1 $", &variables),
+            "freq 3k; amp 2k; $fr $1 $"
+        );
+    }
+}
@@ -0,0 +1,430 @@
+use crate::audio::SampleTap;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::f64::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+/// FFT size limits, the largest being under half of the tap at 44.1 kHz.
+pub const SIZE_MIN: usize = 256;
+pub const SIZE_MAX: usize = 32768;
+/// Lowest level shown, in dBFS.
+pub const FLOOR: f32 = -120.0;
+/// Lowest frequency of the logarithmic axis, in Hz.
+pub const LOG_MIN: f32 = 10.0;
+/// Harmonics marked, counting the fundamental.
+pub const HARMONICS: usize = 5;
+/// Spectra averaged at most.
+pub const AVERAGE_MAX: usize = 64;
+
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum Window {
+    #[default]
+    Hann,
+    /// 4-term Blackman-Harris, for a low noise floor.
+    BlackmanHarris,
+    /// For accurate levels between bins.
+    FlatTop,
+}
+
+impl Window {
+    /// Cosine terms of the window.
+    fn terms(&self) -> &'static [f64] {
+        match self {
+            Window::Hann => &[0.5, 0.5],
+            Window::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            Window::FlatTop => &[
+                0.215_578_95,
+                0.416_631_58,
+                0.277_263_158,
+                0.083_578_947,
+                0.006_947_368,
+            ],
+        }
+    }
+
+    /// Coefficients of a periodic window of `size` samples.
+    pub fn coefficients(&self, size: usize) -> Vec<f32> {
+        (0..size)
+            .map(|i| {
+                let x = 2.0 * PI * i as f64 / size as f64;
+                self.terms()
+                    .iter()
+                    .enumerate()
+                    .map(|(k, a)| match k % 2 {
+                        0 => a * (k as f64 * x).cos(),
+                        _ => -a * (k as f64 * x).cos(),
+                    })
+                    .sum::<f64>() as f32
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Window::Hann => write!(f, "Hann"),
+            Window::BlackmanHarris => write!(f, "Blackman-Harris"),
+            Window::FlatTop => write!(f, "Flat-top"),
+        }
+    }
+}
+
+/// Setting changed by the `spectrum` command.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpectrumSetting {
+    /// Samples of each FFT, a power of two.
+    Size(usize),
+    Window(Window),
+    /// Logarithmic frequency axis.
+    Log(bool),
+    /// Number of spectra averaged, 1 for none.
+    Average(usize),
+    /// Keep the highest levels, toggles when `None`.
+    Hold(Option<bool>),
+    /// Output channel analyzed.
+    Source(usize),
+}
+
+/// Level of the fundamental or of one of its harmonics.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HarmonicMarker {
+    /// 1 for the fundamental.
+    pub harmonic: usize,
+    pub frequency: f32,
+    /// Level in dBFS.
+    pub level: f32,
+}
+
+/// Spectrum analyzer of an output channel.
+pub struct Spectrum {
+    pub size: usize,
+    pub window: Window,
+    pub log: bool,
+    pub average: usize,
+    pub hold: bool,
+    pub source: usize,
+    /// Averaged magnitude of each bin up to the Nyquist frequency, 1 for a full scale sine.
+    pub magnitudes: Vec<f32>,
+    /// Highest magnitude of each bin since the hold was enabled.
+    pub peaks: Vec<f32>,
+    /// Frames written to the tap at the last analysis, the same samples are not averaged twice.
+    analyzed: usize,
+    coefficients: Vec<f32>,
+    fft: Option<Arc<dyn Fft<f32>>>,
+}
+
+impl Default for Spectrum {
+    fn default() -> Self {
+        Self {
+            size: 4096,
+            window: Window::default(),
+            log: true,
+            average: 4,
+            hold: false,
+            source: 0,
+            magnitudes: Vec::new(),
+            peaks: Vec::new(),
+            analyzed: 0,
+            coefficients: Vec::new(),
+            fft: None,
+        }
+    }
+}
+
+impl Spectrum {
+    /// Change the analysis, returning the settings as shown in the title, or a warning for an
+    /// FFT size or an output the tap can't provide. A new size, window or source restarts the
+    /// averaging.
+    pub fn apply(&mut self, setting: SpectrumSetting, tap: &SampleTap) -> Result<String, String> {
+        match setting {
+            SpectrumSetting::Size(size) => {
+                // the frames analyzed are the last ones kept by the tap
+                let max = match tap.capacity() {
+                    0 => 0,
+                    capacity => SIZE_MAX.min(1 << capacity.ilog2()),
+                };
+                if !size.is_power_of_two() || !(SIZE_MIN..=max).contains(&size) {
+                    return Err(format!(
+                        "FFT size must be a power of two from {} to {}",
+                        SIZE_MIN, max
+                    ));
+                }
+                self.size = size;
+                self.reset();
+            }
+            SpectrumSetting::Window(window) => {
+                self.window = window;
+                self.reset();
+            }
+            SpectrumSetting::Log(log) => self.log = log,
+            SpectrumSetting::Average(average) => self.average = average.clamp(1, AVERAGE_MAX),
+            SpectrumSetting::Hold(hold) => {
+                self.hold = hold.unwrap_or(!self.hold);
+                self.peaks.clear();
+            }
+            SpectrumSetting::Source(output) => {
+                if output >= tap.channels() {
+                    return Err("Invalid output channel".to_string());
+                }
+                self.source = output;
+                self.reset();
+            }
+        }
+        Ok(format!("Spectrum: {}", self.describe()))
+    }
+
+    /// Settings as shown in the title, like `4096pt Hann avg 4 ch1`.
+    pub fn describe(&self) -> String {
+        let mut text = format!("{}pt {}", self.size, self.window);
+        if self.average > 1 {
+            text += &format!(" avg {}", self.average);
+        }
+        if self.hold {
+            text += " hold";
+        }
+        text + &format!(" ch{}", self.source + 1)
+    }
+
+    /// Forget the analyzed spectra, after a change of what is analyzed.
+    fn reset(&mut self) {
+        self.magnitudes.clear();
+        self.peaks.clear();
+        self.coefficients.clear();
+        self.fft = None;
+        self.analyzed = 0;
+    }
+
+    /// Analyze the latest samples of the tap, averaged with the previous spectra.
+    pub fn analyze(&mut self, tap: &SampleTap) {
+        if tap.written() == self.analyzed {
+            return;
+        }
+        let (written, mut samples) = tap.read(self.size);
+        if samples.get(self.source).map(|x| x.len()) != Some(self.size) {
+            return;
+        }
+        self.analyzed = written;
+        if self.coefficients.len() != self.size {
+            self.coefficients = self.window.coefficients(self.size);
+        }
+        let fft = self
+            .fft
+            .get_or_insert_with(|| FftPlanner::new().plan_fft_forward(self.size));
+        let mut buffer: Vec<Complex<f32>> = samples
+            .swap_remove(self.source)
+            .iter()
+            .zip(self.coefficients.iter())
+            .map(|(x, w)| Complex::new(x * w, 0.0))
+            .collect();
+        fft.process(&mut buffer);
+
+        // a sine is split between the positive and negative frequencies, DC is not
+        let gain = 2.0 / self.coefficients.iter().sum::<f32>();
+        let bins = self.size / 2 + 1;
+        let magnitudes = buffer[..bins].iter().enumerate().map(|(i, x)| match i {
+            0 => x.norm() * gain / 2.0,
+            _ => x.norm() * gain,
+        });
+        if self.magnitudes.len() == bins {
+            // exponential average over about `average` spectra
+            let weight = 1.0 / self.average as f32;
+            for (average, magnitude) in self.magnitudes.iter_mut().zip(magnitudes) {
+                *average += (magnitude - *average) * weight;
+            }
+        } else {
+            self.magnitudes = magnitudes.collect();
+        }
+        if self.hold {
+            self.peaks.resize(bins, 0.0);
+            for (peak, magnitude) in self.peaks.iter_mut().zip(self.magnitudes.iter()) {
+                *peak = peak.max(*magnitude);
+            }
+        }
+    }
+
+    /// Frequency of a bin in Hz.
+    pub fn frequency(&self, bin: f32, sample_rate: f32) -> f32 {
+        bin * sample_rate / self.size as f32
+    }
+
+    /// Position of a frequency on the horizontal axis.
+    pub fn x(&self, frequency: f32) -> f64 {
+        match self.log {
+            true => (frequency.max(LOG_MIN) as f64).log10(),
+            false => frequency as f64,
+        }
+    }
+
+    /// Horizontal axis bounds, up to the Nyquist frequency.
+    pub fn bounds(&self, sample_rate: f32) -> [f64; 2] {
+        [self.x(0.0), self.x(sample_rate / 2.0)]
+    }
+
+    /// Chart points of the magnitudes in dBFS, keeping the highest of the bins in each of
+    /// `columns` columns.
+    pub fn points(&self, magnitudes: &[f32], sample_rate: f32, columns: usize) -> Vec<(f64, f64)> {
+        let [min, max] = self.bounds(sample_rate);
+        let mut points: Vec<(f64, f64)> = Vec::new();
+        let mut last_column = None;
+        for (i, magnitude) in magnitudes.iter().enumerate() {
+            let frequency = self.frequency(i as f32, sample_rate);
+            if self.log && frequency < LOG_MIN {
+                continue;
+            }
+            let x = self.x(frequency);
+            let level = decibels(*magnitude) as f64;
+            let column = ((x - min) / (max - min) * columns as f64) as usize;
+            match points.last_mut() {
+                Some(point) if last_column == Some(column) => point.1 = point.1.max(level),
+                _ => {
+                    points.push((x, level));
+                    last_column = Some(column);
+                }
+            }
+        }
+        points
+    }
+
+    /// The fundamental, the highest bin away from DC, and its harmonics below the Nyquist
+    /// frequency.
+    pub fn markers(&self, sample_rate: f32) -> Vec<HarmonicMarker> {
+        // skip the main lobe of the window around DC
+        let skip = self.window.terms().len() + 1;
+        let Some((peak, _)) = self
+            .magnitudes
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .max_by(|a, b| a.1.total_cmp(b.1))
+        else {
+            return Vec::new();
+        };
+        // parabolic interpolation between the bins around the peak
+        let level = |i: usize| decibels(self.magnitudes[i]);
+        let offset = match self.magnitudes.get(peak + 1) {
+            Some(_) => {
+                let (a, b, c) = (level(peak - 1), level(peak), level(peak + 1));
+                let curvature = a - 2.0 * b + c;
+                if curvature < 0.0 {
+                    0.5 * (a - c) / curvature
+                } else {
+                    0.0
+                }
+            }
+            None => 0.0,
+        };
+        let fundamental = peak as f32 + offset;
+        (1..=HARMONICS)
+            .map_while(|harmonic| {
+                let center = (fundamental * harmonic as f32).round() as usize;
+                if center >= self.magnitudes.len() {
+                    return None;
+                }
+                // the harmonic may fall between bins
+                let width = harmonic / 2 + 1;
+                let end = (center + width).min(self.magnitudes.len() - 1);
+                let magnitude = self.magnitudes[center.saturating_sub(width)..=end]
+                    .iter()
+                    .fold(0.0f32, |a, b| a.max(*b));
+                Some(HarmonicMarker {
+                    harmonic,
+                    frequency: self.frequency(fundamental * harmonic as f32, sample_rate),
+                    level: decibels(magnitude),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Magnitude in dBFS, no lower than the floor.
+pub fn decibels(magnitude: f32) -> f32 {
+    (20.0 * magnitude.log10()).max(FLOOR)
+}
+
+/// Frequency with the unit it reads best in, like `440Hz` or `1.5kHz`.
+pub fn format_frequency(hertz: f32) -> String {
+    let (value, unit) = match hertz.abs() {
+        x if x >= 1e3 => (hertz / 1e3, "kHz"),
+        _ => (hertz, "Hz"),
+    };
+    let value = format!("{:.1}", value);
+    let value = value.trim_end_matches('0').trim_end_matches('.');
+    format!("{}{}", value, unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tap of a 1 kHz sine at half scale on the first channel and silence on the second.
+    fn tap(sample_rate: f32, frames: usize) -> SampleTap {
+        let tap = SampleTap::new(2, frames);
+        let data: Vec<f32> = (0..frames)
+            .flat_map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * 1000.0 * i as f32 / sample_rate;
+                [0.5 * phase.sin() + 0.01 * (2.0 * phase).sin(), 0.0]
+            })
+            .collect();
+        tap.write(&data);
+        tap
+    }
+
+    #[test]
+    fn test_spectrum_window() {
+        let hann = Window::Hann.coefficients(8);
+        assert!(hann[0].abs() < 1e-6);
+        assert!((hann[4] - 1.0).abs() < 1e-6);
+        // the flat-top window peaks above 1
+        let flat_top = Window::FlatTop.coefficients(64);
+        assert!(flat_top[32] > 0.99);
+    }
+
+    #[test]
+    fn test_spectrum_markers() {
+        let tap = tap(48000.0, 8192);
+        let mut spectrum = Spectrum::default();
+        spectrum
+            .apply(SpectrumSetting::Window(Window::FlatTop), &tap)
+            .unwrap();
+        spectrum.analyze(&tap);
+        assert_eq!(spectrum.magnitudes.len(), 2049);
+        let markers = spectrum.markers(48000.0);
+        assert_eq!(markers.len(), HARMONICS);
+        assert!((markers[0].frequency - 1000.0).abs() < 12.0);
+        // -6 dBFS fundamental and -40 dBFS second harmonic
+        assert!((markers[0].level + 6.02).abs() < 0.1);
+        assert!((markers[1].level + 40.0).abs() < 0.5);
+        assert!(markers[2].level < -90.0);
+
+        // the silent channel
+        spectrum.apply(SpectrumSetting::Source(1), &tap).unwrap();
+        spectrum.analyze(&tap);
+        assert!(spectrum.magnitudes.iter().all(|x| decibels(*x) == FLOOR));
+        assert!(spectrum.apply(SpectrumSetting::Size(1000), &tap).is_err());
+        assert!(spectrum.apply(SpectrumSetting::Size(8192), &tap).is_ok());
+        assert_eq!(
+            spectrum.apply(SpectrumSetting::Size(16384), &tap),
+            Err("FFT size must be a power of two from 256 to 8192".to_string())
+        );
+        assert!(spectrum.apply(SpectrumSetting::Source(2), &tap).is_err());
+    }
+
+    #[test]
+    fn test_spectrum_points() {
+        let mut spectrum = Spectrum {
+            size: 1024,
+            ..Spectrum::default()
+        };
+        let magnitudes = vec![0.1; 513];
+        // fewer points than bins at high frequencies on a logarithmic axis
+        let points = spectrum.points(&magnitudes, 48000.0, 100);
+        assert!(points.len() <= 100);
+        assert!(points[0].0 >= (LOG_MIN as f64).log10());
+        assert_eq!(points[0].1, -20.0);
+        spectrum.log = false;
+        assert_eq!(spectrum.points(&magnitudes, 48000.0, 1000).len(), 513);
+        assert_eq!(format_frequency(1500.0), "1.5kHz");
+        assert_eq!(format_frequency(440.0), "440Hz");
+    }
+}
@@ -0,0 +1,42 @@
+use crate::command::{self, Command};
+use crate::parameter::Parameter;
+
+/// Address of the OSC map in the config file, controlling a parameter. A 0 to 1 argument is
+/// scaled to the range, like the faders of TouchOSC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub address: String,
+    pub channel: Option<usize>,
+    pub parameter: Parameter,
+    pub range: Option<(f32, f32)>,
+}
+
+/// Parse a parameter like `ch1:amp`, `ch 2 freq` or `vol`, as the commands name them.
+pub fn parse_target(target: &str) -> Option<(Option<usize>, Parameter)> {
+    match command::parse(&format!("{}?", target)).ok()?.as_slice() {
+        [Command::Get {
+            channel,
+            parameter: Some(parameter),
+        }] => Some((*channel, *parameter)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_parse() {
+        assert_eq!(
+            parse_target("ch2:amp"),
+            Some((Some(1), Parameter::Amplitude))
+        );
+        assert_eq!(
+            parse_target("ch 2 freq"),
+            Some((Some(1), Parameter::Frequency))
+        );
+        assert_eq!(parse_target("vol"), Some((None, Parameter::Volume)));
+        assert_eq!(parse_target("ch1:save"), None);
+    }
+}
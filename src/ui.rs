@@ -1,17 +1,23 @@
 use ratatui::{
     layout::{Constraint, Direction, Layout, Margin},
-    prelude::{Alignment, Frame, Marker, Modifier, Rect, Text},
+    prelude::{Alignment, Frame, Marker, Modifier, Rect, Span, Text},
     style::{Color, Style, Stylize},
-    symbols,
     widgets::{
-        canvas::*, Axis, Block, BorderType, Borders, Cell, Chart, Clear, Dataset, Gauge, GraphType,
-        LineGauge, List, ListItem, ListState, Paragraph, Row, StatefulWidget, Table, TableState,
-        Tabs, Widget,
+        block::{Position, Title},
+        canvas::*,
+        Axis, Block, BorderType, Borders, Cell, Chart, Clear, Dataset, Gauge, GraphType, List,
+        ListItem, ListState, Paragraph, Row, StatefulWidget, Table, TableState, Tabs, Widget,
     },
 };
 
-use crate::app::TAB_TITLES;
-use crate::app::{App, Mode};
+use crate::app::{channel_name, App, Mode, View};
+use crate::audio::Routing;
+use crate::complete;
+use crate::parameter::Parameter;
+use crate::preview;
+use crate::scope::{format_time, DIVISIONS_X, DIVISIONS_Y};
+use crate::spectrum::{self, FLOOR};
+use waveforms_rs::WaveformType;
 
 pub const HELP_LOGO: &str = r#"
   ___ (_)__ ____  ___ _/ /__ ____ ___  ___ _______ _/ /____  ____
@@ -57,61 +63,111 @@ pub fn render(app: &mut App, frame: &mut Frame) {
 
     let tab_area = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Min(5), Constraint::Length(3)])
+        .constraints([
+            Constraint::Min(5),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
         .split(main_sub_area[0]);
 
+    // output indicator on the left of the version
+    let version = format!("Signal Generator v{}", env!("CARGO_PKG_VERSION"));
+    let status_area = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Min(0),
+            Constraint::Length(version.chars().count() as u16),
+        ])
+        .split(title_area[1]);
+
     frame.render_widget(
-        Paragraph::new(format!("Signal Generator v{}", env!("CARGO_PKG_VERSION")))
+        Paragraph::new(version)
             .style(Style::default())
             .alignment(Alignment::Right),
-        title_area[1],
+        status_area[1],
     );
+    frame.render_widget(make_output_indicator(app), status_area[0]);
 
-    let tab_color = match app.tab_index {
-        0 => Color::Yellow,
-        1 => Color::Cyan,
-        _ => Color::White,
+    let tab_color = if app.is_output_tab() {
+        app.theme.output
+    } else {
+        app.theme.channel(app.tab_index)
     };
 
     frame.render_widget(make_tab_bar(app, tab_color), title_area[0]);
 
-    frame.render_widget(make_preview_canvas(app), main_sub_area[1]);
+    match app.view {
+        View::Preview => frame.render_widget(make_preview_canvas(app), main_sub_area[1]),
+        View::Scope => frame.render_widget(make_scope_canvas(app), main_sub_area[1]),
+        View::Spectrum => render_spectrum(app, frame, main_sub_area[1]),
+        View::Xy => frame.render_widget(make_xy_canvas(app), main_sub_area[1]),
+    }
 
-    let waveform = format!(
-        "{}",
-        app.waveform_previews[app.selected_waveform].waveform_type()
-    );
-    let frequency = format!(
-        "{:.2} Hz",
-        app.waveform_previews[app.selected_waveform].frequency()
-    );
-    let amplitude = format!(
-        "{:.2}",
-        app.waveform_previews[app.selected_waveform].amplitude()
-    );
-    let phase_offset = format!(
-        "{:.2}",
-        app.waveform_previews[app.selected_waveform].phase_offset()
-    );
-    let dc_offset = format!(
-        "{:.2}",
-        app.waveform_previews[app.selected_waveform].dc_offset()
-    );
-    let parameters = vec![
-        vec!["Waveform:", waveform.as_str()],
-        vec!["Frequency:", frequency.as_str()],
-        vec!["Amplitude:", amplitude.as_str()],
-        vec!["Phase offset:", phase_offset.as_str()],
-        vec!["DC offset:", dc_offset.as_str()],
-        vec!["Pan:", "0.0"],
-    ];
-    frame.render_stateful_widget(
-        make_parameter_table(parameters, tab_color),
-        tab_area[0],
-        &mut app.table_state,
-    );
+    // remember where the interactive widgets are for mouse events
+    app.areas.tabs = tab_rects(&app.tab_titles(), title_area[0]);
+    app.areas.parameters = tab_area[0];
+    app.areas.preview = main_sub_area[1];
+
+    let parameters = app
+        .parameters()
+        .iter()
+        .map(|parameter| vec![format!("{}:", parameter), format_parameter(app, *parameter)])
+        .collect();
+
+    if app.is_output_tab() {
+        let output_area = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(app.parameters().len() as u16 + 2),
+                Constraint::Min(8),
+                Constraint::Length(app.audio.info.channels as u16 + 2),
+            ])
+            .split(tab_area[0]);
+        app.areas.parameters = output_area[0];
+
+        frame.render_stateful_widget(
+            make_parameter_table(parameters, tab_color),
+            output_area[0],
+            &mut app.table_state,
+        );
+        frame.render_widget(make_output_info(app), output_area[1]);
+        render_level_meters(app, frame, output_area[2]);
+    } else {
+        frame.render_stateful_widget(
+            make_parameter_table(parameters, tab_color),
+            tab_area[0],
+            &mut app.table_state,
+        );
+    }
 
     frame.render_widget(make_status_bar(app), tab_area[1]);
+    frame.render_widget(make_hint_line(app), tab_area[2]);
+
+    if app.mode == Mode::Keypad {
+        let area = keypad_rect(app, app.areas.parameters);
+        frame.render_widget(Clear, area);
+        frame.render_widget(make_keypad_popup(app), area);
+    }
+
+    if app.mode == Mode::Presets {
+        let area = centered_rect(40, 50, area);
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(
+            make_preset_popup(app, tab_color),
+            area,
+            &mut app.preset_list_state,
+        );
+    }
+
+    if app.history_search.is_some() {
+        let area = centered_rect(60, 50, area);
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(
+            make_history_popup(app, tab_color),
+            area,
+            &mut app.history_search_state,
+        );
+    }
 
     if app.mode == Mode::Help {
         let area = centered_rect(80, 60, area);
@@ -120,16 +176,16 @@ pub fn render(app: &mut App, frame: &mut Frame) {
     }
 }
 
-fn make_parameter_table<'a>(
-    parameters: Vec<Vec<&'a str>>,
+fn make_parameter_table(
+    parameters: Vec<Vec<String>>,
     tab_color: Color,
-) -> impl StatefulWidget<State = TableState> + 'a {
+) -> impl StatefulWidget<State = TableState> + 'static {
     let selected_style = Style::default()
         .fg(tab_color)
         .add_modifier(Modifier::REVERSED);
 
-    let rows = parameters.iter().map(|item| {
-        let cells = item.iter().map(|c| Cell::from(*c));
+    let rows = parameters.into_iter().map(|item| {
+        let cells = item.into_iter().map(Cell::from);
         Row::new(cells)
     });
 
@@ -146,8 +202,197 @@ fn make_parameter_table<'a>(
     .highlight_style(selected_style)
 }
 
+/// Value of a parameter formatted for display.
+fn format_parameter(app: &App, parameter: Parameter) -> String {
+    let waveform = &app.waveform_previews[app.selected_waveform];
+    let settings = app.audio.settings.lock().unwrap();
+    match parameter {
+        Parameter::Waveform => format!("{}", waveform.waveform_type()),
+        Parameter::Frequency => format!("{:.2} Hz", waveform.frequency()),
+        Parameter::Amplitude => format!("{:.2}", waveform.amplitude()),
+        Parameter::PhaseOffset => format!("{:.2}", waveform.phase_offset()),
+        Parameter::DcOffset => format!("{:.2}", waveform.dc_offset()),
+        Parameter::Pan => format!("{:.2}", settings.pans[app.selected_waveform]),
+        Parameter::Output => {
+            if settings.channel_gain(app.selected_waveform) > 0.0 { "ON" } else { "OFF" }.to_string()
+        }
+        Parameter::Routing => format!("{}", settings.routing),
+        Parameter::Volume => format!("{:.2}", settings.volume),
+        Parameter::Mute => if settings.mute { "On" } else { "Off" }.to_string(),
+    }
+}
+
+/// ON/OFF indicator of each channel output and the master mute, like the OUTPUT buttons on a
+/// bench generator.
+fn make_output_indicator(app: &App) -> impl Widget + 'static {
+    let settings = app.audio.settings.lock().unwrap();
+    let mut spans: Vec<Span> = Vec::new();
+    for i in 0..settings.enabled.len() {
+        let label = (b'A' + i as u8) as char;
+        if settings.channel_gain(i) > 0.0 {
+            spans.push(format!(" {} ON ", label).black().on_green());
+        } else {
+            spans.push(format!(" {} OFF ", label).dark_gray());
+        }
+        spans.push(" ".into());
+    }
+    if settings.mute {
+        spans.push(" MUTE ".black().on_red());
+    }
+    Paragraph::new(ratatui::text::Line::from(spans)).alignment(Alignment::Left)
+}
+
+fn make_output_info(app: &App) -> impl Widget + 'static {
+    let info = &app.audio.info;
+    let status = app.audio.status.lock().unwrap();
+
+    let buffer_size = match info.buffer_size {
+        Some(frames) => format!("{} frames", frames),
+        None => format!("{} frames (default)", status.buffer_frames),
+    };
+    let state = match &status.error {
+        Some(error) => format!("Error: {}", error),
+        None => format!("{:?}", app.audio.state),
+    };
+    let routing = app.audio.settings.lock().unwrap().routing;
+    let channels = (0..info.channels)
+        .map(|i| match routing {
+            Routing::Split if i < app.channels() => channel_name(i),
+            Routing::Split => "-".to_string(),
+            _ => format!("{}", routing),
+        })
+        .enumerate()
+        .map(|(i, source)| format!("{}:{}", i + 1, source))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let rows = vec![
+        vec!["Host:".to_string(), info.host.clone()],
+        vec!["Device:".to_string(), info.device.clone()],
+        vec!["Sample rate:".to_string(), format!("{} Hz", info.sample_rate)],
+        vec!["Buffer size:".to_string(), buffer_size],
+        vec!["Channels:".to_string(), channels],
+        vec!["Status:".to_string(), state],
+        vec!["Xruns:".to_string(), format!("{}", status.xruns)],
+    ]
+    .into_iter()
+    .map(|item| Row::new(item.into_iter().map(Cell::from)));
+
+    Table::new(
+        rows,
+        [Constraint::Percentage(50), Constraint::Percentage(50)],
+    )
+    .block(
+        Block::default()
+            .title(" Device ")
+            .borders(Borders::ALL)
+            .border_type(BorderType::Plain),
+    )
+}
+
+/// Lowest level of the meters, in dBFS.
+const METER_FLOOR: f32 = -60.0;
+
+/// Peak level of each output channel in dBFS, with the RMS and true peak levels and a latching
+/// clip indicator.
+fn render_level_meters(app: &App, frame: &mut Frame, area: Rect) {
+    let (meters, clips) = {
+        let status = app.audio.status.lock().unwrap();
+        (status.meters.clone(), status.clips.clone())
+    };
+    frame.render_widget(
+        Block::default()
+            .title(" Levels dBFS ")
+            .borders(Borders::ALL)
+            .border_type(BorderType::Plain),
+        area,
+    );
+    let inner = area.inner(&Margin {
+        horizontal: 1,
+        vertical: 1,
+    });
+    for (i, levels) in meters.iter().enumerate().take(inner.height as usize) {
+        let clipped = clips.get(i).copied().unwrap_or(false);
+        let (peak, rms, true_peak) = (
+            spectrum::decibels(levels.peak),
+            spectrum::decibels(levels.rms),
+            spectrum::decibels(levels.true_peak),
+        );
+        let color = match true_peak {
+            x if x >= 0.0 => Color::Red,
+            x if x > -6.0 => Color::Yellow,
+            _ => Color::Green,
+        };
+        let row = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Min(10),
+                Constraint::Length(5),
+            ])
+            .split(Rect {
+                y: inner.y + i as u16,
+                height: 1,
+                ..inner
+            });
+        frame.render_widget(Paragraph::new(format!("{:>2}", i + 1)), row[0]);
+        let gauge = Gauge::default()
+            .gauge_style(Style::default().fg(color).bg(Color::Black))
+            .ratio(((peak - METER_FLOOR) / -METER_FLOOR).clamp(0.0, 1.0) as f64)
+            .label(format!(
+                "Peak {:.1}  RMS {:.1}  TP {:.1}",
+                peak, rms, true_peak
+            ));
+        frame.render_widget(gauge, row[1]);
+        let clip = match clipped {
+            true => " CLIP".red().bold(),
+            false => " CLIP".dark_gray(),
+        };
+        frame.render_widget(Paragraph::new(clip), row[2]);
+    }
+}
+
+fn make_preset_popup(app: &App, color: Color) -> impl StatefulWidget<State = ListState> + 'static {
+    let items: Vec<ListItem> = app
+        .preset_names
+        .iter()
+        .map(|x| ListItem::new(x.clone()))
+        .collect();
+    List::new(items)
+        .block(
+            Block::default()
+                .title(" Presets ")
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL)
+                .border_type(BorderType::Thick),
+        )
+        .highlight_style(Style::default().fg(color).add_modifier(Modifier::REVERSED))
+}
+
+fn make_history_popup(app: &App, color: Color) -> impl StatefulWidget<State = ListState> + 'static {
+    let items: Vec<ListItem> = app
+        .history_matches()
+        .into_iter()
+        .map(ListItem::new)
+        .collect();
+    List::new(items)
+        .block(
+            Block::default()
+                .title(" History search ")
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL)
+                .border_type(BorderType::Thick),
+        )
+        .highlight_style(Style::default().fg(color).add_modifier(Modifier::REVERSED))
+}
+
 fn make_help_popup(app: &App) -> impl Widget + 'static {
-    let help_text = Text::raw(format!("{}\n\n{}", HELP_LOGO, HELP_TEXT));
+    let mut help_text = format!("{}\n\n{}", HELP_LOGO, HELP_TEXT);
+    let macros = app.macros.describe();
+    if !macros.is_empty() {
+        help_text += &format!("\n\nAliases and macros:\n  {}", macros.join("\n  "));
+    }
+    let help_text = Text::raw(help_text);
     Paragraph::new(help_text)
         .block(
             Block::default()
@@ -160,8 +405,58 @@ fn make_help_popup(app: &App) -> impl Widget + 'static {
         .alignment(Alignment::Left)
 }
 
+fn make_keypad_popup(app: &App) -> impl Widget + 'static {
+    let parameter = app.selected_parameter;
+    let preview = match (parameter, app.keypad_value()) {
+        (Parameter::Waveform, Some(value)) => {
+            match WaveformType::try_from((value as usize).wrapping_sub(1)) {
+                Ok(waveform) => format!("= {}", waveform),
+                Err(_) => "= ---".to_string(),
+            }
+        }
+        (_, Some(value)) => format!("= {:.2} {}", value, parameter.unit()),
+        (_, None) => "= ---".to_string(),
+    };
+    let color = if app.keypad.is_empty() || app.keypad_value().is_some() {
+        Color::White
+    } else {
+        Color::Red
+    };
+
+    Paragraph::new(vec![
+        format!("{}_", app.keypad).into(),
+        preview.dark_gray().into(),
+    ])
+    .block(
+        Block::default()
+            .title(format!(" {} ", parameter))
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Thick),
+    )
+    .style(Style::default().fg(color))
+    .alignment(Alignment::Right)
+}
+
+/// helper function to place the keypad popup over the value of the selected parameter row
+fn keypad_rect(app: &App, table_area: Rect) -> Rect {
+    let height = 4;
+    let width = (table_area.width / 2).max(16).min(table_area.width);
+    let row = table_area.y + 1 + app.table_state.selected().unwrap_or(0) as u16;
+    let y = row
+        .saturating_sub(1)
+        .min(table_area.bottom().saturating_sub(height))
+        .max(table_area.y);
+    Rect {
+        x: table_area.right().saturating_sub(width),
+        y,
+        width,
+        height: height.min(table_area.height),
+    }
+}
+
 fn make_tab_bar(app: &mut App, tab_color: Color) -> impl Widget + 'static {
-    Tabs::new(TAB_TITLES.to_vec())
+    Tabs::new(app.tab_titles())
         .style(Style::default())
         .highlight_style(
             Style::default()
@@ -171,78 +466,350 @@ fn make_tab_bar(app: &mut App, tab_color: Color) -> impl Widget + 'static {
         .select(app.tab_index)
 }
 
+/// helper function to find the screen area of each tab title, matching the default `Tabs` padding
+/// and divider
+fn tab_rects(titles: &[String], area: Rect) -> Vec<Rect> {
+    let mut rects = Vec::new();
+    let mut x = area.x;
+    for title in titles.iter() {
+        let width = (title.chars().count() as u16 + 2).min(area.right().saturating_sub(x));
+        rects.push(Rect {
+            x,
+            y: area.y,
+            width,
+            height: 1,
+        });
+        // skip the divider
+        x = x.saturating_add(width + 1);
+    }
+    rects
+}
+
 fn make_preview_canvas(app: &mut App) -> impl Widget + 'static {
-    for waveform in app.waveform_previews.iter_mut() {
-        waveform.reset();
+    // a window of periods of the selected channel, in seconds
+    let sample_rate = app.preview_sample_rate();
+    let frequency = app
+        .parameter_value(Parameter::Frequency)
+        .unwrap_or_default();
+    let (skip, samples, step) = app.preview.samples(frequency, sample_rate);
+    let time = |sample: usize| sample as f64 / sample_rate as f64;
+    let (start, end) = (time(skip), time(skip + samples));
+    let mut traces: Vec<(Vec<(f64, f64)>, Color)> = Vec::new();
+    for (channel, waveform) in app.waveform_previews.iter_mut().enumerate() {
+        let values = preview::trace(waveform, skip, samples, step);
+        traces.push((values, app.theme.channel(channel)));
     }
-    let mut values_a: Vec<(f64, f64)> = Vec::new();
-    let mut values_b: Vec<(f64, f64)> = Vec::new();
-    let mut val;
-    for i in 0..100 {
-        val = app.waveform_previews[0].process() as f64;
-        values_a.push((i as f64, val));
-        val = app.waveform_previews[1].process() as f64;
-        values_b.push((i as f64, val));
+    let title = format!(
+        " {} periods x{:.2} at {} Hz ",
+        app.preview.periods, app.preview.zoom, sample_rate
+    );
+    let labels = [
+        (start, Alignment::Left),
+        ((start + end) / 2.0, Alignment::Center),
+        (end, Alignment::Right),
+    ]
+    .map(|(time, alignment)| {
+        Title::from(format_time(time as f32).dark_gray())
+            .position(Position::Bottom)
+            .alignment(alignment)
+    });
+    // draw the selected channel on top
+    if app.selected_waveform < traces.len() {
+        let selected = traces.remove(app.selected_waveform);
+        traces.push(selected);
     }
+    Canvas::default()
+        .block(
+            labels.into_iter().fold(
+                Block::default()
+                    .title(title)
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Plain),
+                |block, label| block.title(label),
+            ),
+        )
+        .marker(Marker::Braille)
+        .paint(move |ctx| {
+            ctx.draw(&Line {
+                x1: start,
+                y1: 0.0,
+                x2: end,
+                y2: 0.0,
+                color: Color::DarkGray,
+            });
+            ctx.print(start, -1.0, "-1.0".dark_gray());
+            ctx.print(start, 0.0, "0.0".dark_gray());
+            ctx.print(start, 1.0, "+1.0".dark_gray());
+
+            // draw the waveforms
+            for (values, color) in traces.iter() {
+                for points in values.windows(2) {
+                    ctx.draw(&Line {
+                        x1: points[0].0,
+                        y1: points[0].1,
+                        x2: points[1].0,
+                        y2: points[1].1,
+                        color: *color,
+                    });
+                }
+            }
+        })
+        .x_bounds([start, end])
+        .y_bounds([-1.1, 1.1])
+}
+
+/// Lissajous figure of two channels, older frames fading out.
+fn make_xy_canvas(app: &mut App) -> impl Widget + 'static {
+    app.xy.update(&mut app.waveform_previews);
+    let (x_scale, y_scale) = app.xy.scale();
+    let (x_scale, y_scale) = (x_scale * 1.1, y_scale * 1.1);
+    let color = app.theme.channel(app.xy.y_channel);
+    let persistence = app.xy.persistence;
+    // the oldest frames first, so the newest is drawn on top
+    let traces: Vec<(Vec<(f64, f64)>, Color)> = app
+        .xy
+        .traces
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(age, points)| {
+            let color = match age {
+                0 => color,
+                age if age * 2 < persistence => Color::Gray,
+                _ => Color::DarkGray,
+            };
+            (points.clone(), color)
+        })
+        .collect();
+    let title = match traces.is_empty() {
+        true => " XY needs two channels ".to_string(),
+        false => format!(
+            " XY {} / {} ",
+            channel_name(app.xy.x_channel),
+            channel_name(app.xy.y_channel)
+        ),
+    };
     Canvas::default()
         .block(
             Block::default()
+                .title(title)
                 .borders(Borders::ALL)
                 .border_type(BorderType::Plain),
         )
         .marker(Marker::Braille)
         .paint(move |ctx| {
             ctx.draw(&Line {
-                x1: 0.0,
+                x1: -x_scale,
                 y1: 0.0,
-                x2: 100.0,
+                x2: x_scale,
                 y2: 0.0,
                 color: Color::DarkGray,
             });
-            ctx.print(0.0, -1.0, "-1.0".dark_gray());
-            ctx.print(0.0, 0.0, "0.0".dark_gray());
-            ctx.print(0.0, 1.0, "+1.0".dark_gray());
+            ctx.draw(&Line {
+                x1: 0.0,
+                y1: -y_scale,
+                x2: 0.0,
+                y2: y_scale,
+                color: Color::DarkGray,
+            });
+            ctx.print(-x_scale, 0.0, format!("{:+.2}", -x_scale).dark_gray());
+            ctx.print(0.0, y_scale, format!("{:+.2}", y_scale).dark_gray());
+            ctx.layer();
 
-            // draw the waveforms
-            let mut p1;
-            let mut p2;
-            for i in 0..values_a.len() - 1 {
-                p1 = values_a.get(i).unwrap_or(&(0.0, 0.0));
-                p2 = values_a.get(i + 1).unwrap_or(&(0.0, 100.0));
+            for (points, color) in traces.iter() {
+                for points in points.windows(2) {
+                    ctx.draw(&Line {
+                        x1: points[0].0,
+                        y1: points[0].1,
+                        x2: points[1].0,
+                        y2: points[1].1,
+                        color: *color,
+                    });
+                }
+                ctx.layer();
+            }
+        })
+        .x_bounds([-x_scale, x_scale])
+        .y_bounds([-y_scale, y_scale])
+}
+
+/// Points of the scope traces drawn at most, longer traces are decimated.
+const SCOPE_POINTS: usize = 1024;
+
+/// Oscilloscope of the output samples, in divisions with zero in the middle.
+fn make_scope_canvas(app: &mut App) -> impl Widget + 'static {
+    let sample_rate = app.audio.info.sample_rate as f32;
+    app.scope.capture(&app.audio.tap, sample_rate);
+    let scope = &app.scope;
+    let half = DIVISIONS_Y as f64 / 2.0;
+    let scale = scope.scale as f64;
+    let mut traces: Vec<(Vec<(f64, f64)>, Color)> = Vec::new();
+    for (channel, samples) in scope.trace.iter().enumerate() {
+        if !scope.enabled.get(channel).copied().unwrap_or(true) {
+            continue;
+        }
+        let step = samples.len().div_ceil(SCOPE_POINTS).max(1);
+        let divisions = DIVISIONS_X as f64 / samples.len().max(1) as f64;
+        let values = samples
+            .iter()
+            .enumerate()
+            .step_by(step)
+            .map(|(i, x)| (i as f64 * divisions, (*x as f64 / scale).clamp(-half, half)))
+            .collect();
+        traces.push((values, app.theme.channel(channel)));
+    }
+    let level = (scope.level as f64 / scale).clamp(-half, half);
+    let title = format!(" Scope {} {} ", scope.describe(), scope.acquisition);
+    Canvas::default()
+        .block(
+            Block::default()
+                .title(title)
+                .borders(Borders::ALL)
+                .border_type(BorderType::Plain),
+        )
+        .marker(Marker::Braille)
+        .paint(move |ctx| {
+            // graticule
+            for i in 0..=DIVISIONS_X as usize {
+                let x = i as f64;
                 ctx.draw(&Line {
-                    x1: p1.0,
-                    y1: p1.1,
-                    x2: p2.0,
-                    y2: p2.1,
-                    color: Color::Yellow,
+                    x1: x,
+                    y1: -half,
+                    x2: x,
+                    y2: half,
+                    color: Color::DarkGray,
                 });
             }
-            for i in 0..values_b.len() - 1 {
-                p1 = values_b.get(i).unwrap_or(&(0.0, 0.0));
-                p2 = values_b.get(i + 1).unwrap_or(&(0.0, 100.0));
+            for i in 0..=DIVISIONS_Y as usize {
+                let y = i as f64 - half;
                 ctx.draw(&Line {
-                    x1: p1.0,
-                    y1: p1.1,
-                    x2: p2.0,
-                    y2: p2.1,
-                    color: Color::Cyan,
+                    x1: 0.0,
+                    y1: y,
+                    x2: DIVISIONS_X as f64,
+                    y2: y,
+                    color: Color::DarkGray,
                 });
             }
+            ctx.layer();
+
+            // trigger level marker
+            ctx.print(0.0, level, "T".yellow());
+
+            for (values, color) in traces.iter() {
+                for points in values.windows(2) {
+                    ctx.draw(&Line {
+                        x1: points[0].0,
+                        y1: points[0].1,
+                        x2: points[1].0,
+                        y2: points[1].1,
+                        color: *color,
+                    });
+                }
+            }
         })
-        .x_bounds([0.0, 100.0])
-        .y_bounds([-1.1, 1.1])
+        .x_bounds([0.0, DIVISIONS_X as f64])
+        .y_bounds([-half, half])
+}
+
+/// Spectrum of an output channel in dBFS, with the peaks held and the harmonics marked.
+fn render_spectrum(app: &mut App, frame: &mut Frame, area: Rect) {
+    let sample_rate = app.audio.info.sample_rate as f32;
+    app.spectrum.analyze(&app.audio.tap);
+    let spectrum = &app.spectrum;
+    // braille has two dots per cell
+    let columns = area.width.saturating_sub(8) as usize * 2;
+    let magnitudes = spectrum.points(&spectrum.magnitudes, sample_rate, columns);
+    let peaks = match spectrum.hold {
+        true => spectrum.points(&spectrum.peaks, sample_rate, columns),
+        false => Vec::new(),
+    };
+    let markers = spectrum.markers(sample_rate);
+    let marker_points: Vec<(f64, f64)> = markers
+        .iter()
+        .map(|x| (spectrum.x(x.frequency), x.level as f64))
+        .collect();
+    let marker_text = markers
+        .iter()
+        .map(|x| match x.harmonic {
+            1 => format!(
+                "{} {:.1}dB",
+                spectrum::format_frequency(x.frequency),
+                x.level
+            ),
+            n => format!("H{} {:.1}dB", n, x.level),
+        })
+        .collect::<Vec<String>>()
+        .join("  ");
+
+    let datasets = vec![
+        Dataset::default()
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::DarkGray))
+            .data(&peaks),
+        Dataset::default()
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(app.theme.channel(spectrum.source)))
+            .data(&magnitudes),
+        Dataset::default()
+            .marker(Marker::Dot)
+            .graph_type(GraphType::Scatter)
+            .style(Style::default().fg(Color::Yellow))
+            .data(&marker_points),
+    ];
+    // labels are evenly spaced, on a logarithmic axis too
+    let [min, max] = spectrum.bounds(sample_rate);
+    let x_labels = (0..3)
+        .map(|i| {
+            let x = min + (max - min) * i as f64 / 2.0;
+            let frequency = match spectrum.log {
+                true => 10f64.powf(x),
+                false => x,
+            };
+            Span::from(spectrum::format_frequency(frequency as f32)).dark_gray()
+        })
+        .collect();
+    let y_labels = [FLOOR, FLOOR / 2.0, 0.0]
+        .iter()
+        .map(|x| Span::from(format!("{}", x)).dark_gray())
+        .collect();
+    let chart = Chart::new(datasets)
+        .block(
+            Block::default()
+                .title(format!(" Spectrum {} ", spectrum.describe()))
+                .title(Title::from(marker_text).position(Position::Bottom))
+                .borders(Borders::ALL)
+                .border_type(BorderType::Plain),
+        )
+        .x_axis(Axis::default().bounds([min, max]).labels(x_labels))
+        .y_axis(Axis::default().bounds([FLOOR as f64, 0.0]).labels(y_labels));
+    frame.render_widget(chart, area);
 }
 
 fn make_status_bar(app: &App) -> impl Widget + 'static {
     let mut status_text = format!("{:?}", app.mode);
     status_text += &" | Press 'h' for help, 'q' to quit.";
+    if let Some(script) = &app.script {
+        status_text = format!(
+            "Running {} line {} | Press Esc to abort.",
+            script.name, script.line
+        );
+    }
 
     if app.mode == Mode::Command {
-        status_text = format!(":{}", app.command);
+        status_text = match &app.history_search {
+            Some(query) => format!("(reverse-i-search)`{}'", query),
+            None => format!(":{}", app.command),
+        };
     }
 
     let mut status_color = Color::White;
 
+    if let Some(message) = &app.message {
+        status_text = message.clone();
+    }
+
     if app.warning != None {
         status_text = format!("{}!", app.warning.as_ref().unwrap());
         status_color = Color::Red;
@@ -262,6 +829,18 @@ fn make_status_bar(app: &App) -> impl Widget + 'static {
         .alignment(Alignment::Left)
 }
 
+/// Completion candidates or the usage of the command being typed.
+fn make_hint_line(app: &App) -> impl Widget + 'static {
+    let mut hint = String::new();
+    if app.mode == Mode::Command && app.history_search.is_none() {
+        hint = match app.completions.is_empty() {
+            true => complete::hint(&app.command).unwrap_or_default(),
+            false => app.completions.join("  "),
+        };
+    }
+    Paragraph::new(hint).style(Style::default().fg(Color::DarkGray))
+}
+
 /// helper function to create a centered rect using up certain percentage of the available rect `r`
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let popup_layout = Layout::default()
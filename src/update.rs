@@ -1,5 +1,7 @@
-use crate::app::{App, Mode};
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crate::app::{App, Mode, View};
+use crate::parameter::Parameter;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::layout::Rect;
 
 pub fn update(app: &mut App, key_event: KeyEvent) {
     // ignore key repeat events
@@ -11,17 +13,50 @@ pub fn update(app: &mut App, key_event: KeyEvent) {
 
     app.clear_warning();
 
+    // reverse history search within command mode
+    if app.mode == Mode::Command && app.history_search.is_some() {
+        match key_event.code {
+            KeyCode::Char('r') if key_event.modifiers == KeyModifiers::CONTROL => {
+                app.search_history();
+            }
+            KeyCode::Char('c') if key_event.modifiers == KeyModifiers::CONTROL => {
+                app.cancel_history_search();
+            }
+            // other chords aren't part of the query
+            KeyCode::Char(c)
+                if key_event.modifiers == KeyModifiers::NONE
+                    || key_event.modifiers == KeyModifiers::SHIFT =>
+            {
+                app.push_search_char(c)
+            }
+            KeyCode::Backspace => app.pop_search_char(),
+            KeyCode::Up => app.search_history(),
+            KeyCode::Down => app.previous_search_match(),
+            KeyCode::Enter => app.accept_history_search(),
+            KeyCode::Esc => app.cancel_history_search(),
+            _ => {}
+        }
+        return;
+    }
+
     if key_event.code == KeyCode::Esc {
+        app.stop_script();
         app.set_normal_mode();
         return;
     } else if app.mode == Mode::Command {
         match key_event.code {
+            KeyCode::Char('r') if key_event.modifiers == KeyModifiers::CONTROL => {
+                app.search_history();
+            }
             KeyCode::Char(c) => {
                 app.push_command_char(c);
             }
             KeyCode::Backspace => {
                 app.pop_command_char();
             }
+            KeyCode::Tab => {
+                app.complete_command();
+            }
             KeyCode::Up => {
                 app.command_history_last();
             }
@@ -34,7 +69,37 @@ pub fn update(app: &mut App, key_event: KeyEvent) {
             _ => {}
         }
         return;
+    } else if app.mode == Mode::Presets {
+        match key_event.code {
+            KeyCode::Up => app.previous_preset(),
+            KeyCode::Down => app.next_preset(),
+            KeyCode::Enter => app.process_preset(),
+            _ => {}
+        }
+        return;
+    } else if app.mode == Mode::Keypad {
+        match key_event.code {
+            KeyCode::Char(c) => {
+                app.push_keypad_char(c);
+            }
+            KeyCode::Backspace => {
+                app.pop_keypad_char();
+            }
+            KeyCode::Enter => {
+                app.process_keypad();
+            }
+            _ => {}
+        }
+        return;
     }
+    // user key bindings take precedence in normal mode
+    if app.mode == Mode::Normal {
+        if let Some(command) = app.key_binding(key_event.code, key_event.modifiers) {
+            app.execute_command(&command);
+            return;
+        }
+    }
+
     // normal mode
     match key_event.code {
         KeyCode::Char('q') | KeyCode::Char('Q') => {
@@ -47,6 +112,8 @@ pub fn update(app: &mut App, key_event: KeyEvent) {
         KeyCode::Char('c') | KeyCode::Char('C') => {
             if key_event.modifiers == KeyModifiers::CONTROL {
                 app.quit();
+            } else {
+                app.reset_clips();
             }
         }
         KeyCode::Char('h') | KeyCode::Char('H') => {
@@ -70,15 +137,83 @@ pub fn update(app: &mut App, key_event: KeyEvent) {
             app.command.push_str("wave ");
         }
 
+        // output shortcuts
+        KeyCode::Char('m') | KeyCode::Char('M') => app.toggle_mute(),
+        KeyCode::Char('o') | KeyCode::Char('O') => app.toggle_output(),
+        KeyCode::Char('v') | KeyCode::Char('V') => app.next_view(),
+
         KeyCode::Tab => app.next_tab(),
         KeyCode::BackTab => app.previous_tab(),
 
         KeyCode::Down => app.next_parameter(),
         KeyCode::Up => app.previous_parameter(),
 
+        KeyCode::Enter => app.set_keypad_mode(),
         KeyCode::Char(':') => app.set_command_mode(),
-        KeyCode::Char('1') => app.set_tab(0),
-        KeyCode::Char('2') => app.set_tab(1),
+        KeyCode::Char(c @ '1'..='9') => app.set_tab(c as usize - '1' as usize),
         _ => {}
     };
 }
+
+pub fn update_mouse(app: &mut App, mouse_event: MouseEvent) {
+    // mouse is only used in normal mode
+    if app.mode != Mode::Normal {
+        return;
+    }
+
+    let (column, row) = (mouse_event.column, mouse_event.row);
+    match mouse_event.kind {
+        MouseEventKind::Down(_) => {
+            app.clear_warning();
+            if let Some(index) = app.areas.tabs.iter().position(|x| contains(x, column, row)) {
+                app.set_tab(index);
+            } else if let Some(parameter) = parameter_at(app, column, row) {
+                app.select_parameter(parameter);
+            } else if app.view == View::Preview && contains(&app.areas.preview, column, row) {
+                app.start_drag(column, row);
+            }
+        }
+        MouseEventKind::Drag(button) => {
+            app.drag(column, row, button != MouseButton::Left);
+        }
+        MouseEventKind::Up(_) => app.stop_drag(),
+        MouseEventKind::ScrollUp => {
+            if let Some(parameter) = parameter_at(app, column, row) {
+                app.select_parameter(parameter);
+                app.increase_parameter_value(parameter);
+            } else if app.view == View::Preview && contains(&app.areas.preview, column, row) {
+                app.pan_preview(1.0);
+            }
+        }
+        MouseEventKind::ScrollDown => {
+            if let Some(parameter) = parameter_at(app, column, row) {
+                app.select_parameter(parameter);
+                app.decrease_parameter_value(parameter);
+            } else if app.view == View::Preview && contains(&app.areas.preview, column, row) {
+                app.pan_preview(-1.0);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Parameter row of the parameter table at the given screen position.
+fn parameter_at(app: &App, column: u16, row: u16) -> Option<Parameter> {
+    let area = app.areas.parameters;
+    // skip the table border
+    let inner = Rect {
+        x: area.x + 1,
+        y: area.y + 1,
+        width: area.width.saturating_sub(2),
+        height: area.height.saturating_sub(2),
+    };
+    if !contains(&inner, column, row) {
+        return None;
+    }
+    let index = (row - inner.y) as usize + app.table_state.offset();
+    app.parameters().get(index).copied()
+}
+
+fn contains(rect: &Rect, column: u16, row: u16) -> bool {
+    column >= rect.x && column < rect.right() && row >= rect.y && row < rect.bottom()
+}
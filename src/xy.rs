@@ -0,0 +1,122 @@
+use crate::app::channel_name;
+use std::collections::VecDeque;
+use waveforms_rs::Waveform;
+
+/// Samples of each channel drawn per frame.
+pub const XY_SAMPLES: usize = 512;
+/// Frames kept on screen at most.
+pub const PERSISTENCE_MAX: usize = 32;
+/// Smallest half range of the axes, so silence is not zoomed into.
+const SCALE_MIN: f64 = 0.01;
+
+/// Setting changed by the `xy` command.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum XySetting {
+    /// Channels plotted on the X and Y axes.
+    Channels(usize, usize),
+    /// Frames kept on screen, fading out.
+    Persistence(usize),
+}
+
+/// Lissajous figure of two channels, from the preview generators running on from frame to
+/// frame so figures of frequencies that are not multiples drift as on an analog scope.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lissajous {
+    pub x_channel: usize,
+    pub y_channel: usize,
+    pub persistence: usize,
+    /// Points of the last frames, the newest first.
+    pub traces: VecDeque<Vec<(f64, f64)>>,
+}
+
+impl Default for Lissajous {
+    fn default() -> Self {
+        Self {
+            x_channel: 0,
+            y_channel: 1,
+            persistence: 8,
+            traces: VecDeque::new(),
+        }
+    }
+}
+
+impl Lissajous {
+    /// Change the plotted channels or the persistence, returning the new settings or a warning
+    /// for a channel that doesn't exist.
+    pub fn apply(&mut self, setting: XySetting, channels: usize) -> Result<String, String> {
+        match setting {
+            XySetting::Channels(x, y) => {
+                if x >= channels || y >= channels {
+                    return Err("Invalid channel".to_string());
+                }
+                (self.x_channel, self.y_channel) = (x, y);
+                self.traces.clear();
+            }
+            XySetting::Persistence(frames) => {
+                self.persistence = frames.clamp(1, PERSISTENCE_MAX);
+                self.traces.truncate(self.persistence);
+            }
+        }
+        Ok(format!(
+            "XY: {} on X, {} on Y, {} frames of persistence",
+            channel_name(self.x_channel),
+            channel_name(self.y_channel),
+            self.persistence
+        ))
+    }
+
+    /// Add a frame of samples from the generators, dropping the oldest one.
+    pub fn update(&mut self, waveforms: &mut [Waveform]) {
+        if self.x_channel.max(self.y_channel) >= waveforms.len() {
+            self.traces.clear();
+            return;
+        }
+        let points = (0..XY_SAMPLES)
+            .map(|_| {
+                let x = waveforms[self.x_channel].process() as f64;
+                let y = waveforms[self.y_channel].process() as f64;
+                (x, y)
+            })
+            .collect();
+        self.traces.push_front(points);
+        self.traces.truncate(self.persistence);
+    }
+
+    /// Half ranges of the X and Y axes fitting all the points kept.
+    pub fn scale(&self) -> (f64, f64) {
+        self.traces
+            .iter()
+            .flatten()
+            .fold((SCALE_MIN, SCALE_MIN), |(x, y), point| {
+                (x.max(point.0.abs()), y.max(point.1.abs()))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xy_persistence() {
+        let mut waveforms = vec![Waveform::new(44100.0, 440.0), Waveform::new(44100.0, 660.0)];
+        waveforms[1].set_amplitude(0.5);
+        let mut xy = Lissajous::default();
+        for _ in 0..10 {
+            xy.update(&mut waveforms);
+        }
+        assert_eq!(xy.traces.len(), 8);
+        assert!(xy.traces.iter().all(|x| x.len() == XY_SAMPLES));
+        let (x, y) = xy.scale();
+        assert!(x > 0.9 && x <= 1.0);
+        assert!(y > 0.45 && y <= 0.5);
+
+        xy.apply(XySetting::Persistence(2), 2).unwrap();
+        assert_eq!(xy.traces.len(), 2);
+        assert!(xy.apply(XySetting::Channels(0, 2), 2).is_err());
+        // a single channel has nothing to plot against
+        xy.update(&mut waveforms[..1]);
+        assert!(xy.traces.is_empty());
+        assert_eq!(xy.scale(), (SCALE_MIN, SCALE_MIN));
+    }
+}